@@ -1,29 +1,60 @@
 use anyhow::Result;
-use async_openai::types::{
-    ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestSystemMessageArgs,
-    ChatCompletionRequestUserMessageArgs, CreateChatCompletionRequestArgs,
-};
-use async_openai::{config::OpenAIConfig, Client};
 use console::Style;
-use dialoguer::Input;
 use indicatif::{ProgressBar, ProgressStyle};
 
-use crate::config::get_api_key;
+use crate::config::Config;
+use crate::prompt::Prompter;
+use crate::provider::{ChatMessage, ChatRequest, ChatResponse, Provider, ToolDefinition, Usage};
 use crate::shell::ShellContext;
+use crate::usage::UsageTracker;
 
 pub static OUTPUT_DELIMITER: &str = "§";
+pub static DEFAULT_MODEL: &str = "gpt-4o-mini";
+pub static DEFAULT_OLLAMA_MODEL: &str = "llama3.2";
+pub static DEFAULT_ANTHROPIC_MODEL: &str = "claude-3-5-haiku-latest";
+static SUGGEST_COMMAND_TOOL: &str = "suggest_command";
 
-pub fn create_client(shell_context: &ShellContext) -> Result<Client<OpenAIConfig>> {
-    let api_key = get_api_key(&shell_context.shell_type)?;
-    Ok(Client::with_config(OpenAIConfig::new().with_api_key(api_key)))
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion {
+    pub explanation: String,
+    pub command: String,
+    /// The model that answered, which differs from the configured one after a fallback
+    pub model: String,
+    /// None for cached suggestions
+    pub usage: Option<Usage>,
+}
+
+pub fn create_client(shell_context: &ShellContext, config: &Config) -> Result<Provider> {
+    Ok(Provider::from_config(shell_context, &config.provider, &config.request)?
+        .with_fallbacks(&config.fallbacks))
+}
+
+/// Shows "Retrying (2/3)…" on the spinner while a request is being retried, and which
+/// model is tried next when one fails.
+pub fn show_retries_on(spinner: &ProgressBar, client: Provider) -> Provider {
+    let retry_spinner = spinner.clone();
+    let fallback_spinner = spinner.clone();
+    client
+        .on_retry(move |retry| {
+            retry_spinner.set_message(format!(
+                "Retrying ({}/{})…",
+                retry.attempt, retry.max_retries
+            ))
+        })
+        .on_fallback(move |fallback| {
+            fallback_spinner.set_message(format!(
+                "{} failed, trying {}…",
+                fallback.failed_model, fallback.next_model
+            ))
+        })
 }
 
 pub async fn get_command_suggestion(
-    client: &Client<OpenAIConfig>,
+    client: &Provider,
     shell_context: &ShellContext,
     query: &str,
     context: Option<&str>,
-) -> Result<(String, String)> {
+) -> Result<Suggestion> {
     let mut context_str = String::new();
 
     // Add piped input context if provided
@@ -35,39 +66,60 @@ pub async fn get_command_suggestion(
 
     let system_prompt = get_command_suggestion_prompt(shell_context);
 
-    let request = CreateChatCompletionRequestArgs::default()
-        .model("gpt-4o-mini")
-        .messages([
-            ChatCompletionRequestSystemMessageArgs::default()
-                .content(system_prompt)
-                .build()?
-                .into(),
-            ChatCompletionRequestUserMessageArgs::default()
-                .content(format!("Context:\n{:#?}\n\nQuery: {}", context, query))
-                .build()?
-                .into(),
-        ])
-        .temperature(0.3)
-        .max_tokens(150u16)
-        .build()?;
-
-    let response = client.chat().create(request).await?;
-
-    if let Some(choice) = response.choices.first() {
-        if let Some(content) = &choice.message.content {
-            if let Some((explanation, command)) = content.split_once(OUTPUT_DELIMITER) {
-                return Ok((explanation.trim().to_string(), command.trim().to_string()));
+    let request = ChatRequest {
+        messages: vec![
+            ChatMessage::system(system_prompt),
+            ChatMessage::user(format!("Context:\n{:#?}\n\nQuery: {}", context, query)),
+        ],
+        temperature: 0.3,
+        max_tokens: Some(150),
+        tools: vec![suggest_command_tool()],
+        tool_choice: Some(SUGGEST_COMMAND_TOOL.to_string()),
+    };
+
+    let response = client.chat(&request).await?;
+
+    let (explanation, command) = if let Some(suggestion) = suggestion_from_tool_call(&response) {
+        suggestion
+    } else if let Some(content) = &response.content {
+        match content.split_once(OUTPUT_DELIMITER) {
+            Some((explanation, command)) => {
+                (explanation.trim().to_string(), command.trim().to_string())
             }
-            return Ok((content.trim().to_string(), String::new()));
+            None => (content.trim().to_string(), String::new()),
         }
-    }
+    } else {
+        (String::new(), String::new())
+    };
 
-    Ok((String::new(), String::new()))
+    Ok(Suggestion {
+        explanation,
+        command,
+        model: response.model,
+        usage: response.usage,
+    })
+}
+
+/// Asks for a corrected version of a suggested command, given what was wrong with it, such as
+/// validation problems or the error output of a failed run.
+pub async fn revise_command(
+    client: &Provider,
+    shell_context: &ShellContext,
+    query: &str,
+    command: &str,
+    problem: &str,
+) -> Result<Suggestion> {
+    let request = format!(
+        "{query}\n\nYou suggested `{command}`, which {problem}\nSuggest a corrected command."
+    );
+    get_command_suggestion(client, shell_context, &request, None).await
 }
 
 pub async fn interactive_chat(
-    client: &Client<OpenAIConfig>,
+    client: Provider,
     shell_context: &ShellContext,
+    usage: &UsageTracker,
+    prompter: &dyn Prompter,
 ) -> Result<()> {
     println!(
         "{}",
@@ -83,65 +135,87 @@ pub async fn interactive_chat(
         shell_context.os
     );
 
-    let mut messages = vec![ChatCompletionRequestSystemMessageArgs::default()
-        .content(system_prompt)
-        .build()?
-        .into()];
+    let mut messages = vec![ChatMessage::system(system_prompt)];
 
     let spinner = ProgressBar::new_spinner();
     spinner.set_style(
         ProgressStyle::default_spinner()
             .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈")
-            .template("{spinner} Thinking...")?,
+            .template("{spinner} {msg}")?,
     );
+    let client = show_retries_on(&spinner, client);
 
     loop {
-        let query: String = Input::new().with_prompt("You").interact()?;
+        let query = prompter.input("You", "")?;
 
         if query.eq_ignore_ascii_case("exit") || query.eq_ignore_ascii_case("quit") {
             break;
         }
 
-        messages.push(
-            ChatCompletionRequestUserMessageArgs::default()
-                .content(query)
-                .build()?
-                .into(),
-        );
+        usage.check_budget()?;
+        messages.push(ChatMessage::user(query));
 
+        spinner.set_message("Thinking...");
         spinner.enable_steady_tick(std::time::Duration::from_millis(80));
 
-        let request = CreateChatCompletionRequestArgs::default()
-            .model("gpt-4o-mini")
-            .messages(messages.clone())
-            .temperature(0.7)
-            .build()?;
+        let request = ChatRequest {
+            messages: messages.clone(),
+            temperature: 0.7,
+            max_tokens: None,
+            tools: Vec::new(),
+            tool_choice: None,
+        };
 
-        let response = client.chat().create(request).await?;
+        let response = client.chat(&request).await?;
 
         spinner.disable_steady_tick();
 
-        if let Some(choice) = response.choices.first() {
-            if let Some(content) = &choice.message.content {
-                println!(
-                    "\n{} {}",
-                    Style::new().green().apply_to("Assistant:"),
-                    content
-                );
-
-                messages.push(
-                    ChatCompletionRequestAssistantMessageArgs::default()
-                        .content(content.clone())
-                        .build()?
-                        .into(),
-                );
-            }
+        if let Some(content) = response.content {
+            let label = if response.model == client.model() {
+                "Assistant:".to_string()
+            } else {
+                format!("Assistant ({}):", response.model)
+            };
+            println!("\n{} {}", Style::new().green().apply_to(label), content);
+
+            messages.push(ChatMessage::assistant(content));
         }
+        usage.record(&response.model, response.usage);
     }
 
     Ok(())
 }
 
+fn suggest_command_tool() -> ToolDefinition {
+    ToolDefinition {
+        name: SUGGEST_COMMAND_TOOL.to_string(),
+        description: "Suggest a single shell command that accomplishes the user's request.".to_string(),
+        input_schema: serde_json::json!({
+            "type": "object",
+            "properties": {
+                "explanation": {
+                    "type": "string",
+                    "description": "One sentence explaining what the command does"
+                },
+                "command": {
+                    "type": "string",
+                    "description": "The exact command to run"
+                }
+            },
+            "required": ["explanation", "command"]
+        }),
+    }
+}
+
+fn suggestion_from_tool_call(response: &ChatResponse) -> Option<(String, String)> {
+    let call = response
+        .tool_calls
+        .iter()
+        .find(|call| call.name == SUGGEST_COMMAND_TOOL)?;
+    let field = |name: &str| call.input[name].as_str().unwrap_or_default().trim().to_string();
+    Some((field("explanation"), field("command")))
+}
+
 fn get_command_suggestion_prompt(shell_context: &ShellContext) -> String {
     let role_and_env_assertion = format!(
         "You are a command line assistant for {} on {}.",
@@ -166,3 +240,35 @@ fn get_command_suggestion_prompt(shell_context: &ShellContext) -> String {
         role_and_env_assertion, response_format, format_description, prompt_example
     )
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::ToolCall;
+
+    #[test]
+    fn test_suggestion_from_tool_call() {
+        let response = ChatResponse {
+            content: None,
+            tool_calls: vec![ToolCall {
+                name: SUGGEST_COMMAND_TOOL.to_string(),
+                input: serde_json::json!({ "explanation": "Lists files", "command": " ls -la " }),
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            suggestion_from_tool_call(&response),
+            Some(("Lists files".to_string(), "ls -la".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_suggestion_from_text_response_has_no_tool_call() {
+        let response = ChatResponse {
+            content: Some(format!("Lists files{OUTPUT_DELIMITER}ls")),
+            ..Default::default()
+        };
+        assert_eq!(suggestion_from_tool_call(&response), None);
+    }
+}
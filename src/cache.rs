@@ -0,0 +1,372 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::config::{get_cache_dir, CacheConfig};
+use crate::shell::ShellContext;
+
+static CACHE_FILE: &str = "responses.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Serve cached responses and store new ones
+    Use,
+    /// Skip the lookup but store the fresh response
+    Refresh,
+    Disabled,
+}
+
+impl CacheMode {
+    pub fn from_flags(config: &CacheConfig, no_cache: bool, refresh: bool) -> Self {
+        if no_cache || !config.enabled {
+            CacheMode::Disabled
+        } else if refresh {
+            CacheMode::Refresh
+        } else {
+            CacheMode::Use
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey(String);
+
+impl CacheKey {
+    pub fn new(
+        query: &str,
+        shell_context: &ShellContext,
+        model: &str,
+        context: Option<&str>,
+    ) -> Self {
+        let normalized_query = query
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase();
+        let context_hash = context
+            .map(|context| hex(&Sha256::digest(context.as_bytes())))
+            .unwrap_or_default();
+
+        let mut hasher = Sha256::new();
+        for part in [
+            normalized_query.as_str(),
+            &shell_context.shell_type.to_string(),
+            &shell_context.os.to_string(),
+            model,
+            &context_hash,
+        ] {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+
+        Self(hex(&hasher.finalize()))
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub explanation: String,
+    pub command: String,
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub hits: u64,
+}
+
+#[derive(Debug)]
+pub struct CacheStats {
+    pub path: PathBuf,
+    pub entries: usize,
+    pub expired: usize,
+    pub hits: u64,
+    pub size_bytes: u64,
+}
+
+pub struct ResponseCache {
+    path: PathBuf,
+    ttl: Duration,
+    max_entries: usize,
+}
+
+impl ResponseCache {
+    pub fn new(path: impl Into<PathBuf>, config: &CacheConfig) -> Self {
+        Self {
+            path: path.into(),
+            ttl: Duration::hours(config.ttl_hours as i64),
+            max_entries: config.max_entries,
+        }
+    }
+
+    pub fn open_default(config: &CacheConfig) -> Result<Self> {
+        Ok(Self::new(get_cache_dir()?.join(CACHE_FILE), config))
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Result<Option<(String, String)>> {
+        let mut entries = self.load()?;
+        let Some(entry) = entries.get_mut(&key.0) else {
+            return Ok(None);
+        };
+        if self.is_expired(entry) {
+            return Ok(None);
+        }
+
+        entry.hits += 1;
+        let response = (entry.explanation.clone(), entry.command.clone());
+        self.save(&entries)?;
+        Ok(Some(response))
+    }
+
+    pub fn put(&self, key: &CacheKey, explanation: &str, command: &str) -> Result<()> {
+        let mut entries = self.load()?;
+        entries.retain(|_, entry| !self.is_expired(entry));
+        entries.insert(
+            key.0.clone(),
+            CachedResponse {
+                explanation: explanation.to_string(),
+                command: command.to_string(),
+                created_at: Utc::now(),
+                hits: 0,
+            },
+        );
+
+        // Evict the oldest entries once the size limit is exceeded
+        if entries.len() > self.max_entries {
+            let mut by_age: Vec<(String, DateTime<Utc>)> = entries
+                .iter()
+                .map(|(key, entry)| (key.clone(), entry.created_at))
+                .collect();
+            by_age.sort_by_key(|(_, created_at)| *created_at);
+            let excess = entries.len() - self.max_entries;
+            for (key, _) in by_age.into_iter().take(excess) {
+                entries.remove(&key);
+            }
+        }
+
+        self.save(&entries)
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn stats(&self) -> Result<CacheStats> {
+        let entries = self.load()?;
+        Ok(CacheStats {
+            path: self.path.clone(),
+            entries: entries.len(),
+            expired: entries
+                .values()
+                .filter(|entry| self.is_expired(entry))
+                .count(),
+            hits: entries.values().map(|entry| entry.hits).sum(),
+            size_bytes: fs::metadata(&self.path).map(|meta| meta.len()).unwrap_or(0),
+        })
+    }
+
+    fn is_expired(&self, entry: &CachedResponse) -> bool {
+        Utc::now() - entry.created_at > self.ttl
+    }
+
+    fn load(&self) -> Result<HashMap<String, CachedResponse>> {
+        match fs::read_to_string(&self.path) {
+            // A corrupt cache is just treated as empty and rewritten on the next store
+            Ok(contents) => Ok(serde_json::from_str(&contents).unwrap_or_default()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn save(&self, entries: &HashMap<String, CachedResponse>) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Queries and commands stay private to the user, like the history log
+        let mut options = fs::OpenOptions::new();
+        options.create(true).write(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let tmp_path = self.path.with_extension("json.tmp");
+        options
+            .open(&tmp_path)?
+            .write_all(serde_json::to_string(entries)?.as_bytes())?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("Failed to write cache file {}", self.path.display()))
+    }
+}
+
+pub fn print_stats(stats: &CacheStats) {
+    println!(
+        "{} {}",
+        console::style("Cache file:").green(),
+        stats.path.display()
+    );
+    println!("{} {}", console::style("Entries:").green(), stats.entries);
+    println!("{} {}", console::style("Expired:").green(), stats.expired);
+    println!("{} {}", console::style("Hits:").green(), stats.hits);
+    println!(
+        "{} {:.1} KiB",
+        console::style("Size:").green(),
+        stats.size_bytes as f64 / 1024.0
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::{ShellType, SupportedOperatingSystem};
+    use std::path::Path;
+
+    fn shell_context(shell_type: ShellType) -> ShellContext {
+        ShellContext {
+            shell_type,
+            os: SupportedOperatingSystem::Linux,
+            shell_path: None,
+        }
+    }
+
+    fn cache_in(dir: &Path, max_entries: usize) -> ResponseCache {
+        let config = CacheConfig {
+            max_entries,
+            ..Default::default()
+        };
+        ResponseCache::new(dir.join(CACHE_FILE), &config)
+    }
+
+    #[test]
+    fn test_cache_key_normalizes_query() {
+        let context = shell_context(ShellType::Bash);
+        assert_eq!(
+            CacheKey::new("List  listening ports ", &context, "gpt-4o-mini", None),
+            CacheKey::new("list listening ports", &context, "gpt-4o-mini", None)
+        );
+    }
+
+    #[test]
+    fn test_cache_key_varies_by_environment() {
+        let bash = shell_context(ShellType::Bash);
+        let fish = shell_context(ShellType::Fish);
+        let key = CacheKey::new("list files", &bash, "gpt-4o-mini", None);
+
+        assert_ne!(key, CacheKey::new("list files", &fish, "gpt-4o-mini", None));
+        assert_ne!(key, CacheKey::new("list files", &bash, "gpt-4o", None));
+        assert_ne!(
+            key,
+            CacheKey::new("list files", &bash, "gpt-4o-mini", Some("input"))
+        );
+    }
+
+    #[test]
+    fn test_put_and_get() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache_in(dir.path(), 10);
+        let key = CacheKey::new("list files", &shell_context(ShellType::Zsh), "m", None);
+
+        assert_eq!(cache.get(&key).unwrap(), None);
+        cache.put(&key, "Lists files", "ls -la").unwrap();
+        assert_eq!(
+            cache.get(&key).unwrap(),
+            Some(("Lists files".to_string(), "ls -la".to_string()))
+        );
+        assert_eq!(cache.stats().unwrap().hits, 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_cache_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache_in(dir.path(), 10);
+        let key = CacheKey::new("list files", &shell_context(ShellType::Zsh), "m", None);
+        cache.put(&key, "Lists files", "ls -la").unwrap();
+
+        let mode = fs::metadata(dir.path().join(CACHE_FILE)).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_expired_entries_are_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = CacheConfig {
+            ttl_hours: 0,
+            ..Default::default()
+        };
+        let cache = ResponseCache::new(dir.path().join(CACHE_FILE), &config);
+        let key = CacheKey::new("list files", &shell_context(ShellType::Zsh), "m", None);
+
+        cache.put(&key, "Lists files", "ls").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert_eq!(cache.get(&key).unwrap(), None);
+        assert_eq!(cache.stats().unwrap().expired, 1);
+    }
+
+    #[test]
+    fn test_oldest_entries_are_evicted() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache_in(dir.path(), 2);
+        let context = shell_context(ShellType::Bash);
+        let keys: Vec<CacheKey> = (0..3)
+            .map(|i| CacheKey::new(&format!("query {i}"), &context, "m", None))
+            .collect();
+
+        for key in &keys {
+            cache.put(key, "explanation", "command").unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+
+        assert_eq!(cache.stats().unwrap().entries, 2);
+        assert_eq!(cache.get(&keys[0]).unwrap(), None);
+        assert!(cache.get(&keys[2]).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_clear() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache_in(dir.path(), 10);
+        let key = CacheKey::new("list files", &shell_context(ShellType::Zsh), "m", None);
+
+        cache.clear().unwrap();
+        cache.put(&key, "Lists files", "ls").unwrap();
+        cache.clear().unwrap();
+        assert_eq!(cache.stats().unwrap().entries, 0);
+    }
+
+    #[test]
+    fn test_cache_mode_from_flags() {
+        let config = CacheConfig::default();
+        assert_eq!(CacheMode::from_flags(&config, false, false), CacheMode::Use);
+        assert_eq!(
+            CacheMode::from_flags(&config, false, true),
+            CacheMode::Refresh
+        );
+        assert_eq!(
+            CacheMode::from_flags(&config, true, true),
+            CacheMode::Disabled
+        );
+
+        let disabled = CacheConfig {
+            enabled: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            CacheMode::from_flags(&disabled, false, false),
+            CacheMode::Disabled
+        );
+    }
+}
@@ -1,18 +1,177 @@
-use clap::{Parser, Subcommand};
+use chrono::NaiveDate;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use std::io::{self, Write};
+
+use crate::config::ProviderKind;
+use crate::history::StatusFilter;
+use crate::integration::InitShell;
 
 #[derive(Parser)]
-#[command(author, version, about = "CommandLM - Your AI-powered command line assistant", long_about = None)]
+#[command(name = "clm", author, version, about = "CommandLM - Your AI-powered command line assistant", long_about = None)]
 pub struct Cli {
     #[command(subcommand)]
     pub command: Option<Commands>,
 
     #[arg(value_name = "QUERY")]
     pub query: Option<String>,
+
+    /// Print only the suggested command to stdout, without prompting to execute it
+    #[arg(short, long)]
+    pub print: bool,
+
+    /// Show the suggested command and the files it would affect, without executing it
+    #[arg(long, conflicts_with = "print")]
+    pub dry_run: bool,
+
+    /// Try the command in a sandbox first and show what it changed before running it for real
+    #[arg(long, conflicts_with_all = ["print", "dry_run"])]
+    pub sandbox: bool,
+
+    /// Let sandboxed commands use the network
+    #[arg(long, requires = "sandbox")]
+    pub allow_network: bool,
+
+    /// Don't read or write the local response cache
+    #[arg(long, global = true)]
+    pub no_cache: bool,
+
+    /// Ignore any cached response and fetch a fresh one
+    #[arg(long, global = true, conflicts_with = "no_cache")]
+    pub refresh: bool,
+
+    /// Show the tokens used and their cost after each answer
+    #[arg(long = "usage", global = true)]
+    pub show_usage: bool,
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
+    /// Start an interactive chat session
     Chat,
+    /// Print the shell integration script for the given shell
+    Init {
+        #[arg(value_enum)]
+        shell: InitShell,
+    },
+    /// Print a tab completion script for the given shell
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Print the man page in roff format
+    Man,
+    /// Show previously suggested and executed commands
+    History(HistoryArgs),
+    /// List the models available from the configured provider
+    Models {
+        #[command(subcommand)]
+        command: Option<ModelCommands>,
+    },
+    /// Inspect or clear the local response cache
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommands,
+    },
+    /// Store, remove or inspect provider API keys
+    Auth {
+        #[command(subcommand)]
+        command: AuthCommands,
+    },
+    /// Show token usage and cost per day or month
+    Usage(UsageArgs),
+    /// Revert the files changed by the last executed command
+    Undo(UndoArgs),
+}
+
+#[derive(Subcommand)]
+pub enum ModelCommands {
+    /// Download a model to the local Ollama daemon
+    Pull { name: String },
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommands {
+    /// Show the number of cached responses, hits and size on disk
+    Stats,
+    /// Remove all cached responses
+    Clear,
+}
+
+#[derive(Subcommand)]
+pub enum AuthCommands {
+    /// Store an API key in the system keyring, or the encrypted key file if none is available
+    Login {
+        /// Defaults to the configured provider
+        #[arg(value_enum)]
+        provider: Option<ProviderKind>,
+        /// Use the encrypted key file even when a system keyring is available
+        #[arg(long)]
+        file: bool,
+    },
+    /// Remove a stored API key
+    Logout {
+        /// Defaults to the configured provider
+        #[arg(value_enum)]
+        provider: Option<ProviderKind>,
+    },
+    /// Show where each provider's API key is read from
+    Status,
+}
+
+#[derive(Args)]
+pub struct HistoryArgs {
+    /// Only show entries whose query or command contains this text
+    #[arg(value_name = "SEARCH")]
+    pub search: Option<String>,
+
+    /// Only show entries on or after this date (YYYY-MM-DD)
+    #[arg(long)]
+    pub since: Option<NaiveDate>,
+
+    /// Only show entries on or before this date (YYYY-MM-DD)
+    #[arg(long)]
+    pub until: Option<NaiveDate>,
+
+    /// Only show entries with this outcome
+    #[arg(long, value_enum)]
+    pub status: Option<StatusFilter>,
+
+    /// Maximum number of entries to show, most recent last
+    #[arg(short = 'n', long, default_value_t = 20)]
+    pub limit: usize,
+
+    /// Print entries as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Args)]
+pub struct UsageArgs {
+    /// Show totals per month instead of per day
+    #[arg(long)]
+    pub monthly: bool,
+
+    /// Print totals as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Args)]
+pub struct UndoArgs {
+    /// List the snapshots that can be restored instead of restoring one
+    #[arg(long)]
+    pub list: bool,
+}
+
+pub fn write_completions(shell: Shell, out: &mut dyn Write) {
+    let mut command = Cli::command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(shell, &mut command, name, out);
+}
+
+pub fn write_man_page(out: &mut dyn Write) -> io::Result<()> {
+    clap_mangen::Man::new(Cli::command()).render(out)
 }
 
 #[cfg(test)]
@@ -65,6 +224,180 @@ mod tests {
         assert!(cli.command.is_none());
     }
 
+    #[test]
+    fn test_cli_parsing_init_command() {
+        let args = vec!["clm", "init", "zsh"];
+        let cli = Cli::try_parse_from(args).unwrap();
+
+        assert!(matches!(cli.command, Some(Commands::Init { shell: InitShell::Zsh })));
+
+        let args = vec!["clm", "init", "pwsh"];
+        let cli = Cli::try_parse_from(args).unwrap();
+
+        assert!(matches!(cli.command, Some(Commands::Init { shell: InitShell::PowerShell })));
+    }
+
+    #[test]
+    fn test_cli_parsing_completions_command() {
+        let args = vec!["clm", "completions", "elvish"];
+        let cli = Cli::try_parse_from(args).unwrap();
+
+        assert!(matches!(cli.command, Some(Commands::Completions { shell: Shell::Elvish })));
+    }
+
+    #[test]
+    fn test_cli_parsing_auth_command() {
+        let args = vec!["clm", "auth", "login", "openai", "--file"];
+        let cli = Cli::try_parse_from(args).unwrap();
+
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Auth {
+                command: AuthCommands::Login {
+                    provider: Some(ProviderKind::OpenAI),
+                    file: true
+                }
+            })
+        ));
+
+        let args = vec!["clm", "auth", "logout"];
+        let cli = Cli::try_parse_from(args).unwrap();
+
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Auth {
+                command: AuthCommands::Logout { provider: None }
+            })
+        ));
+    }
+
+    #[test]
+    fn test_cli_parsing_usage() {
+        let cli = Cli::try_parse_from(vec!["clm", "--usage", "list files"]).unwrap();
+        assert!(cli.show_usage);
+        assert_eq!(cli.query, Some("list files".to_string()));
+
+        let cli = Cli::try_parse_from(vec!["clm", "usage", "--monthly"]).unwrap();
+        assert!(!cli.show_usage);
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Usage(UsageArgs {
+                monthly: true,
+                json: false
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_write_completions_includes_subcommands() {
+        let mut out = Vec::new();
+        write_completions(Shell::Bash, &mut out);
+        let script = String::from_utf8(out).unwrap();
+
+        assert!(script.contains("clm"));
+        assert!(script.contains("chat"));
+        assert!(script.contains("completions"));
+    }
+
+    #[test]
+    fn test_write_man_page() {
+        let mut out = Vec::new();
+        write_man_page(&mut out).unwrap();
+        let page = String::from_utf8(out).unwrap();
+
+        assert!(page.contains(".TH clm"));
+        assert!(page.contains("interactive chat"));
+    }
+
+    #[test]
+    fn test_cli_parsing_history_command() {
+        let args = vec!["clm", "history", "docker", "--since", "2024-05-01", "--status", "failed", "--json"];
+        let cli = Cli::try_parse_from(args).unwrap();
+
+        match cli.command {
+            Some(Commands::History(history)) => {
+                assert_eq!(history.search, Some("docker".to_string()));
+                assert_eq!(history.since, NaiveDate::from_ymd_opt(2024, 5, 1));
+                assert_eq!(history.status, Some(StatusFilter::Failed));
+                assert_eq!(history.limit, 20);
+                assert!(history.json);
+            }
+            _ => panic!("expected history command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_parsing_history_invalid_date() {
+        let args = vec!["clm", "history", "--since", "last week"];
+        assert!(Cli::try_parse_from(args).is_err());
+    }
+
+    #[test]
+    fn test_cli_parsing_cache_flags() {
+        let args = vec!["clm", "--refresh", "list files"];
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert!(cli.refresh);
+        assert!(!cli.no_cache);
+
+        let args = vec!["clm", "--refresh", "--no-cache", "list files"];
+        assert!(Cli::try_parse_from(args).is_err());
+
+        let args = vec!["clm", "cache", "clear"];
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Cache { command: CacheCommands::Clear })
+        ));
+    }
+
+    #[test]
+    fn test_cli_parsing_models_command() {
+        let cli = Cli::try_parse_from(vec!["clm", "models"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Models { command: None })));
+
+        let cli = Cli::try_parse_from(vec!["clm", "models", "pull", "llama3.2"]).unwrap();
+        match cli.command {
+            Some(Commands::Models { command: Some(ModelCommands::Pull { name }) }) => {
+                assert_eq!(name, "llama3.2");
+            }
+            _ => panic!("expected models pull command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_parsing_print_flag() {
+        let args = vec!["clm", "--print", "--", "list files"];
+        let cli = Cli::try_parse_from(args).unwrap();
+
+        assert!(cli.print);
+        assert_eq!(cli.query, Some("list files".to_string()));
+    }
+
+    #[test]
+    fn test_cli_parsing_dry_run() {
+        let cli = Cli::try_parse_from(["clm", "--dry-run", "delete logs"]).unwrap();
+        assert!(cli.dry_run);
+        assert!(Cli::try_parse_from(["clm", "--dry-run", "--print", "delete logs"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_parsing_sandbox() {
+        let cli = Cli::try_parse_from(["clm", "--sandbox", "--allow-network", "update deps"]).unwrap();
+        assert!(cli.sandbox);
+        assert!(cli.allow_network);
+        assert!(Cli::try_parse_from(["clm", "--allow-network", "update deps"]).is_err());
+        assert!(Cli::try_parse_from(["clm", "--sandbox", "--print", "update deps"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_parsing_undo() {
+        let cli = Cli::try_parse_from(["clm", "undo", "--list"]).unwrap();
+        match cli.command {
+            Some(Commands::Undo(args)) => assert!(args.list),
+            _ => panic!("expected undo command"),
+        }
+    }
+
     #[test]
     fn test_cli_parsing_complex_query() {
         let args = vec!["clm", "create a tar archive with compression"];
@@ -0,0 +1,275 @@
+use crate::shell::ShellType;
+
+/// A word or control operator of a command line, as the current shell would split it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    Word(Word),
+    /// `|`, `;`, `&`, `&&` or `||`
+    Operator(String),
+    /// `>`, `>>`, `<` and friends, whose target is the next word
+    Redirect(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Word {
+    /// The word with quotes and escapes removed
+    pub text: String,
+    /// For each char of `text`, whether it was quoted or escaped
+    quoted: Vec<bool>,
+    /// Contains a variable, command substitution or other expansion the shell would perform
+    pub dynamic: bool,
+}
+
+impl Word {
+    pub fn has_glob(&self) -> bool {
+        self.text
+            .chars()
+            .zip(&self.quoted)
+            .any(|(c, quoted)| !quoted && matches!(c, '*' | '?' | '['))
+    }
+
+    /// The word as a glob pattern, with quoted wildcard characters escaped by a backslash.
+    pub fn glob_pattern(&self) -> String {
+        let mut pattern = String::new();
+        for (c, quoted) in self.text.chars().zip(&self.quoted) {
+            if *quoted && matches!(c, '*' | '?' | '[' | '\\') {
+                pattern.push('\\');
+            }
+            pattern.push(c);
+        }
+        pattern
+    }
+
+    fn push(&mut self, c: char, quoted: bool) {
+        self.text.push(c);
+        self.quoted.push(quoted);
+    }
+}
+
+/// The rules for quoting, escaping and expansion that differ between shells.
+#[derive(Clone, Copy)]
+struct Syntax {
+    escape: Option<char>,
+    single_quotes: bool,
+    /// Chars that start an expansion outside single quotes
+    expansion: &'static [char],
+}
+
+fn syntax(shell_type: &ShellType) -> Syntax {
+    match shell_type {
+        ShellType::PowerShell => Syntax {
+            escape: Some('`'),
+            single_quotes: true,
+            expansion: &['$', '('],
+        },
+        ShellType::Cmd => Syntax {
+            escape: Some('^'),
+            single_quotes: false,
+            expansion: &['%', '!'],
+        },
+        _ => Syntax {
+            escape: Some('\\'),
+            single_quotes: true,
+            expansion: &['$', '`', '('],
+        },
+    }
+}
+
+/// Splits a command line into words and operators. Returns None for input the shell would
+/// reject or keep reading, such as an unterminated quote.
+pub fn tokenize(command: &str, shell_type: &ShellType) -> Option<Vec<Token>> {
+    let syntax = syntax(shell_type);
+    let mut tokens = Vec::new();
+    let mut word: Option<Word> = None;
+    let mut chars = command.chars().peekable();
+
+    let finish = |word: &mut Option<Word>, tokens: &mut Vec<Token>| {
+        if let Some(word) = word.take() {
+            tokens.push(Token::Word(word));
+        }
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => finish(&mut word, &mut tokens),
+            '\'' if syntax.single_quotes => {
+                let word = word.get_or_insert_with(Word::default);
+                loop {
+                    match chars.next()? {
+                        // PowerShell escapes a single quote by doubling it
+                        '\'' if matches!(shell_type, ShellType::PowerShell)
+                            && chars.peek() == Some(&'\'') =>
+                        {
+                            chars.next();
+                            word.push('\'', true);
+                        }
+                        '\'' => break,
+                        c => word.push(c, true),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(Word::default);
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        c if Some(c) == syntax.escape && !matches!(shell_type, ShellType::Cmd) => {
+                            word.push(chars.next()?, true)
+                        }
+                        c => {
+                            if syntax.expansion.contains(&c) && c != '(' {
+                                word.dynamic = true;
+                            }
+                            word.push(c, true);
+                        }
+                    }
+                }
+            }
+            c if Some(c) == syntax.escape => match chars.next() {
+                Some(escaped) => word.get_or_insert_with(Word::default).push(escaped, true),
+                None => return None,
+            },
+            '|' | '&' | ';' => {
+                finish(&mut word, &mut tokens);
+                let mut operator = c.to_string();
+                if c != ';' && chars.peek() == Some(&c) {
+                    operator.push(chars.next()?);
+                }
+                tokens.push(Token::Operator(operator));
+            }
+            '>' | '<' => {
+                // A leading file descriptor such as `2>` belongs to the redirect
+                let mut redirect = String::new();
+                match word.take() {
+                    Some(fd) if fd.text.chars().all(|c| c.is_ascii_digit()) => redirect = fd.text,
+                    Some(previous) => tokens.push(Token::Word(previous)),
+                    None => {}
+                }
+                redirect.push(c);
+                while let Some(&next) = chars.peek() {
+                    if matches!(next, '>' | '&') || (next == '|' && redirect.ends_with('>')) {
+                        redirect.push(next);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Redirect(redirect));
+            }
+            c => {
+                let word = word.get_or_insert_with(Word::default);
+                if syntax.expansion.contains(&c) {
+                    word.dynamic = true;
+                }
+                word.push(c, false);
+            }
+        }
+    }
+    finish(&mut word, &mut tokens);
+    Some(tokens)
+}
+
+/// Splits tokens into simple commands at control operators, dropping redirects and their
+/// targets, which may be a file descriptor as in `2>&1`.
+pub fn simple_commands(tokens: &[Token]) -> Vec<Vec<&Word>> {
+    let mut commands = vec![Vec::new()];
+    let mut skip_next = false;
+    for token in tokens {
+        match token {
+            Token::Operator(_) => commands.push(Vec::new()),
+            Token::Redirect(_) => skip_next = true,
+            Token::Word(_) if skip_next => skip_next = false,
+            Token::Word(word) => commands.last_mut().unwrap().push(word),
+        }
+    }
+    commands.retain(|command| !command.is_empty());
+    commands
+}
+
+/// The files that output redirects such as `>` and `>>` write to, with their operators.
+/// Duplicated file descriptors like `2>&1` are left out.
+pub fn redirect_targets(tokens: &[Token]) -> Vec<(&str, &Word)> {
+    tokens
+        .windows(2)
+        .filter_map(|pair| match pair {
+            [Token::Redirect(operator), Token::Word(target)]
+                if operator.contains('>') && !operator.ends_with('&') =>
+            {
+                Some((operator.as_str(), target))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(command: &str, shell_type: &ShellType) -> Vec<String> {
+        tokenize(command, shell_type)
+            .unwrap()
+            .into_iter()
+            .map(|token| match token {
+                Token::Word(word) => word.text,
+                Token::Operator(operator) | Token::Redirect(operator) => operator,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize_posix_quoting() {
+        assert_eq!(
+            words(r#"rm -f 'my file.txt' "other file" a\ b"#, &ShellType::Bash),
+            ["rm", "-f", "my file.txt", "other file", "a b"]
+        );
+        assert_eq!(
+            words("ls | wc -l && echo done; true", &ShellType::Zsh),
+            ["ls", "|", "wc", "-l", "&&", "echo", "done", ";", "true"]
+        );
+        assert_eq!(
+            words("make 2>&1 > build.log", &ShellType::Bash),
+            ["make", "2>&", "1", ">", "build.log"]
+        );
+        assert_eq!(tokenize("echo 'unterminated", &ShellType::Bash), None);
+    }
+
+    #[test]
+    fn test_tokenize_windows_quoting() {
+        assert_eq!(
+            words("Remove-Item 'it''s here' `$notvar", &ShellType::PowerShell),
+            ["Remove-Item", "it's here", "$notvar"]
+        );
+        assert_eq!(
+            words(r#"del "C:\Program Files\x.txt" a^&b"#, &ShellType::Cmd),
+            ["del", r"C:\Program Files\x.txt", "a&b"]
+        );
+    }
+
+    #[test]
+    fn test_globs_and_expansions() {
+        let tokens = tokenize(r#"rm *.log '*.txt' "$HOME/x" \*"#, &ShellType::Bash).unwrap();
+        let words: Vec<&Word> = simple_commands(&tokens).remove(0);
+        assert!(words[1].has_glob());
+        assert!(!words[2].has_glob());
+        assert_eq!(words[2].glob_pattern(), r"\*.txt");
+        assert!(words[3].dynamic);
+        assert!(!words[4].has_glob());
+    }
+
+    #[test]
+    fn test_simple_commands_drop_redirects() {
+        let tokens = tokenize("cat a > out.txt | rm -r b 2>&1", &ShellType::Bash).unwrap();
+        let commands: Vec<Vec<&str>> = simple_commands(&tokens)
+            .into_iter()
+            .map(|words| words.iter().map(|word| word.text.as_str()).collect())
+            .collect();
+        assert_eq!(commands, [vec!["cat", "a"], vec!["rm", "-r", "b"]]);
+
+        let targets: Vec<(&str, &str)> = redirect_targets(&tokens)
+            .into_iter()
+            .map(|(operator, word)| (operator, word.text.as_str()))
+            .collect();
+        assert_eq!(targets, [(">", "out.txt")]);
+    }
+}
@@ -1,45 +1,366 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
 use directories::ProjectDirs;
-use std::env;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::{env, fs};
 
+use crate::assistant::{DEFAULT_ANTHROPIC_MODEL, DEFAULT_MODEL, DEFAULT_OLLAMA_MODEL};
+use crate::credentials;
+use crate::error::ClmError;
 use crate::shell::ShellType;
 
+static CONFIG_FILE: &str = "config.json";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub provider: ProviderConfig,
+    /// Providers tried in order when `provider` fails
+    pub fallbacks: Vec<ProviderConfig>,
+    pub request: RequestConfig,
+    pub cache: CacheConfig,
+    pub usage: UsageConfig,
+    pub snapshots: SnapshotConfig,
+    pub validation: ValidationConfig,
+    pub correction: CorrectionConfig,
+    pub execution: ExecutionConfig,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    #[default]
+    #[value(name = "openai")]
+    OpenAI,
+    Ollama,
+    Anthropic,
+    Azure,
+}
+
+impl ProviderKind {
+    /// Lowercase name used in the config file, on the command line and in credential stores
+    pub fn id(&self) -> &'static str {
+        match self {
+            ProviderKind::OpenAI => "openai",
+            ProviderKind::Ollama => "ollama",
+            ProviderKind::Anthropic => "anthropic",
+            ProviderKind::Azure => "azure",
+        }
+    }
+
+    pub fn default_model(&self) -> &'static str {
+        match self {
+            ProviderKind::OpenAI => DEFAULT_MODEL,
+            ProviderKind::Ollama => DEFAULT_OLLAMA_MODEL,
+            ProviderKind::Anthropic => DEFAULT_ANTHROPIC_MODEL,
+            ProviderKind::Azure => DEFAULT_MODEL,
+        }
+    }
+
+    pub fn api_key_env(&self) -> &'static str {
+        match self {
+            ProviderKind::OpenAI => "OPENAI_API_KEY",
+            ProviderKind::Ollama => "OLLAMA_API_KEY",
+            ProviderKind::Anthropic => "ANTHROPIC_API_KEY",
+            ProviderKind::Azure => "AZURE_OPENAI_API_KEY",
+        }
+    }
+
+    pub fn setup_url(&self) -> &'static str {
+        match self {
+            ProviderKind::OpenAI => "https://platform.openai.com/docs/quickstart",
+            ProviderKind::Ollama => "https://github.com/ollama/ollama/blob/main/docs/faq.md",
+            ProviderKind::Anthropic => "https://docs.anthropic.com/en/api/getting-started",
+            ProviderKind::Azure => "https://learn.microsoft.com/azure/ai-services/openai/quickstart",
+        }
+    }
+}
+
+impl std::fmt::Display for ProviderKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProviderKind::OpenAI => write!(f, "OpenAI"),
+            ProviderKind::Ollama => write!(f, "Ollama"),
+            ProviderKind::Anthropic => write!(f, "Anthropic"),
+            ProviderKind::Azure => write!(f, "Azure OpenAI"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProviderConfig {
+    #[serde(rename = "type")]
+    pub kind: ProviderKind,
+    pub model: Option<String>,
+    /// Base URL of the API, e.g. a local llama.cpp, Ollama or vLLM server
+    pub api_base: Option<String>,
+    pub headers: HashMap<String, String>,
+    /// Defaults to requiring a key for the OpenAI API and not for a custom `api_base`
+    pub require_api_key: Option<bool>,
+    /// Azure OpenAI deployment name, defaulting to the model name
+    pub deployment: Option<String>,
+    /// Azure OpenAI `api-version` query parameter
+    pub api_version: Option<String>,
+    /// Shell command whose first line of output is the API key, e.g. `pass show openai`
+    pub api_key_command: Option<String>,
+    /// How long `api_key_command` may run before it is killed
+    pub api_key_timeout_secs: Option<u64>,
+}
+
+impl ProviderConfig {
+    pub fn model(&self) -> &str {
+        self.model.as_deref().unwrap_or(self.kind.default_model())
+    }
+
+    pub fn api_key_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.api_key_timeout_secs.unwrap_or(30))
+    }
+
+    pub fn requires_api_key(&self) -> bool {
+        match self.kind {
+            ProviderKind::OpenAI => self.require_api_key.unwrap_or(self.api_base.is_none()),
+            ProviderKind::Anthropic | ProviderKind::Azure => self.require_api_key.unwrap_or(true),
+            ProviderKind::Ollama => self.require_api_key.unwrap_or(false),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RequestConfig {
+    pub connect_timeout_secs: u64,
+    /// Limit for a whole request, including reading a streamed response
+    pub timeout_secs: u64,
+    /// Retries after rate limits, server errors and network failures
+    pub max_retries: u32,
+}
+
+impl Default for RequestConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: 10,
+            timeout_secs: 120,
+            max_retries: 3,
+        }
+    }
+}
+
+impl RequestConfig {
+    pub fn connect_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.connect_timeout_secs)
+    }
+
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.timeout_secs)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    pub ttl_hours: u64,
+    pub max_entries: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ttl_hours: 24 * 7,
+            max_entries: 500,
+        }
+    }
+}
+
+/// Checks run on a suggested command before offering to execute it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ValidationConfig {
+    pub enabled: bool,
+    /// Ask the model for a corrected command when the checks find problems
+    pub repair: bool,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            repair: false,
+        }
+    }
+}
+
+/// Asking the model for a fix when an executed command fails
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CorrectionConfig {
+    pub enabled: bool,
+    /// Fixes offered for one query before giving up
+    pub max_attempts: usize,
+}
+
+impl Default for CorrectionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_attempts: 3,
+        }
+    }
+}
+
+/// How suggested commands are run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExecutionConfig {
+    /// Run commands in an interactive shell so aliases and functions from rc files work
+    pub interactive: bool,
+}
+
+/// Copies of the files a command is about to change, kept for `clm undo`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SnapshotConfig {
+    pub enabled: bool,
+    /// Number of snapshots kept, dropping the oldest first
+    pub keep: usize,
+    /// Commands that change more than this run without a snapshot
+    pub max_size_mb: u64,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            keep: 10,
+            max_size_mb: 100,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UsageConfig {
+    /// Prices by model name or prefix, overriding the built-in table
+    pub prices: HashMap<String, ModelPrice>,
+    /// Spending limit in USD per calendar month
+    pub monthly_budget: Option<f64>,
+    pub on_budget_exceeded: BudgetAction,
+}
+
+/// USD per million tokens
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub input: f64,
+    pub output: f64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetAction {
+    #[default]
+    Warn,
+    Block,
+}
+
+impl Config {
+    pub fn load() -> Result<Self> {
+        let mut config = Self::load_from(&get_config_dir()?.join(CONFIG_FILE))?;
+        config.apply_env_overrides();
+        Ok(config)
+    }
+
+    fn apply_env_overrides(&mut self) {
+        if let Ok(api_base) = env::var("CLM_API_BASE") {
+            if !api_base.trim().is_empty() {
+                self.provider.api_base = Some(api_base.trim().to_string());
+            }
+        }
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("Invalid config file {}", path.display())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+fn get_project_dirs() -> Result<ProjectDirs> {
+    ProjectDirs::from("com", "commandlm", "commandlm")
+        .ok_or_else(|| anyhow!("Failed to get project directories"))
+}
+
 pub fn get_config_dir() -> Result<std::path::PathBuf> {
-    let project_dirs = ProjectDirs::from("com", "commandlm", "commandlm")
-        .ok_or_else(|| anyhow!("Failed to get project directories"))?;
-    Ok(project_dirs.config_dir().to_path_buf())
+    Ok(get_project_dirs()?.config_dir().to_path_buf())
 }
 
-pub fn get_api_key(shell_type: &ShellType) -> Result<String> {
-    match env::var("OPENAI_API_KEY") {
-        Ok(key) => Ok(key),
-        Err(_) => {
-            println!(
+pub fn get_data_dir() -> Result<std::path::PathBuf> {
+    Ok(get_project_dirs()?.data_dir().to_path_buf())
+}
+
+pub fn get_cache_dir() -> Result<std::path::PathBuf> {
+    Ok(get_project_dirs()?.cache_dir().to_path_buf())
+}
+
+pub fn get_api_key(
+    store: &credentials::Store,
+    shell_type: &ShellType,
+    provider: &ProviderConfig,
+) -> Result<Option<String>> {
+    let env_var = provider.kind.api_key_env();
+    let setup_url = provider.kind.setup_url();
+
+    match store.lookup(provider)? {
+        Some(key) => Ok(Some(key)),
+        None if !provider.requires_api_key() => Ok(None),
+        None => {
+            // Kept off stdout, which the shell widgets capture as the command
+            eprintln!(
                 "\n{}",
-                console::style("No OpenAI API key found in environment variables.").yellow()
+                console::style(format!("No {} API key found.", provider.kind)).yellow()
             );
-            println!("{}", format!("\nTo set up your API key securely, add this to your {shell_type} configuration file:"));
-            println!(
+            eprintln!("\nTo store your API key in the system keyring, run:");
+            eprintln!(
                 "{}",
-                console::style("export OPENAI_API_KEY='your-key-here'").green()
-            );
-            println!("\nThen reload your terminal to see your changes take effect.");
-            println!(
-                "\nFor more information, please see the official OpenAI Developer Quickstart:"
+                console::style(format!("clm auth login {}", provider.kind.id())).green()
             );
-            println!(
+            eprintln!("\nOr, for CI and other non-interactive use, set it for the current {shell_type} session:");
+            eprintln!(
                 "{}",
-                console::style("https://platform.openai.com/docs/quickstart").blue()
+                console::style(env_var_instruction(shell_type, env_var)).green()
             );
+            eprintln!(
+                "\nFor more information, please see the official {} documentation:",
+                provider.kind
+            );
+            eprintln!("{}", console::style(setup_url).blue());
 
-            Err(anyhow!(
-                "API key is required. Please set the OPENAI_API_KEY environment variable.\n\
-                See https://platform.openai.com/docs/quickstart for more information."
-            ))
+            Err(ClmError::MissingApiKey {
+                provider: provider.kind,
+            }
+            .into())
         }
     }
 }
 
+fn env_var_instruction(shell_type: &ShellType, env_var: &str) -> String {
+    match shell_type {
+        ShellType::Fish => format!("set -gx {env_var} 'your-key-here'"),
+        ShellType::Nushell => format!("$env.{env_var} = 'your-key-here'"),
+        ShellType::Xonsh => format!("${env_var} = 'your-key-here'"),
+        ShellType::Elvish => format!("set E:{env_var} = 'your-key-here'"),
+        ShellType::PowerShell => format!("$env:{env_var} = 'your-key-here'"),
+        ShellType::Cmd => format!("set {env_var}=your-key-here"),
+        _ => format!("export {env_var}='your-key-here'"),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -54,22 +375,226 @@ mod tests {
         assert!(path.to_string_lossy().contains("commandlm"));
     }
 
+    #[test]
+    fn test_get_data_dir() {
+        let path = get_data_dir().unwrap();
+        assert!(path.to_string_lossy().contains("commandlm"));
+    }
+
+    #[test]
+    fn test_load_config_defaults_when_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config::load_from(&dir.path().join(CONFIG_FILE)).unwrap();
+        assert!(config.cache.enabled);
+        assert_eq!(config.cache.ttl_hours, 168);
+        assert_eq!(config.provider.kind, ProviderKind::OpenAI);
+        assert_eq!(config.provider.model(), DEFAULT_MODEL);
+        assert!(config.provider.requires_api_key());
+    }
+
+    #[test]
+    fn test_load_config_partial_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        std::fs::write(&path, r#"{ "cache": { "ttl_hours": 1 } }"#).unwrap();
+
+        let config = Config::load_from(&path).unwrap();
+        assert!(config.cache.enabled);
+        assert_eq!(config.cache.ttl_hours, 1);
+        assert_eq!(config.cache.max_entries, 500);
+        assert_eq!(config.request.max_retries, 3);
+    }
+
+    #[test]
+    fn test_load_config_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        std::fs::write(&path, r#"{ "request": { "timeout_secs": 30, "max_retries": 0 } }"#).unwrap();
+
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.request.timeout(), std::time::Duration::from_secs(30));
+        assert_eq!(config.request.connect_timeout(), std::time::Duration::from_secs(10));
+        assert_eq!(config.request.max_retries, 0);
+    }
+
+    #[test]
+    fn test_load_config_fallbacks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        std::fs::write(
+            &path,
+            r#"{ "provider": { "model": "gpt-4o-mini" }, "fallbacks": [ { "type": "ollama", "model": "llama3.2" } ] }"#,
+        )
+        .unwrap();
+
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.fallbacks.len(), 1);
+        assert_eq!(config.fallbacks[0].kind, ProviderKind::Ollama);
+        assert_eq!(config.fallbacks[0].model(), "llama3.2");
+        assert!(Config::default().fallbacks.is_empty());
+    }
+
+    #[test]
+    fn test_load_config_usage() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        std::fs::write(
+            &path,
+            r#"{ "usage": { "prices": { "llama3": { "input": 0, "output": 0.5 } }, "monthly_budget": 5, "on_budget_exceeded": "block" } }"#,
+        )
+        .unwrap();
+
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.usage.prices["llama3"].output, 0.5);
+        assert_eq!(config.usage.monthly_budget, Some(5.0));
+        assert_eq!(config.usage.on_budget_exceeded, BudgetAction::Block);
+        assert_eq!(Config::default().usage.on_budget_exceeded, BudgetAction::Warn);
+    }
+
+    #[test]
+    fn test_load_config_snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        std::fs::write(&path, r#"{ "snapshots": { "keep": 3 } }"#).unwrap();
+
+        let config = Config::load_from(&path).unwrap();
+        assert!(config.snapshots.enabled);
+        assert_eq!(config.snapshots.keep, 3);
+        assert_eq!(config.snapshots.max_size_mb, 100);
+        assert!(config.validation.enabled);
+        assert!(!config.validation.repair);
+    }
+
+    #[test]
+    fn test_load_config_correction() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        std::fs::write(&path, r#"{ "correction": { "enabled": true } }"#).unwrap();
+
+        let config = Config::load_from(&path).unwrap();
+        assert!(config.correction.enabled);
+        assert_eq!(config.correction.max_attempts, 3);
+        assert!(!Config::default().correction.enabled);
+        assert!(!config.execution.interactive);
+    }
+
+    #[test]
+    fn test_load_config_provider() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        std::fs::write(
+            &path,
+            r#"{ "provider": { "model": "llama3", "api_base": "http://localhost:11434/v1", "headers": { "X-Team": "infra" } } }"#,
+        )
+        .unwrap();
+
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.provider.model(), "llama3");
+        assert_eq!(config.provider.headers.get("X-Team"), Some(&"infra".to_string()));
+        assert!(!config.provider.requires_api_key());
+    }
+
+    #[test]
+    fn test_load_config_ollama_provider() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        std::fs::write(&path, r#"{ "provider": { "type": "ollama" } }"#).unwrap();
+
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.provider.kind, ProviderKind::Ollama);
+        assert_eq!(config.provider.model(), DEFAULT_OLLAMA_MODEL);
+        assert!(!config.provider.requires_api_key());
+    }
+
+    #[test]
+    fn test_load_config_azure_provider() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        std::fs::write(
+            &path,
+            r#"{ "provider": { "type": "azure", "api_base": "https://acme.openai.azure.com", "deployment": "clm-gpt4o", "api_version": "2024-06-01" } }"#,
+        )
+        .unwrap();
+
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.provider.kind, ProviderKind::Azure);
+        assert_eq!(config.provider.deployment.as_deref(), Some("clm-gpt4o"));
+        assert_eq!(config.provider.api_version.as_deref(), Some("2024-06-01"));
+        assert!(config.provider.requires_api_key());
+    }
+
+    #[test]
+    fn test_load_config_api_key_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        std::fs::write(
+            &path,
+            r#"{ "provider": { "api_key_command": "pass show openai", "api_key_timeout_secs": 5 } }"#,
+        )
+        .unwrap();
+
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.provider.api_key_command.as_deref(), Some("pass show openai"));
+        assert_eq!(config.provider.api_key_timeout(), std::time::Duration::from_secs(5));
+        assert_eq!(ProviderConfig::default().api_key_timeout(), std::time::Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_api_base_env_override() {
+        let original = env::var("CLM_API_BASE").ok();
+
+        env::set_var("CLM_API_BASE", "http://127.0.0.1:8000/v1");
+        let mut config = Config::default();
+        config.apply_env_overrides();
+        assert_eq!(config.provider.api_base.as_deref(), Some("http://127.0.0.1:8000/v1"));
+
+        match original {
+            Some(value) => env::set_var("CLM_API_BASE", value),
+            None => env::remove_var("CLM_API_BASE"),
+        }
+    }
+
+    #[test]
+    fn test_load_config_invalid_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        std::fs::write(&path, "not json").unwrap();
+
+        let err = Config::load_from(&path).unwrap_err();
+        assert!(err.to_string().contains("Invalid config file"));
+    }
+
     #[test]
     fn test_get_api_key_with_env() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = credentials::Store::empty_in(dir.path());
         // Store original value
         let original_key = env::var("OPENAI_API_KEY").ok();
         
         // Test with valid API key
         env::set_var("OPENAI_API_KEY", "test-key-123");
-        let result = get_api_key(&ShellType::Bash);
+        let result = get_api_key(&store, &ShellType::Bash, &ProviderConfig::default());
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), "test-key-123");
+        assert_eq!(result.unwrap(), Some("test-key-123".to_string()));
         
         // Test without API key
         env::remove_var("OPENAI_API_KEY");
-        let result = get_api_key(&ShellType::Zsh);
+        let result = get_api_key(&store, &ShellType::Zsh, &ProviderConfig::default());
         assert!(result.is_err());
         assert!(result.unwrap_err().to_string().contains("API key is required"));
+
+        // A custom endpoint doesn't need a key unless configured to
+        let local = ProviderConfig {
+            api_base: Some("http://localhost:8080/v1".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(get_api_key(&store, &ShellType::Zsh, &local).unwrap(), None);
+
+        let keyed = ProviderConfig {
+            require_api_key: Some(true),
+            ..local
+        };
+        assert!(get_api_key(&store, &ShellType::Zsh, &keyed).is_err());
         
         // Restore original value or remove if it didn't exist
         match original_key {
@@ -78,8 +603,42 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_get_api_key_uses_provider_env_var() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = credentials::Store::empty_in(dir.path());
+        let original_key = env::var("ANTHROPIC_API_KEY").ok();
+        let provider = ProviderConfig {
+            kind: ProviderKind::Anthropic,
+            ..Default::default()
+        };
+
+        env::set_var("ANTHROPIC_API_KEY", "sk-ant-test");
+        assert_eq!(get_api_key(&store, &ShellType::Zsh, &provider).unwrap(), Some("sk-ant-test".to_string()));
+
+        env::remove_var("ANTHROPIC_API_KEY");
+        let err = get_api_key(&store, &ShellType::Zsh, &provider).unwrap_err();
+        assert!(err.to_string().contains("ANTHROPIC_API_KEY"));
+        assert!(err.to_string().contains("docs.anthropic.com"));
+
+        match original_key {
+            Some(value) => env::set_var("ANTHROPIC_API_KEY", value),
+            None => env::remove_var("ANTHROPIC_API_KEY"),
+        }
+    }
+
+    #[test]
+    fn test_env_var_instruction_per_shell() {
+        assert_eq!(env_var_instruction(&ShellType::Zsh, "OPENAI_API_KEY"), "export OPENAI_API_KEY='your-key-here'");
+        assert_eq!(env_var_instruction(&ShellType::Fish, "OPENAI_API_KEY"), "set -gx OPENAI_API_KEY 'your-key-here'");
+        assert_eq!(env_var_instruction(&ShellType::PowerShell, "X"), "$env:X = 'your-key-here'");
+        assert_eq!(env_var_instruction(&ShellType::Nushell, "X"), "$env.X = 'your-key-here'");
+    }
+
     #[test]
     fn test_get_api_key_error_message_contains_shell() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = credentials::Store::empty_in(dir.path());
         // Store original value
         let original_key = env::var("OPENAI_API_KEY").ok();
         
@@ -88,7 +647,7 @@ mod tests {
         
         // The function should print shell-specific instructions
         // We can't easily test the print output, but we can test it doesn't panic
-        let result = get_api_key(&ShellType::Fish);
+        let result = get_api_key(&store, &ShellType::Fish, &ProviderConfig::default());
         assert!(result.is_err());
         
         // Restore original value
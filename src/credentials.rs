@@ -0,0 +1,608 @@
+use anyhow::{anyhow, bail, Context, Result};
+use argon2::Argon2;
+use atty::Stream;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use dialoguer::Password;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::config::{get_data_dir, ProviderConfig, ProviderKind};
+
+static KEYRING_SERVICE: &str = "commandlm";
+static KEY_FILE: &str = "credentials.json";
+static PASSPHRASE_ENV: &str = "CLM_KEYSTORE_PASSPHRASE";
+
+/// Where an API key was found. Lookups try these in declaration order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeySource {
+    Env(&'static str),
+    Command,
+    Keyring,
+    File,
+}
+
+impl std::fmt::Display for KeySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeySource::Env(var) => write!(f, "environment ({var})"),
+            KeySource::Command => write!(f, "api_key_command"),
+            KeySource::Keyring => write!(f, "system keyring"),
+            KeySource::File => write!(f, "encrypted file"),
+        }
+    }
+}
+
+/// Where API keys are read from after the environment and `api_key_command`.
+pub struct Store {
+    keyring: bool,
+    /// `None` for the key file in the data directory
+    file: Option<KeyFile>,
+}
+
+impl Store {
+    pub fn system() -> Self {
+        Self {
+            keyring: true,
+            file: None,
+        }
+    }
+
+    /// Only the given key file, leaving the system keyring alone.
+    pub fn file(file: KeyFile) -> Self {
+        Self {
+            keyring: false,
+            file: Some(file),
+        }
+    }
+
+    /// An empty key file in `dir`, so tests never read the user's real credentials.
+    #[cfg(test)]
+    pub(crate) fn empty_in(dir: &Path) -> Self {
+        Self::file(KeyFile::new(dir.join(KEY_FILE)))
+    }
+
+    /// Resolves the API key for a provider from the environment, then `api_key_command`,
+    /// then the system keyring, then the encrypted key file.
+    pub fn lookup(&self, provider: &ProviderConfig) -> Result<Option<String>> {
+        let kind = provider.kind;
+        if let Ok(key) = std::env::var(kind.api_key_env()) {
+            return Ok(Some(key));
+        }
+        if let Some(command) = &provider.api_key_command {
+            return run_key_command(command, provider.api_key_timeout()).map(Some);
+        }
+        if self.keyring {
+            if let Some(key) = keyring_get(kind) {
+                return Ok(Some(key));
+            }
+        }
+
+        let default_file;
+        let file = match &self.file {
+            Some(file) => file,
+            None => {
+                default_file = KeyFile::open_default()?;
+                &default_file
+            }
+        };
+        if !file.contains(kind)? {
+            return Ok(None);
+        }
+        let passphrase = passphrase(file.path(), false)?;
+        file.get(kind, &passphrase)
+    }
+}
+
+/// Stores a key in the system keyring, falling back to the encrypted file when no
+/// keyring is reachable (e.g. a headless Linux box without a Secret Service daemon).
+pub fn store(kind: ProviderKind, key: &str, file_only: bool) -> Result<KeySource> {
+    if !file_only {
+        match keyring_entry(kind).and_then(|entry| entry.set_password(key)) {
+            Ok(()) => return Ok(KeySource::Keyring),
+            Err(err) => eprintln!(
+                "{}",
+                console::style(format!(
+                    "System keyring unavailable ({err}), using the encrypted key file instead."
+                ))
+                .yellow()
+            ),
+        }
+    }
+
+    let file = KeyFile::open_default()?;
+    let passphrase = passphrase(file.path(), !file.exists())?;
+    file.set(kind, key, &passphrase)?;
+    Ok(KeySource::File)
+}
+
+/// Removes a stored key from every credential store, returning the ones it was found in.
+pub fn remove(kind: ProviderKind) -> Result<Vec<KeySource>> {
+    let mut removed = Vec::new();
+    if keyring_entry(kind)
+        .and_then(|entry| entry.delete_credential())
+        .is_ok()
+    {
+        removed.push(KeySource::Keyring);
+    }
+    if KeyFile::open_default()?.remove(kind)? {
+        removed.push(KeySource::File);
+    }
+    Ok(removed)
+}
+
+/// Reports where a key for the provider would be read from, without decrypting the key file.
+pub fn source(provider: &ProviderConfig) -> Result<Option<KeySource>> {
+    let kind = provider.kind;
+    if std::env::var(kind.api_key_env()).is_ok() {
+        return Ok(Some(KeySource::Env(kind.api_key_env())));
+    }
+    if provider.api_key_command.is_some() {
+        return Ok(Some(KeySource::Command));
+    }
+    if keyring_get(kind).is_some() {
+        return Ok(Some(KeySource::Keyring));
+    }
+    if KeyFile::open_default()?.contains(kind)? {
+        return Ok(Some(KeySource::File));
+    }
+    Ok(None)
+}
+
+/// Runs `api_key_command` through the shell and returns the first line it prints. The
+/// result is kept for the rest of the process so the command runs at most once.
+fn run_key_command(command: &str, timeout: Duration) -> Result<String> {
+    static KEYS: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
+    let keys = KEYS.get_or_init(Default::default);
+    if let Some(key) = keys.lock().unwrap().get(command) {
+        return Ok(key.clone());
+    }
+
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let mut key_command = Command::new(shell);
+    // stdin and stderr stay attached so tools like pass or op can prompt for unlocking
+    key_command.args([flag, command]).stdout(Stdio::piped());
+    #[cfg(unix)]
+    let terminal = own_process_group(&mut key_command);
+    let mut child = key_command
+        .spawn()
+        .with_context(|| format!("Failed to run api_key_command `{command}`"))?;
+
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let reader = std::thread::spawn(move || {
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).map(|_| output)
+    });
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Ok(status),
+            Ok(None) => {}
+            Err(err) => break Err(err.into()),
+        }
+        if Instant::now() >= deadline {
+            // Kill everything the command started, not just the shell running it
+            #[cfg(unix)]
+            // SAFETY: plain syscall; the group was created for this child alone
+            unsafe {
+                libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+            }
+            #[cfg(not(unix))]
+            let _ = child.kill();
+            let _ = child.wait();
+            break Err(anyhow!(
+                "api_key_command `{command}` timed out after {}s",
+                timeout.as_secs_f32()
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    #[cfg(unix)]
+    if terminal {
+        reclaim_terminal();
+    }
+    let status = status?;
+    if !status.success() {
+        bail!("api_key_command `{command}` failed with {status}");
+    }
+
+    let output = reader
+        .join()
+        .map_err(|_| anyhow!("Failed to read api_key_command output"))??;
+    let key = output.lines().next().unwrap_or_default().trim().to_string();
+    if key.is_empty() {
+        bail!("api_key_command `{command}` printed no key");
+    }
+
+    keys.lock()
+        .unwrap()
+        .insert(command.to_string(), key.clone());
+    Ok(key)
+}
+
+/// Starts the command in a process group of its own, so a timeout can kill everything it
+/// started. When clm runs in the foreground of a terminal the group gets the terminal, or
+/// a prompt for unlocking would stop it. Returns whether the terminal was handed over.
+#[cfg(unix)]
+fn own_process_group(command: &mut Command) -> bool {
+    use std::os::unix::process::CommandExt;
+
+    // SAFETY: plain syscalls on the standard input descriptor
+    let terminal = unsafe { libc::tcgetpgrp(libc::STDIN_FILENO) == libc::getpgrp() };
+    if !terminal {
+        command.process_group(0);
+        return false;
+    }
+    // SAFETY: only async-signal-safe calls run between fork and exec
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setpgid(0, 0) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            // A background group taking the terminal gets SIGTTOU unless it's blocked
+            with_sigttou_blocked(|| libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp()));
+            Ok(())
+        });
+    }
+    true
+}
+
+/// Takes the terminal back once the key command's group is done with it.
+#[cfg(unix)]
+fn reclaim_terminal() {
+    // SAFETY: plain syscalls on the standard input descriptor
+    unsafe {
+        with_sigttou_blocked(|| libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp()));
+    }
+}
+
+#[cfg(unix)]
+unsafe fn with_sigttou_blocked(f: impl FnOnce() -> libc::c_int) {
+    let mut blocked: libc::sigset_t = std::mem::zeroed();
+    let mut previous: libc::sigset_t = std::mem::zeroed();
+    libc::sigemptyset(&mut blocked);
+    libc::sigaddset(&mut blocked, libc::SIGTTOU);
+    libc::pthread_sigmask(libc::SIG_BLOCK, &blocked, &mut previous);
+    f();
+    libc::pthread_sigmask(libc::SIG_SETMASK, &previous, std::ptr::null_mut());
+}
+
+fn keyring_entry(kind: ProviderKind) -> keyring::Result<keyring::Entry> {
+    keyring::Entry::new(KEYRING_SERVICE, kind.id())
+}
+
+fn keyring_get(kind: ProviderKind) -> Option<String> {
+    // A missing or unreachable keyring is not an error, the file store is tried next
+    keyring_entry(kind)
+        .and_then(|entry| entry.get_password())
+        .ok()
+}
+
+fn passphrase(path: &Path, confirm: bool) -> Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    if !atty::is(Stream::Stdin) {
+        bail!(
+            "A passphrase is needed to unlock {}. Set {PASSPHRASE_ENV} when running non-interactively.",
+            path.display()
+        );
+    }
+
+    let mut prompt = Password::new().with_prompt(format!("Passphrase for {}", path.display()));
+    if confirm {
+        prompt = prompt.with_confirmation("Confirm passphrase", "Passphrases don't match");
+    }
+    Ok(prompt.interact()?)
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct KeyFileContents {
+    /// Hex encoded Argon2 salt shared by all entries
+    salt: String,
+    entries: BTreeMap<String, EncryptedKey>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EncryptedKey {
+    nonce: String,
+    ciphertext: String,
+}
+
+/// API keys encrypted with XChaCha20-Poly1305 under a passphrase-derived key. Provider
+/// names are stored in the clear so `clm auth status` works without the passphrase.
+pub struct KeyFile {
+    path: PathBuf,
+}
+
+impl KeyFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn open_default() -> Result<Self> {
+        Ok(Self::new(get_data_dir()?.join(KEY_FILE)))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    pub fn contains(&self, kind: ProviderKind) -> Result<bool> {
+        Ok(self
+            .load()?
+            .is_some_and(|contents| contents.entries.contains_key(kind.id())))
+    }
+
+    pub fn get(&self, kind: ProviderKind, passphrase: &str) -> Result<Option<String>> {
+        let Some(contents) = self.load()? else {
+            return Ok(None);
+        };
+        let Some(entry) = contents.entries.get(kind.id()) else {
+            return Ok(None);
+        };
+        let cipher = cipher(passphrase, &decode_hex(&contents.salt)?)?;
+        decrypt(&cipher, kind.id(), entry).map(Some)
+    }
+
+    pub fn set(&self, kind: ProviderKind, key: &str, passphrase: &str) -> Result<()> {
+        let mut contents = self.load()?.unwrap_or_else(|| {
+            let mut salt = [0u8; 16];
+            OsRng.fill_bytes(&mut salt);
+            KeyFileContents {
+                salt: encode_hex(&salt),
+                entries: BTreeMap::new(),
+            }
+        });
+        let cipher = cipher(passphrase, &decode_hex(&contents.salt)?)?;
+
+        // Every entry shares the passphrase, so check it against an existing one first
+        if let Some((id, entry)) = contents.entries.iter().next() {
+            decrypt(&cipher, id, entry)?;
+        }
+
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: key.as_bytes(),
+                    aad: kind.id().as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow!("Failed to encrypt API key"))?;
+        contents.entries.insert(
+            kind.id().to_string(),
+            EncryptedKey {
+                nonce: encode_hex(&nonce),
+                ciphertext: encode_hex(&ciphertext),
+            },
+        );
+        self.save(&contents)
+    }
+
+    pub fn remove(&self, kind: ProviderKind) -> Result<bool> {
+        let Some(mut contents) = self.load()? else {
+            return Ok(false);
+        };
+        if contents.entries.remove(kind.id()).is_none() {
+            return Ok(false);
+        }
+        if contents.entries.is_empty() {
+            fs::remove_file(&self.path)?;
+            return Ok(true);
+        }
+        self.save(&contents)?;
+        Ok(true)
+    }
+
+    fn load(&self) -> Result<Option<KeyFileContents>> {
+        match fs::read_to_string(&self.path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map(Some)
+                .with_context(|| format!("Invalid key file {}", self.path.display())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn save(&self, contents: &KeyFileContents) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut options = fs::OpenOptions::new();
+        options.create(true).write(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let tmp_path = self.path.with_extension("json.tmp");
+        let file = options.open(&tmp_path)?;
+        serde_json::to_writer_pretty(file, contents)?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("Failed to write key file {}", self.path.display()))
+    }
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<XChaCha20Poly1305> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|err| anyhow!("Failed to derive encryption key: {err}"))?;
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
+fn decrypt(cipher: &XChaCha20Poly1305, id: &str, entry: &EncryptedKey) -> Result<String> {
+    let nonce = decode_hex(&entry.nonce)?;
+    if nonce.len() != 24 {
+        bail!("Corrupt entry for {id} in key file");
+    }
+    let plaintext = cipher
+        .decrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: &decode_hex(&entry.ciphertext)?,
+                aad: id.as_bytes(),
+            },
+        )
+        .map_err(|_| anyhow!("Wrong passphrase for the key file"))?;
+    String::from_utf8(plaintext).context("Stored API key is not valid UTF-8")
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>> {
+    // Checked up front so the byte slicing below can't split a multi-byte character
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        bail!("Invalid hex in key file");
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).context("Invalid hex in key file"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_file_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = KeyFile::new(dir.path().join(KEY_FILE));
+
+        assert!(!file.contains(ProviderKind::OpenAI).unwrap());
+        file.set(ProviderKind::OpenAI, "sk-test", "hunter2")
+            .unwrap();
+        file.set(ProviderKind::Anthropic, "sk-ant-test", "hunter2")
+            .unwrap();
+
+        assert!(file.contains(ProviderKind::OpenAI).unwrap());
+        assert_eq!(
+            file.get(ProviderKind::OpenAI, "hunter2").unwrap(),
+            Some("sk-test".to_string())
+        );
+        assert_eq!(
+            file.get(ProviderKind::Anthropic, "hunter2").unwrap(),
+            Some("sk-ant-test".to_string())
+        );
+        assert_eq!(file.get(ProviderKind::Azure, "hunter2").unwrap(), None);
+
+        let raw = fs::read_to_string(file.path()).unwrap();
+        assert!(!raw.contains("sk-test"));
+    }
+
+    #[test]
+    fn test_key_file_rejects_wrong_passphrase() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = KeyFile::new(dir.path().join(KEY_FILE));
+        file.set(ProviderKind::OpenAI, "sk-test", "hunter2")
+            .unwrap();
+
+        let err = file.get(ProviderKind::OpenAI, "wrong").unwrap_err();
+        assert!(err.to_string().contains("Wrong passphrase"));
+        assert!(file.set(ProviderKind::Azure, "key", "wrong").is_err());
+    }
+
+    #[test]
+    fn test_decode_hex_rejects_invalid_input() {
+        assert_eq!(decode_hex("00ff").unwrap(), vec![0x00, 0xff]);
+        assert!(decode_hex("0").is_err());
+        assert!(decode_hex("zz").is_err());
+        assert!(decode_hex("é0").is_err());
+        assert!(decode_hex("0é").is_err());
+    }
+
+    #[test]
+    fn test_key_file_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = KeyFile::new(dir.path().join(KEY_FILE));
+        file.set(ProviderKind::OpenAI, "sk-test", "hunter2")
+            .unwrap();
+
+        assert!(!file.remove(ProviderKind::Ollama).unwrap());
+        assert!(file.remove(ProviderKind::OpenAI).unwrap());
+        assert!(!file.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_key_command() {
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            run_key_command("printf 'sk-test\\nuser: me\\n'", timeout).unwrap(),
+            "sk-test"
+        );
+
+        let err = run_key_command("exit 3", timeout).unwrap_err();
+        assert!(err.to_string().contains("failed"));
+        let err = run_key_command("true", timeout).unwrap_err();
+        assert!(err.to_string().contains("printed no key"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_key_command_caches_result() {
+        let dir = tempfile::tempdir().unwrap();
+        let counter = dir.path().join("runs");
+        let command = format!("echo run >> {0}; echo sk-cached", counter.display());
+
+        for _ in 0..2 {
+            assert_eq!(
+                run_key_command(&command, Duration::from_secs(5)).unwrap(),
+                "sk-cached"
+            );
+        }
+        assert_eq!(fs::read_to_string(counter).unwrap().lines().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_key_command_times_out() {
+        let started = Instant::now();
+        let err = run_key_command("sleep 5", Duration::from_millis(200)).unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_key_command_timeout_kills_children() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("still-running");
+        let command = format!("(sleep 1; touch {0}) & sleep 5", marker.display());
+
+        let err = run_key_command(&command, Duration::from_millis(200)).unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+        std::thread::sleep(Duration::from_millis(1500));
+        assert!(!marker.exists());
+    }
+
+    #[test]
+    fn test_hex_round_trip() {
+        let bytes = [0u8, 15, 16, 255];
+        assert_eq!(decode_hex(&encode_hex(&bytes)).unwrap(), bytes);
+        assert!(decode_hex("abc").is_err());
+    }
+}
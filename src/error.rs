@@ -0,0 +1,413 @@
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::config::ProviderKind;
+
+/// Provider failures classified so they can be reported with a suggested fix and a
+/// distinct exit code. Everything else stays a plain `anyhow::Error`.
+#[derive(Debug, Error)]
+pub enum ClmError {
+    #[error(
+        "API key is required. Please run `clm auth login {}` or set the {} environment variable.\n\
+        See {} for more information.",
+        .provider.id(),
+        .provider.api_key_env(),
+        .provider.setup_url()
+    )]
+    MissingApiKey { provider: ProviderKind },
+
+    #[error("{provider} rejected the API key: {message}")]
+    Auth {
+        provider: ProviderKind,
+        message: String,
+    },
+
+    #[error("Your {provider} account is out of quota: {message}")]
+    Quota {
+        provider: ProviderKind,
+        message: String,
+    },
+
+    #[error("{provider} rate limit reached: {message}")]
+    RateLimit {
+        provider: ProviderKind,
+        message: String,
+        retry_after: Option<Duration>,
+    },
+
+    #[error("Model '{model}' is not available from {provider}: {message}")]
+    ModelNotFound {
+        provider: ProviderKind,
+        model: String,
+        message: String,
+    },
+
+    #[error("The request is too long for the context window of '{model}': {message}")]
+    ContextTooLong { model: String, message: String },
+
+    #[error("Could not reach {provider} at {url}: {message}")]
+    Network {
+        provider: ProviderKind,
+        url: String,
+        message: String,
+    },
+
+    #[error("Could not parse the response from {provider}: {message}")]
+    Parse {
+        provider: ProviderKind,
+        message: String,
+    },
+
+    #[error("{provider} request failed: {message}")]
+    Api {
+        provider: ProviderKind,
+        status: Option<u16>,
+        message: String,
+    },
+
+    #[error("Monthly budget of ${budget:.2} reached: ${spent:.2} spent this month")]
+    BudgetExceeded { spent: f64, budget: f64 },
+}
+
+impl ClmError {
+    /// Classifies an error response from the HTTP status and the provider's error code or
+    /// type, falling back to the message text for servers that only send that.
+    pub fn from_response(
+        provider: ProviderKind,
+        model: &str,
+        status: Option<u16>,
+        code: &str,
+        message: impl Into<String>,
+    ) -> Self {
+        let message = message.into();
+        let text = format!("{code} {message}").to_lowercase();
+        let mentions = |needles: &[&str]| needles.iter().any(|needle| text.contains(needle));
+
+        if matches!(status, Some(401 | 403))
+            || mentions(&[
+                "invalid_api_key",
+                "incorrect api key",
+                "authentication_error",
+                "permission_error",
+                "unauthorized",
+            ])
+        {
+            ClmError::Auth { provider, message }
+        } else if status == Some(402)
+            || mentions(&[
+                "insufficient_quota",
+                "exceeded your current quota",
+                "credit balance",
+                "billing",
+            ])
+        {
+            ClmError::Quota { provider, message }
+        } else if status == Some(429) || mentions(&["rate_limit", "rate limit"]) {
+            ClmError::RateLimit {
+                provider,
+                message,
+                retry_after: None,
+            }
+        } else if mentions(&[
+            "context_length_exceeded",
+            "context length",
+            "context window",
+            "maximum context",
+            "prompt is too long",
+        ]) {
+            ClmError::ContextTooLong {
+                model: model.to_string(),
+                message,
+            }
+        } else if mentions(&["model_not_found", "deploymentnotfound", "not_found_error"])
+            || (status == Some(404) && mentions(&["model", "deployment"]))
+        {
+            ClmError::ModelNotFound {
+                provider,
+                model: model.to_string(),
+                message,
+            }
+        } else {
+            let message = if code.is_empty() || message.contains(code) {
+                message
+            } else {
+                format!("{code}: {message}")
+            };
+            ClmError::Api {
+                provider,
+                status,
+                message,
+            }
+        }
+    }
+
+    pub fn network(provider: ProviderKind, url: &str, err: reqwest::Error) -> Self {
+        let message = if err.is_timeout() {
+            "the request timed out".to_string()
+        } else if err.is_connect() {
+            "connection failed".to_string()
+        } else {
+            err.to_string()
+        };
+        ClmError::Network {
+            provider,
+            url: url.to_string(),
+            message,
+        }
+    }
+
+    pub fn parse(provider: ProviderKind, err: impl std::fmt::Display) -> Self {
+        ClmError::Parse {
+            provider,
+            message: err.to_string(),
+        }
+    }
+
+    pub fn with_retry_after(mut self, delay: Option<Duration>) -> Self {
+        if let ClmError::RateLimit { retry_after, .. } = &mut self {
+            *retry_after = delay;
+        }
+        self
+    }
+
+    /// Whether the same request may succeed if sent again after a delay.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ClmError::RateLimit { .. } | ClmError::Network { .. } => true,
+            ClmError::Api {
+                status: Some(status),
+                ..
+            } => *status == 408 || *status >= 500,
+            // Anthropic reports overload inside the stream, after a 200 status
+            ClmError::Api { message, .. } => message.contains("overloaded"),
+            _ => false,
+        }
+    }
+
+    /// Whether a different model or provider might answer instead. A request that is too
+    /// long for one model is most likely too long for the next.
+    pub fn allows_fallback(&self) -> bool {
+        !matches!(self, ClmError::ContextTooLong { .. })
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ClmError::RateLimit { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    pub fn hint(&self) -> Option<String> {
+        match self {
+            ClmError::MissingApiKey { .. } => None,
+            ClmError::Auth { provider, .. } => Some(format!(
+                "Check where the key is read from with `clm auth status`, then store a new one with `clm auth login {}`.",
+                provider.id()
+            )),
+            ClmError::Quota { provider, .. } => Some(format!(
+                "Add credits or raise the spending limit in your {provider} account, or switch provider.type in your config."
+            )),
+            ClmError::RateLimit { retry_after, .. } => Some(match retry_after {
+                Some(delay) => format!("Try again in {} seconds.", delay.as_secs().max(1)),
+                None => "Wait a moment and try again.".to_string(),
+            }),
+            ClmError::ModelNotFound {
+                provider: ProviderKind::Ollama,
+                model,
+                ..
+            } => Some(format!("Download it with `clm models pull {model}`.")),
+            ClmError::ModelNotFound { .. } => Some(
+                "Run `clm models` to list the available models and set provider.model in your config."
+                    .to_string(),
+            ),
+            ClmError::ContextTooLong { .. } => Some(
+                "Pipe in less input, e.g. through `head` or `tail`, or use a model with a larger context window."
+                    .to_string(),
+            ),
+            ClmError::Network {
+                provider: ProviderKind::Ollama,
+                ..
+            } => Some(
+                "Is the daemon running? Start it with `ollama serve`, or set provider.api_base / OLLAMA_HOST to its address."
+                    .to_string(),
+            ),
+            ClmError::Network { .. } => Some(
+                "Check your network connection and provider.api_base in your config.".to_string(),
+            ),
+            ClmError::Parse { .. } => Some(
+                "Check that provider.type matches the kind of server at provider.api_base."
+                    .to_string(),
+            ),
+            ClmError::Api { .. } => None,
+            ClmError::BudgetExceeded { .. } => Some(
+                "See `clm usage` for details. Raise usage.monthly_budget in your config, or set usage.on_budget_exceeded to \"warn\"."
+                    .to_string(),
+            ),
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ClmError::MissingApiKey { .. } | ClmError::Auth { .. } => 3,
+            ClmError::Quota { .. } => 4,
+            ClmError::RateLimit { .. } => 5,
+            ClmError::ModelNotFound { .. } => 6,
+            ClmError::ContextTooLong { .. } => 7,
+            ClmError::Network { .. } => 8,
+            ClmError::Parse { .. } => 9,
+            ClmError::Api { .. } => 10,
+            ClmError::BudgetExceeded { .. } => 11,
+        }
+    }
+}
+
+/// Prints an error for the user and returns the process exit code for it.
+pub fn report(err: &anyhow::Error) -> i32 {
+    let Some(clm_error) = err.downcast_ref::<ClmError>() else {
+        eprintln!("{} {err:#}", console::style("Error:").red().bold());
+        return 1;
+    };
+
+    eprintln!("{} {clm_error}", console::style("Error:").red().bold());
+    if let Some(hint) = clm_error.hint() {
+        eprintln!("{} {hint}", console::style("Hint:").yellow());
+    }
+    clm_error.exit_code()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classify(status: Option<u16>, code: &str, message: &str) -> ClmError {
+        ClmError::from_response(ProviderKind::OpenAI, "gpt-4o-mini", status, code, message)
+    }
+
+    #[test]
+    fn test_classify_by_status() {
+        assert!(matches!(
+            classify(Some(401), "", "nope"),
+            ClmError::Auth { .. }
+        ));
+        assert!(matches!(
+            classify(Some(429), "", "slow down"),
+            ClmError::RateLimit { .. }
+        ));
+        assert!(matches!(
+            classify(Some(404), "", "model 'llama9' not found"),
+            ClmError::ModelNotFound { .. }
+        ));
+        assert!(matches!(
+            classify(Some(404), "", "page not found"),
+            ClmError::Api {
+                status: Some(404),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_classify_by_code() {
+        assert!(matches!(
+            classify(
+                None,
+                "insufficient_quota",
+                "You exceeded your current quota"
+            ),
+            ClmError::Quota { .. }
+        ));
+        assert!(matches!(
+            classify(
+                Some(429),
+                "insufficient_quota",
+                "You exceeded your current quota"
+            ),
+            ClmError::Quota { .. }
+        ));
+        assert!(matches!(
+            classify(
+                None,
+                "context_length_exceeded",
+                "This model's maximum context length is 128000 tokens"
+            ),
+            ClmError::ContextTooLong { .. }
+        ));
+        assert!(matches!(
+            classify(None, "invalid_api_key", "Incorrect API key provided"),
+            ClmError::Auth { .. }
+        ));
+        assert!(matches!(
+            classify(
+                None,
+                "DeploymentNotFound",
+                "The API deployment for this resource does not exist"
+            ),
+            ClmError::ModelNotFound { .. }
+        ));
+    }
+
+    #[test]
+    fn test_unclassified_error_keeps_code() {
+        let err = classify(Some(529), "overloaded_error", "Overloaded");
+        assert_eq!(
+            err.to_string(),
+            "OpenAI request failed: overloaded_error: Overloaded"
+        );
+        assert_eq!(err.exit_code(), 10);
+        assert!(err.hint().is_none());
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(classify(Some(429), "", "slow down").is_retryable());
+        assert!(classify(Some(503), "", "unavailable").is_retryable());
+        assert!(classify(None, "overloaded_error", "Overloaded").is_retryable());
+        assert!(!classify(Some(401), "", "bad key").is_retryable());
+        assert!(!classify(Some(429), "insufficient_quota", "").is_retryable());
+        assert!(!classify(Some(400), "", "bad request").is_retryable());
+    }
+
+    #[test]
+    fn test_allows_fallback() {
+        assert!(classify(Some(401), "", "bad key").allows_fallback());
+        assert!(classify(Some(404), "model_not_found", "").allows_fallback());
+        assert!(classify(Some(500), "", "server error").allows_fallback());
+        assert!(!classify(None, "context_length_exceeded", "").allows_fallback());
+    }
+
+    #[test]
+    fn test_rate_limit_hint_uses_retry_after() {
+        let err =
+            classify(Some(429), "", "slow down").with_retry_after(Some(Duration::from_secs(20)));
+        assert_eq!(err.hint().unwrap(), "Try again in 20 seconds.");
+    }
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let errors = [
+            classify(Some(401), "", ""),
+            classify(Some(402), "", ""),
+            classify(Some(429), "", ""),
+            classify(None, "model_not_found", ""),
+            classify(None, "context_length_exceeded", ""),
+            ClmError::parse(ProviderKind::OpenAI, "eof"),
+            classify(Some(500), "", ""),
+            ClmError::BudgetExceeded {
+                spent: 5.0,
+                budget: 5.0,
+            },
+        ];
+        let mut codes: Vec<i32> = errors.iter().map(ClmError::exit_code).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+        assert!(!codes.contains(&1));
+    }
+
+    #[test]
+    fn test_report_returns_exit_code() {
+        let err = anyhow::Error::from(classify(Some(401), "", "bad key"));
+        assert_eq!(report(&err), 3);
+        assert_eq!(report(&anyhow::anyhow!("something else")), 1);
+    }
+}
@@ -0,0 +1,363 @@
+use anyhow::{Context, Result};
+use std::ffi::OsString;
+use std::io::{self, Read, Write};
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::sandbox::Sandbox;
+use crate::shell::{ShellContext, ShellType};
+
+/// Bytes kept from each of stdout and stderr; the rest is read and dropped.
+const MAX_OUTPUT: usize = 1024 * 1024;
+
+/// How a command ran and what it printed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionResult {
+    /// None when the command was killed by a signal
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub duration: Duration,
+    /// Whether stdout or stderr was longer than the 1 MiB kept of each
+    pub truncated: bool,
+}
+
+impl ExecutionResult {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+
+    /// The code to exit with for this result, using 128 plus the signal number the way shells
+    /// do when the command was killed.
+    pub fn process_exit_code(&self) -> i32 {
+        match (self.exit_code, self.signal) {
+            (Some(code), _) => code,
+            (None, Some(signal)) => 128 + signal,
+            (None, None) => 1,
+        }
+    }
+}
+
+/// Runs the command with the user's shell, inside the sandbox when one is given. Its output
+/// goes to the terminal as it arrives and is captured as well. `interactive` starts the shell
+/// with `-i` so aliases and functions from its rc files are available.
+pub fn execute(
+    command: &str,
+    shell_context: &ShellContext,
+    interactive: bool,
+    sandbox: Option<&Sandbox>,
+) -> Result<ExecutionResult> {
+    let (program, mut args) = shell_invocation(shell_context, interactive);
+    args.push(command);
+    let child = match sandbox {
+        Some(sandbox) => sandbox.command(&program, &args),
+        None => {
+            let mut child = Command::new(&program);
+            child.args(&args);
+            child
+        }
+    };
+    capture(child, true, interactive)
+        .with_context(|| format!("Failed to run {}", program.to_string_lossy()))
+}
+
+/// Runs the command with the terminal as its stdin, which prompts like `rm -i` need, and
+/// captures stdout and stderr, copying them to the terminal when `echo` is set.
+fn capture(mut command: Command, echo: bool, interactive: bool) -> Result<ExecutionResult> {
+    let started = Instant::now();
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let stderr: Box<dyn Read + Send> = if interactive {
+        Box::new(SkipJobControlNoise::new(stderr))
+    } else {
+        Box::new(stderr)
+    };
+    // Read both at once so a command filling one pipe can't block on the other
+    let stderr = thread::spawn(move || read_limited(stderr, echo.then(io::stderr)));
+    let (stdout, stdout_truncated) = read_limited(stdout, echo.then(io::stdout))?;
+    let (stderr, stderr_truncated) = stderr.join().expect("stderr reader panicked")?;
+    let status = child.wait()?;
+
+    Ok(ExecutionResult {
+        exit_code: status.code(),
+        signal: signal(&status),
+        stdout: String::from_utf8_lossy(&stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&stderr).into_owned(),
+        duration: started.elapsed(),
+        truncated: stdout_truncated || stderr_truncated,
+    })
+}
+
+/// The shell binary and the arguments that come before the command. Uses the path the shell
+/// was detected from, falling back to its usual name.
+fn shell_invocation(shell_context: &ShellContext, interactive: bool) -> (OsString, Vec<&str>) {
+    let (name, interactive_flag, command_flag) = match &shell_context.shell_type {
+        ShellType::Bash => ("bash", Some("-i"), "-c"),
+        ShellType::Zsh => ("zsh", Some("-i"), "-c"),
+        ShellType::Fish => ("fish", Some("-i"), "-c"),
+        ShellType::Dash => ("dash", Some("-i"), "-c"),
+        ShellType::Ksh => ("ksh", Some("-i"), "-c"),
+        ShellType::Xonsh => ("xonsh", Some("-i"), "-c"),
+        // Nushell and elvish have no flag that loads their config alongside -c
+        ShellType::Nushell => ("nu", None, "-c"),
+        ShellType::Elvish => ("elvish", None, "-c"),
+        // PowerShell loads the profile for -Command already
+        ShellType::PowerShell => ("powershell", None, "-Command"),
+        ShellType::Cmd => ("cmd", None, "/C"),
+        ShellType::Unknown(_) => ("sh", Some("-i"), "-c"),
+    };
+    let program = shell_context.shell_path.as_ref().map_or_else(
+        || OsString::from(name),
+        |path| path.clone().into_os_string(),
+    );
+
+    let mut args = Vec::new();
+    if interactive {
+        args.extend(interactive_flag);
+    }
+    args.push(command_flag);
+    (program, args)
+}
+
+/// Drops the warnings an interactive shell prints at startup when it can't take control of
+/// the terminal, such as bash's "no job control in this shell".
+struct SkipJobControlNoise<R> {
+    inner: R,
+    at_start: bool,
+    pending: Vec<u8>,
+}
+
+impl<R> SkipJobControlNoise<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            at_start: true,
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl<R: Read> Read for SkipJobControlNoise<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.at_start {
+            let mut chunk = [0; 8192];
+            let read = self.inner.read(&mut chunk)?;
+            self.pending.extend_from_slice(&chunk[..read]);
+            while let Some(end) = self.pending.iter().position(|&byte| byte == b'\n') {
+                if !is_job_control_noise(&self.pending[..end]) {
+                    break;
+                }
+                self.pending.drain(..=end);
+            }
+            self.at_start = read > 0 && could_be_noise(&self.pending);
+        }
+        if self.pending.is_empty() {
+            return self.inner.read(buf);
+        }
+        let read = buf.len().min(self.pending.len());
+        buf[..read].copy_from_slice(&self.pending[..read]);
+        self.pending.drain(..read);
+        Ok(read)
+    }
+}
+
+/// Whether output so far could still be the start of a warning. Warnings begin with the
+/// shell's name, like `sh: 0: can't access tty`, and some shells write them in pieces.
+fn could_be_noise(pending: &[u8]) -> bool {
+    const SHELLS: [&str; 7] = ["bash", "zsh", "sh", "dash", "ksh", "mksh", "ksh93"];
+    if pending.contains(&b'\n') || pending.len() > 256 {
+        return false;
+    }
+    let text = String::from_utf8_lossy(pending);
+    match text.split_once(':') {
+        Some((name, _)) => SHELLS.contains(&name.rsplit('/').next().unwrap_or_default()),
+        None => text.starts_with('/') || SHELLS.iter().any(|shell| shell.starts_with(&*text)),
+    }
+}
+
+fn is_job_control_noise(line: &[u8]) -> bool {
+    const WARNINGS: [&str; 5] = [
+        "cannot set terminal process group",
+        "no job control in this shell",
+        "can't access tty; job control turned off",
+        "can't set tty pgrp",
+        "won't have full job control",
+    ];
+    let line = String::from_utf8_lossy(line);
+    WARNINGS.iter().any(|warning| line.contains(warning))
+}
+
+/// Reads until the end, keeping the first `MAX_OUTPUT` bytes and copying everything to
+/// `echo`. Copying stops if `echo` is closed, but reading goes on so the command isn't blocked.
+fn read_limited(
+    mut reader: impl Read,
+    mut echo: Option<impl Write>,
+) -> io::Result<(Vec<u8>, bool)> {
+    let mut kept = Vec::new();
+    let mut truncated = false;
+    let mut buffer = [0; 8192];
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => return Ok((kept, truncated)),
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        if let Some(out) = echo.as_mut() {
+            if out
+                .write_all(&buffer[..read])
+                .and_then(|_| out.flush())
+                .is_err()
+            {
+                echo = None;
+            }
+        }
+        let room = MAX_OUTPUT - kept.len();
+        kept.extend_from_slice(&buffer[..read.min(room)]);
+        truncated |= read > room;
+    }
+}
+
+#[cfg(unix)]
+fn signal(status: &ExitStatus) -> Option<i32> {
+    std::os::unix::process::ExitStatusExt::signal(status)
+}
+
+#[cfg(not(unix))]
+fn signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+/// Reports how the command ended. Its output was already shown while it ran.
+pub fn print_result(result: &ExecutionResult) {
+    let outcome = match (result.exit_code, result.signal) {
+        (Some(0), _) => {
+            println!(
+                "{}",
+                console::style("✓ Command completed successfully").green()
+            );
+            return;
+        }
+        (Some(code), _) => format!("exit code {code}"),
+        (None, Some(signal)) => format!("killed by signal {signal}"),
+        (None, None) => "no exit code".to_string(),
+    };
+    println!(
+        "{}",
+        console::style(format!("✗ Command failed ({outcome})")).red()
+    );
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::shell::SupportedOperatingSystem;
+    use std::path::PathBuf;
+
+    fn context(shell_type: ShellType, shell_path: Option<&str>) -> ShellContext {
+        ShellContext {
+            shell_type,
+            os: SupportedOperatingSystem::Linux,
+            shell_path: shell_path.map(PathBuf::from),
+        }
+    }
+
+    fn run_with(command: &str, interactive: bool) -> ExecutionResult {
+        let shell_context = context(ShellType::Unknown("sh".to_string()), None);
+        let (program, mut args) = shell_invocation(&shell_context, interactive);
+        args.push(command);
+        let mut child = Command::new(program);
+        child.args(args);
+        capture(child, false, interactive).unwrap()
+    }
+
+    fn run(command: &str) -> ExecutionResult {
+        run_with(command, false)
+    }
+
+    #[test]
+    fn test_captures_output_and_exit_code() {
+        let result = run("echo out; echo err >&2");
+        assert!(result.success());
+        assert_eq!(result.stdout, "out\n");
+        assert_eq!(result.stderr, "err\n");
+        assert!(!result.truncated);
+
+        let result = run("exit 3");
+        assert!(!result.success());
+        assert_eq!(result.exit_code, Some(3));
+        assert_eq!(result.process_exit_code(), 3);
+    }
+
+    #[test]
+    fn test_reports_signal() {
+        let result = run("kill -9 $$");
+        assert_eq!(result.exit_code, None);
+        assert_eq!(result.signal, Some(9));
+        assert_eq!(result.process_exit_code(), 137);
+    }
+
+    #[test]
+    fn test_truncates_long_output() {
+        let result = run("head -c 2000000 /dev/zero; echo done >&2");
+        assert!(result.truncated);
+        assert_eq!(result.stdout.len(), MAX_OUTPUT);
+        assert_eq!(result.stderr, "done\n");
+    }
+
+    #[test]
+    fn test_uses_detected_shell_path() {
+        let shell_context = context(ShellType::Dash, Some("/usr/local/bin/dash"));
+        assert_eq!(
+            shell_invocation(&shell_context, true),
+            (OsString::from("/usr/local/bin/dash"), vec!["-i", "-c"])
+        );
+        let shell_context = context(ShellType::Nushell, None);
+        assert_eq!(
+            shell_invocation(&shell_context, true),
+            (OsString::from("nu"), vec!["-c"])
+        );
+    }
+
+    #[test]
+    fn test_interactive_shell() {
+        let flags = "case $- in *i*) echo interactive;; *) echo batch;; esac";
+        assert_eq!(run_with(flags, true).stdout, "interactive\n");
+        assert_eq!(run_with(flags, false).stdout, "batch\n");
+        // Without a terminal, sh -i warns about job control; only the command's output is kept
+        let result = run_with("echo warning >&2; printf 'no newline' >&2", true);
+        assert_eq!(result.stderr, "warning\nno newline");
+    }
+
+    #[test]
+    fn test_leaves_stdin_to_the_caller() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        std::fs::write(&input, "yes\n").unwrap();
+        let mut child = Command::new("sh");
+        child
+            .args(["-c", "read answer && echo \"got $answer\""])
+            .stdin(std::fs::File::open(&input).unwrap());
+        assert_eq!(capture(child, false, false).unwrap().stdout, "got yes\n");
+    }
+
+    #[test]
+    fn test_skips_job_control_warnings_written_in_pieces() {
+        let stderr = io::Cursor::new("sh: 0: ")
+            .chain(io::Cursor::new("can't access tty; job control turned off\n"))
+            .chain(io::Cursor::new("bash: no job control in this shell\n"))
+            .chain(io::Cursor::new("Name? "))
+            .chain(io::Cursor::new("bash: no job control in this shell\n"));
+        let mut output = String::new();
+        SkipJobControlNoise::new(stderr)
+            .read_to_string(&mut output)
+            .unwrap();
+        assert_eq!(output, "Name? bash: no job control in this shell\n");
+    }
+}
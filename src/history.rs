@@ -0,0 +1,337 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::get_data_dir;
+
+static HISTORY_FILE: &str = "history.jsonl";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub timestamp: DateTime<Utc>,
+    pub cwd: PathBuf,
+    pub query: String,
+    pub model: String,
+    pub suggestion: String,
+    /// The command that was finally accepted, which differs from `suggestion` when edited or
+    /// when placeholders were filled in
+    pub command: String,
+    pub edited: bool,
+    pub executed: bool,
+    pub exit_code: Option<i32>,
+    pub duration_ms: Option<u64>,
+}
+
+impl HistoryEntry {
+    pub fn new(query: &str, model: &str, suggestion: &str) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            cwd: std::env::current_dir().unwrap_or_default(),
+            query: query.trim().to_string(),
+            model: model.to_string(),
+            suggestion: suggestion.to_string(),
+            command: suggestion.to_string(),
+            edited: false,
+            executed: false,
+            exit_code: None,
+            duration_ms: None,
+        }
+    }
+
+    /// Records a change the user made to the command, as opposed to filling its placeholders.
+    pub fn record_edit(&mut self, command: &str) {
+        if command != self.command {
+            self.edited = true;
+            self.command = command.to_string();
+        }
+    }
+
+    pub fn record_execution(&mut self, exit_code: Option<i32>, duration: Duration) {
+        self.executed = true;
+        self.exit_code = exit_code;
+        self.duration_ms = Some(duration.as_millis() as u64);
+    }
+
+    pub fn status(&self) -> StatusFilter {
+        match (self.executed, self.exit_code) {
+            (false, _) => StatusFilter::NotExecuted,
+            (true, Some(0)) => StatusFilter::Succeeded,
+            (true, _) => StatusFilter::Failed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StatusFilter {
+    Succeeded,
+    Failed,
+    NotExecuted,
+}
+
+#[derive(Debug, Default)]
+pub struct HistoryFilter {
+    pub search: Option<String>,
+    pub since: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+    pub status: Option<StatusFilter>,
+}
+
+impl HistoryFilter {
+    pub fn matches(&self, entry: &HistoryEntry) -> bool {
+        let date = entry.timestamp.with_timezone(&Local).date_naive();
+
+        if self.since.is_some_and(|since| date < since) {
+            return false;
+        }
+        if self.until.is_some_and(|until| date > until) {
+            return false;
+        }
+        if self.status.is_some_and(|status| entry.status() != status) {
+            return false;
+        }
+
+        match &self.search {
+            Some(search) => {
+                let search = search.to_lowercase();
+                [&entry.query, &entry.suggestion, &entry.command]
+                    .iter()
+                    .any(|field| field.to_lowercase().contains(&search))
+            }
+            None => true,
+        }
+    }
+}
+
+/// Append-only log of every suggestion and execution, stored as one JSON object per line.
+pub struct History {
+    path: PathBuf,
+}
+
+impl History {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn open_default() -> Result<Self> {
+        Ok(Self::new(get_data_dir()?.join(HISTORY_FILE)))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append(&self, entry: &HistoryEntry) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let mut file = options
+            .open(&self.path)
+            .with_context(|| format!("Failed to open history file {}", self.path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        Ok(())
+    }
+
+    pub fn entries(&self) -> Result<Vec<HistoryEntry>> {
+        let file = match fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        // Skip lines that fail to parse, including invalid UTF-8, rather than refusing to show
+        // the whole log
+        Ok(BufReader::new(file)
+            .split(b'\n')
+            .map_while(|line| line.ok())
+            .filter_map(|line| serde_json::from_slice(&line).ok())
+            .collect())
+    }
+
+    pub fn search(&self, filter: &HistoryFilter, limit: usize) -> Result<Vec<HistoryEntry>> {
+        let matching: Vec<HistoryEntry> = self
+            .entries()?
+            .into_iter()
+            .filter(|entry| filter.matches(entry))
+            .collect();
+
+        let skip = matching.len().saturating_sub(limit);
+        Ok(matching.into_iter().skip(skip).collect())
+    }
+}
+
+pub fn print_entries(entries: &[HistoryEntry], json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(entries)?);
+        return Ok(());
+    }
+
+    if entries.is_empty() {
+        println!("{}", console::style("No history entries found.").dim());
+        return Ok(());
+    }
+
+    for entry in entries {
+        let status = match entry.status() {
+            StatusFilter::Succeeded => console::style("✓".to_string()).green(),
+            StatusFilter::Failed => {
+                console::style(format!("✗ {}", entry.exit_code.unwrap_or(-1))).red()
+            }
+            StatusFilter::NotExecuted => console::style("-".to_string()).dim(),
+        };
+        let edited = if entry.edited { " (edited)" } else { "" };
+
+        println!(
+            "{}  {}  {}{}",
+            entry
+                .timestamp
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M"),
+            status,
+            console::style(&entry.command).bold(),
+            console::style(edited).dim()
+        );
+        println!(
+            "{}",
+            console::style(format!(
+                "                  {} — {}",
+                entry.query,
+                entry.cwd.display()
+            ))
+            .dim()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(query: &str, command: &str) -> HistoryEntry {
+        HistoryEntry::new(query, "gpt-4o-mini", command)
+    }
+
+    #[test]
+    fn test_append_and_read_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::new(dir.path().join("nested").join(HISTORY_FILE));
+
+        let mut executed = entry("list files", "ls -la");
+        executed.record_execution(Some(0), Duration::from_millis(12));
+        history.append(&entry("disk usage", "du -sh .")).unwrap();
+        history.append(&executed).unwrap();
+
+        let entries = history.entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1], executed);
+        assert_eq!(entries[1].duration_ms, Some(12));
+        assert!(!entries[1].edited);
+    }
+
+    #[test]
+    fn test_unreadable_lines_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::new(dir.path().join(HISTORY_FILE));
+        history.append(&entry("disk usage", "du -sh .")).unwrap();
+        let mut file = OpenOptions::new().append(true).open(history.path()).unwrap();
+        file.write_all(b"{\"query\": \"\xff\xfe\"}\nnot json\n").unwrap();
+        history.append(&entry("list files", "ls -la")).unwrap();
+
+        let queries: Vec<String> = history
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.query)
+            .collect();
+        assert_eq!(queries, ["disk usage", "list files"]);
+    }
+
+    #[test]
+    fn test_missing_history_file_is_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::new(dir.path().join(HISTORY_FILE));
+        assert!(history.entries().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_record_edit() {
+        let mut entry = entry("list files", "ls <dir>");
+        entry.command = "ls src".to_string();
+        entry.record_edit("ls src");
+        assert!(!entry.edited);
+
+        entry.record_edit("ls -lah");
+        entry.record_execution(Some(2), Duration::from_secs(1));
+        assert!(entry.edited);
+        assert_eq!(entry.command, "ls -lah");
+        assert_eq!(entry.status(), StatusFilter::Failed);
+    }
+
+    #[test]
+    fn test_filter_by_search_and_status() {
+        let mut failed = entry("remove build dir", "rm -r build");
+        failed.record_execution(Some(1), Duration::from_millis(3));
+        let not_run = entry("list files", "ls -la");
+
+        let filter = HistoryFilter {
+            search: Some("BUILD".to_string()),
+            ..Default::default()
+        };
+        assert!(filter.matches(&failed));
+        assert!(!filter.matches(&not_run));
+
+        let filter = HistoryFilter {
+            status: Some(StatusFilter::NotExecuted),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&failed));
+        assert!(filter.matches(&not_run));
+    }
+
+    #[test]
+    fn test_filter_by_date() {
+        let mut old = entry("list files", "ls");
+        old.timestamp = "2024-01-15T12:00:00Z".parse().unwrap();
+
+        let filter = HistoryFilter {
+            since: Some(NaiveDate::from_ymd_opt(2024, 2, 1).unwrap()),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&old));
+
+        let filter = HistoryFilter {
+            until: Some(NaiveDate::from_ymd_opt(2024, 2, 1).unwrap()),
+            ..Default::default()
+        };
+        assert!(filter.matches(&old));
+    }
+
+    #[test]
+    fn test_search_keeps_most_recent_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::new(dir.path().join(HISTORY_FILE));
+        for i in 0..5 {
+            history.append(&entry(&format!("query {i}"), "ls")).unwrap();
+        }
+
+        let entries = history.search(&HistoryFilter::default(), 2).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].query, "query 3");
+        assert_eq!(entries[1].query, "query 4");
+    }
+}
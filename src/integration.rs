@@ -0,0 +1,47 @@
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum InitShell {
+    Bash,
+    Zsh,
+    Fish,
+    #[value(name = "powershell", alias = "pwsh")]
+    PowerShell,
+}
+
+pub fn init_script(shell: InitShell) -> &'static str {
+    match shell {
+        InitShell::Bash => include_str!("integration/clm.bash"),
+        InitShell::Zsh => include_str!("integration/clm.zsh"),
+        InitShell::Fish => include_str!("integration/clm.fish"),
+        InitShell::PowerShell => include_str!("integration/clm.ps1"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_init_scripts_use_print_mode() {
+        for shell in InitShell::value_variants() {
+            let script = init_script(*shell);
+            assert!(script.contains("clm --print --"), "{shell:?} script");
+        }
+    }
+
+    #[test]
+    fn test_init_scripts_hook_line_editor() {
+        assert!(init_script(InitShell::Bash).contains("bind -m"));
+        assert!(init_script(InitShell::Zsh).contains("zle -N accept-line"));
+        assert!(init_script(InitShell::Fish).contains("commandline -r"));
+        assert!(init_script(InitShell::PowerShell).contains("PSConsoleReadLine"));
+    }
+
+    #[test]
+    fn test_init_scripts_bind_widget_key() {
+        assert!(init_script(InitShell::Bash).contains(r#"-x '"\C-g": __clm_widget'"#));
+        assert!(init_script(InitShell::Zsh).contains("bindkey -M emacs '^G' __clm_widget"));
+        assert!(init_script(InitShell::Fish).contains(r"bind \cg __clm_widget"));
+    }
+}
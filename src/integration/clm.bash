@@ -0,0 +1,51 @@
+# CommandLM shell integration for bash
+# Add this to ~/.bashrc:  eval "$(clm init bash)"
+#
+# Typing `clm "query"` and pressing Enter replaces the line with the suggested
+# command instead of running it, so it executes in this shell and lands in history.
+# Ctrl-G does the same for whatever natural-language text is typed at the prompt.
+
+__clm_keymaps=(emacs-standard vi-insert)
+
+# Enter runs __clm_accept_line and then whatever \C-x\C-d is bound to, which
+# the function switches between accepting the line and just redrawing it.
+__clm_bind_followup() {
+    local keymap
+    for keymap in "${__clm_keymaps[@]}"; do
+        bind -m "$keymap" "\"\\C-x\\C-d\": $1"
+    done
+}
+
+__clm_accept_line() {
+    local pattern='^[[:space:]]*clm[[:space:]]+["'\''](.*)["'\''][[:space:]]*$'
+    if [[ $READLINE_LINE =~ $pattern ]]; then
+        local suggestion
+        suggestion=$(command clm --print -- "${BASH_REMATCH[1]}" 2>/dev/null)
+        if [[ -n $suggestion ]]; then
+            READLINE_LINE=$suggestion
+            READLINE_POINT=${#READLINE_LINE}
+            __clm_bind_followup redraw-current-line
+            return
+        fi
+    fi
+    __clm_bind_followup accept-line
+}
+
+__clm_widget() {
+    [[ -z ${READLINE_LINE//[[:space:]]/} ]] && return
+    local suggestion
+    suggestion=$(command clm --print -- "$READLINE_LINE" 2>/dev/null)
+    if [[ -n $suggestion ]]; then
+        READLINE_LINE=$suggestion
+        READLINE_POINT=${#READLINE_LINE}
+    fi
+}
+
+for __clm_keymap in "${__clm_keymaps[@]}"; do
+    bind -m "$__clm_keymap" -x '"\C-g": __clm_widget'
+    bind -m "$__clm_keymap" -x '"\C-x\C-c": __clm_accept_line'
+    bind -m "$__clm_keymap" '"\C-m": "\C-x\C-c\C-x\C-d"'
+    bind -m "$__clm_keymap" '"\C-j": "\C-x\C-c\C-x\C-d"'
+done
+unset __clm_keymap
+__clm_bind_followup accept-line
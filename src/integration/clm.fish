@@ -0,0 +1,36 @@
+# CommandLM shell integration for fish
+# Add this to ~/.config/fish/config.fish:  clm init fish | source
+#
+# Typing `clm "query"` and pressing Enter replaces the line with the suggested
+# command instead of running it, so it executes in this shell and lands in history.
+# Ctrl-G does the same for whatever natural-language text is typed at the prompt.
+
+function __clm_accept_line
+    set -l query (string match -r -g '^\s*clm\s+["\'](.*)["\']\s*$' -- (commandline))
+    if test -n "$query"
+        set -l suggestion (command clm --print -- $query 2>/dev/null)
+        if test -n "$suggestion"
+            commandline -r -- (string join \n -- $suggestion)
+            commandline -f repaint
+            return
+        end
+    end
+    commandline -f execute
+end
+
+function __clm_widget
+    set -l line (string join \n -- (commandline))
+    if string match -qr '^\s*$' -- "$line"
+        return
+    end
+    set -l suggestion (command clm --print -- "$line" 2>/dev/null)
+    if test -n "$suggestion"
+        commandline -r -- (string join \n -- $suggestion)
+    end
+    commandline -f repaint
+end
+
+bind \r __clm_accept_line
+bind -M insert \r __clm_accept_line
+bind \cg __clm_widget
+bind -M insert \cg __clm_widget
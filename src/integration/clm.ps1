@@ -0,0 +1,21 @@
+# CommandLM shell integration for PowerShell
+# Add this to your $PROFILE:  Invoke-Expression (& clm init powershell | Out-String)
+#
+# Typing `clm "query"` and pressing Enter replaces the line with the suggested
+# command instead of running it, so it executes in this shell and lands in history.
+
+Set-PSReadLineKeyHandler -Key Enter -BriefDescription ClmAcceptLine -ScriptBlock {
+    $line = $null
+    $cursor = $null
+    [Microsoft.PowerShell.PSConsoleReadLine]::GetBufferState([ref]$line, [ref]$cursor)
+
+    if ($line -match '^\s*clm\s+["''](.*)["'']\s*$') {
+        $suggestion = (& clm --print -- $Matches[1] 2>$null) -join "`n"
+        if ($suggestion) {
+            [Microsoft.PowerShell.PSConsoleReadLine]::Replace(0, $line.Length, $suggestion)
+            return
+        }
+    }
+
+    [Microsoft.PowerShell.PSConsoleReadLine]::AcceptLine()
+}
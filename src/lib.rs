@@ -1,4 +1,21 @@
 pub mod assistant;
+pub mod cache;
 pub mod cli;
+pub mod command_line;
 pub mod config;
-pub mod shell;
\ No newline at end of file
+pub mod credentials;
+pub mod error;
+pub mod executor;
+pub mod history;
+pub mod integration;
+pub mod provider;
+pub mod placeholder;
+pub mod preview;
+pub mod prompt;
+pub mod retry;
+pub mod safety;
+pub mod sandbox;
+pub mod shell;
+pub mod snapshot;
+pub mod usage;
+pub mod validation;
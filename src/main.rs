@@ -1,45 +1,132 @@
 use atty::Stream;
-use clap::{CommandFactory, Parser};
-use dialoguer::Confirm;
+use clap::{CommandFactory, Parser, ValueEnum};
 use indicatif::{ProgressBar, ProgressStyle};
-use commandlm::assistant::{create_client, get_command_suggestion, interactive_chat};
-use commandlm::cli::{Cli, Commands};
+use commandlm::assistant::{
+    create_client, get_command_suggestion, interactive_chat, revise_command, show_retries_on,
+    Suggestion,
+};
+use commandlm::cache::{print_stats, CacheKey, CacheMode, ResponseCache};
+use commandlm::cli::{
+    write_completions, write_man_page, AuthCommands, CacheCommands, Cli, Commands, HistoryArgs,
+    ModelCommands, UndoArgs,
+};
+use commandlm::config::{Config, ProviderConfig, ProviderKind};
+use commandlm::credentials;
+use commandlm::error;
+use commandlm::executor::{execute, print_result, ExecutionResult};
+use commandlm::history::{print_entries, History, HistoryEntry, HistoryFilter};
+use commandlm::integration::init_script;
+use commandlm::placeholder::{fill, find_placeholders};
+use commandlm::preview::{preview, print_preview};
+use commandlm::prompt::{self, Prompter};
+use commandlm::provider::{print_models, Backend, Provider};
+use commandlm::safety::risk_reason;
+use commandlm::sandbox::{print_changes, Sandbox};
 use commandlm::shell::ShellContext;
+use commandlm::snapshot::{print_manifest, print_saved, Snapshots};
+use commandlm::usage::{print_usage, Period, UsageLog, UsageTracker};
+use commandlm::validation::{print_issues, validate};
 use std::io::{self, Read};
-use std::process::Command;
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() {
+    match run().await {
+        Ok(0) => {}
+        Ok(code) => std::process::exit(code),
+        Err(err) => std::process::exit(error::report(&err)),
+    }
+}
+
+/// Returns the code to exit with, which is the executed command's when it failed.
+async fn run() -> anyhow::Result<i32> {
     let cli = Cli::parse();
-    let shell_context = ShellContext::default();
-    let client = create_client(&shell_context)?;
 
+    // Loaded only where needed, so `init`, `completions` and `man` work with a broken config
     match cli.command {
         Some(Commands::Chat) => {
-            interactive_chat(&client, &shell_context).await?;
+            let config = Config::load()?;
+            let shell_context = ShellContext::default();
+            let client = create_client(&shell_context, &config)?;
+            let usage = UsageTracker::new(&config.usage, UsageLog::open_default()?, cli.show_usage);
+            interactive_chat(client, &shell_context, &usage, &*prompt::from_env()?).await?;
+        }
+        Some(Commands::Init { shell }) => {
+            print!("{}", init_script(shell));
+        }
+        Some(Commands::Completions { shell }) => {
+            write_completions(shell, &mut io::stdout());
+        }
+        Some(Commands::Man) => {
+            write_man_page(&mut io::stdout())?;
+        }
+        Some(Commands::History(args)) => {
+            show_history(args)?;
+        }
+        Some(Commands::Models { command }) => {
+            let config = Config::load()?;
+            let shell_context = ShellContext::default();
+            let provider = create_client(&shell_context, &config)?;
+            match command {
+                None => print_models(&provider.list_models().await?),
+                Some(ModelCommands::Pull { name }) => pull_model(&provider, &name).await?,
+            }
+        }
+        Some(Commands::Cache { command }) => {
+            let config = Config::load()?;
+            let cache = ResponseCache::open_default(&config.cache)?;
+            match command {
+                CacheCommands::Stats => print_stats(&cache.stats()?),
+                CacheCommands::Clear => {
+                    cache.clear()?;
+                    println!("{}", console::style("Response cache cleared.").green());
+                }
+            }
+        }
+        Some(Commands::Auth { command }) => {
+            auth(command, &Config::load()?)?;
+        }
+        Some(Commands::Undo(args)) => {
+            undo(&args, &Config::load()?, &*prompt::from_env()?)?;
+        }
+        Some(Commands::Usage(args)) => {
+            let config = Config::load()?;
+            let period = if args.monthly { Period::Month } else { Period::Day };
+            let entries = UsageLog::open_default()?.entries()?;
+            print_usage(&entries, period, &config.usage, args.json)?;
         }
         None => {
+            let config = Config::load()?;
             // Read from stdin if there's piped input
             let piped_input = if !atty::is(Stream::Stdin) {
                 let mut buffer = String::new();
                 io::stdin().read_to_string(&mut buffer)?;
-                Some(buffer)
+                Some(buffer).filter(|input| !input.trim().is_empty())
             } else {
                 None
             };
 
+            let options = QueryOptions {
+                print_only: cli.print,
+                dry_run: cli.dry_run,
+                sandbox: cli.sandbox,
+                allow_network: cli.allow_network,
+                cache_mode: CacheMode::from_flags(&config.cache, cli.no_cache, cli.refresh),
+                usage: UsageTracker::new(&config.usage, UsageLog::open_default()?, cli.show_usage),
+                prompter: prompt::from_env()?,
+            };
+
             match (piped_input, &cli.query) {
                 (Some(input), Some(query)) => {
                     // Both piped input and query argument provided
-                    process_query(query, Some(&input)).await?;
+                    return process_query(query, Some(&input), &config, &options).await;
                 }
                 (Some(input), None) => {
                     // Only piped input, use it as the query
-                    process_query(&input, None).await?;
+                    return process_query(&input, None, &config, &options).await;
                 }
                 (None, Some(query)) => {
                     // Only query argument
-                    process_query(query, None).await?;
+                    return process_query(query, None, &config, &options).await;
                 }
                 (None, None) => {
                     // No input at all, show help
@@ -49,41 +136,162 @@ async fn main() -> anyhow::Result<()> {
         }
     }
 
-    Ok(())
+    Ok(0)
 }
 
-async fn process_query(query: &str, context: Option<&str>) -> anyhow::Result<()> {
-    let shell_context = ShellContext::default();
-    let client = create_client(&shell_context)?;
+struct QueryOptions {
+    print_only: bool,
+    dry_run: bool,
+    sandbox: bool,
+    allow_network: bool,
+    cache_mode: CacheMode,
+    usage: UsageTracker,
+    prompter: Box<dyn Prompter>,
+}
 
-    let spinner = ProgressBar::new_spinner();
-    spinner.set_style(
-        ProgressStyle::default_spinner()
-            .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈")
-            .template("{spinner} Thinking...")?,
-    );
-    spinner.enable_steady_tick(std::time::Duration::from_millis(80));
+async fn process_query(
+    query: &str,
+    context: Option<&str>,
+    config: &Config,
+    options: &QueryOptions,
+) -> anyhow::Result<i32> {
+    let shell_context = ShellContext::default();
 
-    let (explanation, command) = get_command_suggestion(&client, &shell_context, query, context).await?;
+    if options.print_only {
+        // Used by the shell integration, so only the command itself goes to stdout
+        let suggestion =
+            suggest_command(&shell_context, query, context, config, options, None).await?;
+        options.usage.record(&suggestion.model, suggestion.usage);
+        if suggestion.command.is_empty() {
+            anyhow::bail!("No command suggestion available");
+        }
+        record_history(&HistoryEntry::new(query, &suggestion.model, &suggestion.command));
+        println!("{}", suggestion.command);
+        return Ok(0);
+    }
 
+    let spinner = thinking_spinner("Thinking...")?;
+    let suggestion = suggest_command(
+        &shell_context,
+        query,
+        context,
+        config,
+        options,
+        Some(&spinner),
+    )
+    .await;
     spinner.finish_and_clear();
+    let Suggestion {
+        explanation,
+        command,
+        model,
+        usage,
+    } = suggestion?;
+    options.usage.record(&model, usage);
+
+    if model != config.provider.model() {
+        println!(
+            "{}",
+            console::style(format!("Answered by fallback model {model}")).dim()
+        );
+    }
 
     if !command.is_empty() {
+        let mut history_entry = HistoryEntry::new(query, &model, &command);
         println!("\n{}", console::style(&explanation).blue());
-        println!("\n{}", console::style("Command:").green());
-        println!("{}\n", console::style(&command).white().bold());
 
-        let confirmed = Confirm::new()
-            .with_prompt("Execute this command?")
-            .default(false)
-            .interact()?;
+        let prompter = &*options.prompter;
+        let mut command = fill_placeholders(command, &shell_context, prompter)?;
+        let mut attempts = 0;
+        if config.validation.enabled && config.validation.repair {
+            command = repair_if_invalid(query, command, &shell_context, config, options).await?;
+        }
+        // Filled placeholders and repairs aren't edits, so they count as the starting point
+        history_entry.command = command.clone();
+        loop {
+            println!("\n{}", console::style("Command:").green());
+            println!("{}\n", console::style(&command).white().bold());
+            let risk = risk_reason(&command);
+            if let Some(reason) = risk {
+                println!(
+                    "{}\n",
+                    console::style(format!("Warning: this command {reason}.")).red().bold()
+                );
+            }
+            let cwd = std::env::current_dir()?;
+            print_preview(&preview(&command, &shell_context.shell_type, &cwd), &cwd);
+            if config.validation.enabled {
+                print_issues(&validate(&command, &shell_context, &cwd));
+            }
+
+            if options.dry_run {
+                println!("{}", console::style("Dry run: command not executed.").dim());
+                record_history(&history_entry);
+                return Ok(0);
+            }
 
-        if confirmed {
-            println!("{}", console::style("Executing...").yellow());
-            execute_command(&command)?;
-        } else {
-            println!("{}", console::style("Command not executed.").dim());
+            match prompter.select("Execute this command?", &["Execute", "Edit", "Cancel"], 2)? {
+                0 => {
+                    if options.sandbox {
+                        if !try_in_sandbox(&command, &shell_context, config, options)? {
+                            break;
+                        }
+                    } else if risk.is_some() && !prompter.confirm("Are you sure?", false)? {
+                        break;
+                    }
+                    if config.snapshots.enabled {
+                        take_snapshot(&command, &shell_context, config);
+                    }
+                    println!("{}", console::style("Executing...").yellow());
+                    let interactive = config.execution.interactive;
+                    let result = execute(&command, &shell_context, interactive, None)?;
+                    print_result(&result);
+                    history_entry.record_execution(result.exit_code, result.duration);
+                    record_history(&history_entry);
+
+                    if result.success() {
+                        return Ok(0);
+                    }
+                    if config.correction.enabled && attempts < config.correction.max_attempts {
+                        attempts += 1;
+                        let problem = describe_failure(&result);
+                        let revised = request_revision(
+                            query,
+                            &command,
+                            &problem,
+                            &shell_context,
+                            config,
+                            options,
+                        )
+                        .await?;
+                        if let Some(revised) = revised {
+                            println!(
+                                "{}",
+                                console::style(format!(
+                                    "Suggested fix {attempts}/{}",
+                                    config.correction.max_attempts
+                                ))
+                                .dim()
+                            );
+                            history_entry =
+                                HistoryEntry::new(query, &revised.model, &revised.command);
+                            command = fill_placeholders(revised.command, &shell_context, prompter)?;
+                            history_entry.command = command.clone();
+                            continue;
+                        }
+                    }
+                    return Ok(result.process_exit_code());
+                }
+                1 => {
+                    command = prompter.input("Command", &command)?;
+                    history_entry.record_edit(&command);
+                }
+                _ => break,
+            }
         }
+
+        println!("{}", console::style("Command not executed.").dim());
+        record_history(&history_entry);
     } else {
         println!(
             "\n{}",
@@ -91,39 +299,321 @@ async fn process_query(query: &str, context: Option<&str>) -> anyhow::Result<()>
         );
     }
 
-    Ok(())
+    Ok(0)
 }
 
-fn execute_command(command: &str) -> anyhow::Result<()> {
-    let shell_context = ShellContext::default();
-    
-    let (shell_cmd, shell_args) = match shell_context.shell_type {
-        commandlm::shell::ShellType::Bash => ("bash", vec!["-c"]),
-        commandlm::shell::ShellType::Zsh => ("zsh", vec!["-c"]),
-        commandlm::shell::ShellType::Fish => ("fish", vec!["-c"]),
-        commandlm::shell::ShellType::PowerShell => ("powershell", vec!["-Command"]),
-        commandlm::shell::ShellType::Cmd => ("cmd", vec!["/C"]),
-        commandlm::shell::ShellType::Unknown(_) => ("sh", vec!["-c"]),
+async fn suggest_command(
+    shell_context: &ShellContext,
+    query: &str,
+    context: Option<&str>,
+    config: &Config,
+    options: &QueryOptions,
+    spinner: Option<&ProgressBar>,
+) -> anyhow::Result<Suggestion> {
+    let cache_mode = options.cache_mode;
+    let model = config.provider.model();
+    let key = CacheKey::new(query, shell_context, model, context);
+    let cache = match cache_mode {
+        CacheMode::Disabled => None,
+        _ => ResponseCache::open_default(&config.cache).ok(),
     };
 
-    let mut cmd_args = shell_args;
-    cmd_args.push(command);
+    if let (Some(cache), CacheMode::Use) = (&cache, cache_mode) {
+        if let Ok(Some((explanation, command))) = cache.get(&key) {
+            return Ok(Suggestion {
+                explanation,
+                command,
+                model: model.to_string(),
+                usage: None,
+            });
+        }
+    }
 
-    let output = Command::new(shell_cmd)
-        .args(&cmd_args)
-        .output()?;
+    options.usage.check_budget()?;
+    let mut client = create_client(shell_context, config)?;
+    if let Some(spinner) = spinner {
+        client = show_retries_on(spinner, client);
+    }
+    let suggestion = get_command_suggestion(&client, shell_context, query, context).await?;
 
-    if output.status.success() {
-        print!("{}", String::from_utf8_lossy(&output.stdout));
-        if !output.stderr.is_empty() {
-            eprint!("{}", String::from_utf8_lossy(&output.stderr));
+    if let Some(cache) = &cache {
+        // A fallback's answer is not cached under the primary model's key
+        if !suggestion.command.is_empty() && suggestion.model == model {
+            if let Err(err) = cache.put(&key, &suggestion.explanation, &suggestion.command) {
+                eprintln!(
+                    "{}",
+                    console::style(format!("Warning: failed to update response cache: {err}")).yellow()
+                );
+            }
         }
-        println!("{}", console::style("✓ Command completed successfully").green());
-    } else {
-        eprint!("{}", String::from_utf8_lossy(&output.stderr));
-        println!("{}", console::style("✗ Command failed").red());
-        std::process::exit(output.status.code().unwrap_or(1));
     }
 
+    Ok(suggestion)
+}
+
+fn thinking_spinner(message: &'static str) -> anyhow::Result<ProgressBar> {
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_style(
+        ProgressStyle::default_spinner()
+            .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈")
+            .template("{spinner} {msg}")?,
+    );
+    spinner.set_message(message);
+    spinner.enable_steady_tick(std::time::Duration::from_millis(80));
+    Ok(spinner)
+}
+
+/// Asks the model once for a corrected command when validation finds problems, keeping the
+/// original command if it can't offer one.
+async fn repair_if_invalid(
+    query: &str,
+    command: String,
+    shell_context: &ShellContext,
+    config: &Config,
+    options: &QueryOptions,
+) -> anyhow::Result<String> {
+    let issues = validate(&command, shell_context, &std::env::current_dir()?);
+    if issues.is_empty() {
+        return Ok(command);
+    }
+    println!("\n{}", console::style(&command).white().bold());
+    print_issues(&issues);
+
+    let problems: Vec<String> = issues.into_iter().map(|issue| issue.message).collect();
+    let problem = format!("has these problems:\n- {}", problems.join("\n- "));
+    let revised = request_revision(query, &command, &problem, shell_context, config, options).await?;
+    match revised {
+        Some(revised) => fill_placeholders(revised.command, shell_context, &*options.prompter),
+        None => Ok(command),
+    }
+}
+
+/// Asks the model for a corrected command and prints its explanation. None when it suggests
+/// nothing new.
+async fn request_revision(
+    query: &str,
+    command: &str,
+    problem: &str,
+    shell_context: &ShellContext,
+    config: &Config,
+    options: &QueryOptions,
+) -> anyhow::Result<Option<Suggestion>> {
+    options.usage.check_budget()?;
+    let spinner = thinking_spinner("Asking for a corrected command...")?;
+    let client = show_retries_on(&spinner, create_client(shell_context, config)?);
+    let revised = revise_command(&client, shell_context, query, command, problem).await;
+    spinner.finish_and_clear();
+    let revised = revised?;
+    options.usage.record(&revised.model, revised.usage);
+
+    if revised.command.is_empty() || revised.command == command {
+        return Ok(None);
+    }
+    println!("\n{}", console::style(&revised.explanation).blue());
+    Ok(Some(revised))
+}
+
+/// Describes a failed run for the model, keeping only the end of long error output.
+fn describe_failure(result: &ExecutionResult) -> String {
+    const MAX_STDERR: usize = 2000;
+    let stderr = result.stderr.trim();
+    let start = stderr
+        .char_indices()
+        .map(|(index, _)| index)
+        .find(|index| stderr.len() - index <= MAX_STDERR)
+        .unwrap_or(stderr.len());
+    let exit = match (result.exit_code, result.signal) {
+        (Some(code), _) => format!("exited with code {code}"),
+        (None, Some(signal)) => format!("was killed by signal {signal}"),
+        (None, None) => "failed".to_string(),
+    };
+    format!("{exit} and printed this error output:\n{}", &stderr[start..])
+}
+
+/// Asks for a value for each placeholder the model left in the command, such as `<branch>`,
+/// starting from a value suggested by the environment where there is one.
+fn fill_placeholders(
+    mut command: String,
+    shell_context: &ShellContext,
+    prompter: &dyn Prompter,
+) -> anyhow::Result<String> {
+    let placeholders = find_placeholders(&command);
+    if placeholders.is_empty() {
+        return Ok(command);
+    }
+
+    let cwd = std::env::current_dir()?;
+    println!(
+        "\n{}",
+        console::style("The command has placeholders to fill in:").yellow()
+    );
+    for placeholder in placeholders {
+        let suggested = placeholder.suggested_value(&cwd).unwrap_or_default();
+        let value = prompter.input(&placeholder.label, &suggested)?;
+        if !value.trim().is_empty() {
+            command = fill(&command, &placeholder, value.trim(), &shell_context.shell_type);
+        }
+    }
+    Ok(command)
+}
+
+/// Saves the files the command is about to change for `clm undo`. A failure only warns, since
+/// the user already chose to run the command.
+fn take_snapshot(command: &str, shell_context: &ShellContext, config: &Config) {
+    let snapshot = std::env::current_dir().map_err(anyhow::Error::from).and_then(|cwd| {
+        let preview = preview(command, &shell_context.shell_type, &cwd);
+        Snapshots::open_default(&config.snapshots)?.take(command, &cwd, &preview)
+    });
+    match snapshot {
+        Ok(Some(manifest)) => print_saved(&manifest),
+        Ok(None) => {}
+        Err(err) => eprintln!(
+            "{}",
+            console::style(format!("Warning: no snapshot was taken: {err:#}")).yellow()
+        ),
+    }
+}
+
+fn undo(args: &UndoArgs, config: &Config, prompter: &dyn Prompter) -> anyhow::Result<()> {
+    let snapshots = Snapshots::open_default(&config.snapshots)?;
+    let mut manifests = snapshots.list()?;
+    if args.list {
+        if manifests.is_empty() {
+            println!("{}", console::style("No snapshots found.").dim());
+        }
+        manifests.iter().rev().for_each(print_manifest);
+        return Ok(());
+    }
+
+    let Some(manifest) = manifests.pop() else {
+        println!("{}", console::style("Nothing to undo.").dim());
+        return Ok(());
+    };
+    print_manifest(&manifest);
+    if prompter.confirm("Undo this command?", false)? {
+        snapshots.restore(&manifest)?;
+        println!("{}", console::style("Restored the snapshot.").green());
+    }
     Ok(())
 }
+
+/// Runs the command in a sandbox and shows what it changed. Returns whether the user wants to
+/// run it for real.
+fn try_in_sandbox(
+    command: &str,
+    shell_context: &ShellContext,
+    config: &Config,
+    options: &QueryOptions,
+) -> anyhow::Result<bool> {
+    let sandbox = Sandbox::new(&std::env::current_dir()?, options.allow_network)?;
+    println!("{}", console::style("Executing in a sandbox...").yellow());
+    let interactive = config.execution.interactive;
+    print_result(&execute(command, shell_context, interactive, Some(&sandbox))?);
+    print_changes(&sandbox, &sandbox.changes()?);
+    options.prompter.confirm("Run this command for real?", false)
+}
+
+async fn pull_model(provider: &Provider, name: &str) -> anyhow::Result<()> {
+    let Backend::Ollama(ollama) = provider.backend() else {
+        anyhow::bail!("Pulling models is only supported with the Ollama provider");
+    };
+
+    let progress = ProgressBar::new(0);
+    progress.set_style(
+        ProgressStyle::default_bar()
+            .template("{msg} [{bar:30}] {bytes}/{total_bytes}")?
+            .progress_chars("=> "),
+    );
+    ollama
+        .pull_model(name, |update| {
+            progress.set_message(update.status.clone());
+            if let (Some(total), Some(completed)) = (update.total, update.completed) {
+                progress.set_length(total);
+                progress.set_position(completed);
+            }
+        })
+        .await?;
+    progress.finish_and_clear();
+
+    println!("{}", console::style(format!("✓ Pulled {name}")).green());
+    Ok(())
+}
+
+fn auth(command: AuthCommands, config: &Config) -> anyhow::Result<()> {
+    match command {
+        AuthCommands::Login { provider, file } => {
+            let provider = provider.unwrap_or(config.provider.kind);
+            let key = if atty::is(Stream::Stdin) {
+                dialoguer::Password::new()
+                    .with_prompt(format!("{} API key", provider))
+                    .interact()?
+            } else {
+                let mut buffer = String::new();
+                io::stdin().read_to_string(&mut buffer)?;
+                buffer.trim().to_string()
+            };
+            if key.is_empty() {
+                anyhow::bail!("No API key provided");
+            }
+
+            let source = credentials::store(provider, &key, file)?;
+            println!(
+                "{}",
+                console::style(format!("Stored {} API key in the {}.", provider, source)).green()
+            );
+        }
+        AuthCommands::Logout { provider } => {
+            let provider = provider.unwrap_or(config.provider.kind);
+            let removed = credentials::remove(provider)?;
+            if removed.is_empty() {
+                println!("{}", console::style(format!("No stored {} API key.", provider)).dim());
+            }
+            for source in removed {
+                println!(
+                    "{}",
+                    console::style(format!("Removed {} API key from the {}.", provider, source))
+                        .green()
+                );
+            }
+        }
+        AuthCommands::Status => {
+            for kind in ProviderKind::value_variants() {
+                let provider = if *kind == config.provider.kind {
+                    config.provider.clone()
+                } else {
+                    ProviderConfig {
+                        kind: *kind,
+                        ..Default::default()
+                    }
+                };
+                let source = match credentials::source(&provider)? {
+                    Some(source) => console::style(source.to_string()).green(),
+                    None => console::style("not set".to_string()).dim(),
+                };
+                println!("{:<14} {}", kind.id(), source);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn record_history(entry: &HistoryEntry) {
+    let result = History::open_default().and_then(|history| history.append(entry));
+    if let Err(err) = result {
+        eprintln!(
+            "{}",
+            console::style(format!("Warning: failed to write history: {err}")).yellow()
+        );
+    }
+}
+
+fn show_history(args: HistoryArgs) -> anyhow::Result<()> {
+    let filter = HistoryFilter {
+        search: args.search,
+        since: args.since,
+        until: args.until,
+        status: args.status,
+    };
+    let entries = History::open_default()?.search(&filter, args.limit)?;
+    print_entries(&entries, args.json)
+}
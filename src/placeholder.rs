@@ -0,0 +1,353 @@
+use std::path::Path;
+use std::process::Command;
+
+use crate::shell::ShellType;
+
+/// A part of a suggested command that the model left for the user to fill in, such as
+/// `<branch>`, `YOUR_API_KEY` or `path/to/file`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placeholder {
+    /// The placeholder as it appears in the command
+    pub text: String,
+    /// What to ask the user for
+    pub label: String,
+}
+
+impl Placeholder {
+    fn new(text: &str) -> Self {
+        let label = if text.contains('/') || text.contains('@') {
+            text.to_string()
+        } else {
+            let name = text.trim_start_matches('<').trim_end_matches('>');
+            let name = name
+                .strip_prefix("YOUR_")
+                .or_else(|| name.strip_prefix("your_"))
+                .unwrap_or(name);
+            name.replace(['_', '-'], " ").to_lowercase()
+        };
+        Self {
+            text: text.to_string(),
+            label,
+        }
+    }
+
+    /// A likely value worked out from the environment, like the current git branch.
+    pub fn suggested_value(&self, cwd: &Path) -> Option<String> {
+        let label = self.label.as_str();
+        if label.contains("branch") {
+            git(cwd, &["symbolic-ref", "--short", "HEAD"])
+        } else if label.contains("remote") {
+            git(cwd, &["remote"]).and_then(|remotes| remotes.lines().next().map(str::to_string))
+        } else if label.contains("email") {
+            git(cwd, &["config", "user.email"])
+        } else if label == "user" || label == "username" || label == "user name" {
+            std::env::var("USER")
+                .or_else(|_| std::env::var("USERNAME"))
+                .ok()
+        } else {
+            None
+        }
+    }
+}
+
+fn git(cwd: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(cwd)
+        .output()
+        .ok()?;
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !value.is_empty()).then_some(value)
+}
+
+/// Finds the placeholders in a command, each once, in the order they appear.
+pub fn find_placeholders(command: &str) -> Vec<Placeholder> {
+    let mut found: Vec<(usize, &str)> = Vec::new();
+    found.extend(angle_brackets(command));
+    found.extend(words(command).filter(|(_, word)| is_placeholder_word(word)));
+    found.sort_by_key(|(start, _)| *start);
+
+    let mut placeholders: Vec<Placeholder> = Vec::new();
+    for (_, text) in found {
+        if !placeholders
+            .iter()
+            .any(|placeholder| placeholder.text == text)
+        {
+            placeholders.push(Placeholder::new(text));
+        }
+    }
+    placeholders
+}
+
+/// `<name>` tokens. The name must start with a letter and not end in a space, which tells
+/// them apart from redirects like `sort <in.txt >out.txt` and heredocs.
+fn angle_brackets(command: &str) -> impl Iterator<Item = (usize, &str)> {
+    command.match_indices('<').filter_map(move |(start, _)| {
+        let rest = &command[start + 1..];
+        let end = rest.find(['<', '>', '\n'])?;
+        let name = &rest[..end];
+        let valid = rest[end..].starts_with('>')
+            && name.len() <= 40
+            && name.starts_with(|c: char| c.is_ascii_alphabetic())
+            && !name.ends_with(' ')
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ' ' | ':' | '/'));
+        valid.then(|| (start, &command[start..start + end + 2]))
+    })
+}
+
+/// Runs of characters that can appear in a path or identifier, with their positions.
+fn words(command: &str) -> impl Iterator<Item = (usize, &str)> {
+    let is_word_char =
+        |c: char| c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | '/' | '@' | '~');
+    let mut start = None;
+    let mut words = Vec::new();
+    for (index, c) in command.char_indices().chain([(command.len(), ' ')]) {
+        match (start, is_word_char(c)) {
+            (None, true) => start = Some(index),
+            (Some(begin), false) => {
+                words.push((begin, command[begin..index].trim_end_matches(['.', ':'])));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    words.into_iter()
+}
+
+fn is_placeholder_word(word: &str) -> bool {
+    let upper = word.to_uppercase();
+    let your = upper.strip_prefix("YOUR_").is_some_and(|rest| {
+        !rest.is_empty() && rest.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    });
+    let path = ["path/to/", "/path/to/", "~/path/to/"]
+        .iter()
+        .any(|prefix| word.starts_with(prefix));
+    let remote = word.split_once('@').is_some_and(|(user, host)| {
+        matches!(user, "user" | "username") && matches!(host, "host" | "hostname" | "server")
+    });
+    your || path || remote
+}
+
+/// Replaces every occurrence of the placeholder with the value, quoted for the shell so it
+/// stays a single word and its metacharacters are taken literally.
+pub fn fill(
+    command: &str,
+    placeholder: &Placeholder,
+    value: &str,
+    shell_type: &ShellType,
+) -> String {
+    let mut filled = String::with_capacity(command.len());
+    let mut end = 0;
+    for (start, text) in command.match_indices(&placeholder.text) {
+        filled.push_str(&command[end..start]);
+        filled.push_str(&quote(value, quoting_at(command, start, shell_type), shell_type));
+        end = start + text.len();
+    }
+    filled.push_str(&command[end..]);
+    filled
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Quoting {
+    None,
+    Single,
+    Double,
+}
+
+/// The quotes that are open at `position` in the command.
+fn quoting_at(command: &str, position: usize, shell_type: &ShellType) -> Quoting {
+    let escape = match shell_type {
+        ShellType::PowerShell => Some('`'),
+        ShellType::Cmd => None,
+        _ => Some('\\'),
+    };
+    let mut quoting = Quoting::None;
+    let mut chars = command[..position].chars();
+    while let Some(c) = chars.next() {
+        quoting = match (quoting, c) {
+            (Quoting::Single, '\'') => Quoting::None,
+            (Quoting::Single, _) => Quoting::Single,
+            (_, c) if Some(c) == escape => {
+                chars.next();
+                quoting
+            }
+            (Quoting::None, '\'') if !matches!(shell_type, ShellType::Cmd) => Quoting::Single,
+            (Quoting::None, '"') => Quoting::Double,
+            (Quoting::Double, '"') => Quoting::None,
+            _ => quoting,
+        };
+    }
+    quoting
+}
+
+fn quote(value: &str, quoting: Quoting, shell_type: &ShellType) -> String {
+    let is_plain = |c: char| {
+        c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '/' | ':' | '@' | '+' | '=' | '~')
+    };
+    match quoting {
+        Quoting::None if !value.is_empty() && value.chars().all(is_plain) => value.to_string(),
+        Quoting::None => match shell_type {
+            ShellType::Cmd | ShellType::Nushell => {
+                format!("\"{}\"", escape_double(value, shell_type))
+            }
+            _ => format!("'{}'", escape_single(value, shell_type)),
+        },
+        Quoting::Single => escape_single(value, shell_type),
+        Quoting::Double => escape_double(value, shell_type),
+    }
+}
+
+/// Escapes a value for the inside of single quotes.
+fn escape_single(value: &str, shell_type: &ShellType) -> String {
+    match shell_type {
+        ShellType::PowerShell | ShellType::Elvish => value.replace('\'', "''"),
+        ShellType::Fish | ShellType::Xonsh => value.replace('\\', "\\\\").replace('\'', "\\'"),
+        // Nushell single quotes have no escapes and can't contain a quote at all, so one is
+        // dropped rather than left to end the string
+        ShellType::Nushell => value.replace('\'', ""),
+        // Close the quotes, add an escaped quote and reopen them
+        _ => value.replace('\'', "'\\''"),
+    }
+}
+
+/// Escapes a value for the inside of double quotes.
+fn escape_double(value: &str, shell_type: &ShellType) -> String {
+    let (escape, special): (char, &[char]) = match shell_type {
+        ShellType::PowerShell => ('`', &['`', '"', '$']),
+        ShellType::Cmd => return value.replace('"', "\"\""),
+        ShellType::Fish => ('\\', &['\\', '"', '$']),
+        ShellType::Nushell | ShellType::Xonsh | ShellType::Elvish => ('\\', &['\\', '"']),
+        _ => ('\\', &['\\', '"', '$', '`']),
+    };
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if special.contains(&c) {
+            escaped.push(escape);
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(command: &str) -> Vec<String> {
+        find_placeholders(command)
+            .into_iter()
+            .map(|placeholder| placeholder.text)
+            .collect()
+    }
+
+    #[test]
+    fn test_finds_placeholders() {
+        assert_eq!(texts("git push origin <branch>"), ["<branch>"]);
+        assert_eq!(
+            texts("scp path/to/file.txt user@host:/path && export KEY=YOUR_API_KEY"),
+            ["path/to/file.txt", "user@host", "YOUR_API_KEY"]
+        );
+        assert_eq!(
+            texts("docker run -p <host port>:80 <image> && docker logs <image>"),
+            ["<host port>", "<image>"]
+        );
+    }
+
+    #[test]
+    fn test_ignores_redirects_and_ordinary_words() {
+        assert!(texts("sort <in.txt >out.txt").is_empty());
+        assert!(texts("cat <<EOF > notes.txt").is_empty());
+        assert!(texts("git push origin main && echo 'a < b > c'").is_empty());
+        assert!(texts("ls /usr/local/path").is_empty());
+    }
+
+    #[test]
+    fn test_labels() {
+        let placeholders = find_placeholders("curl -H \"Authorization: YOUR_API_KEY\" <base-url>");
+        assert_eq!(placeholders[0].label, "api key");
+        assert_eq!(placeholders[1].label, "base url");
+    }
+
+    #[test]
+    fn test_fill_replaces_every_occurrence() {
+        let command = "docker build -t <image> . && docker push <image>";
+        let placeholder = &find_placeholders(command)[0];
+        assert_eq!(
+            fill(command, placeholder, "app:1.0", &ShellType::Bash),
+            "docker build -t app:1.0 . && docker push app:1.0"
+        );
+    }
+
+    #[test]
+    fn test_fill_quotes_value_for_shell() {
+        let fill_in = |command: &str, value: &str, shell_type: &ShellType| {
+            fill(command, &find_placeholders(command)[0], value, shell_type)
+        };
+        assert_eq!(
+            fill_in("git commit -m <message>", "fix bug", &ShellType::Bash),
+            "git commit -m 'fix bug'"
+        );
+        assert_eq!(
+            fill_in("git checkout <branch>", "x; rm -rf ~", &ShellType::Zsh),
+            "git checkout 'x; rm -rf ~'"
+        );
+        assert_eq!(
+            fill_in("echo <text>", "it's $HOME", &ShellType::Bash),
+            "echo 'it'\\''s $HOME'"
+        );
+        assert_eq!(
+            fill_in("Write-Output <text>", "it's $HOME", &ShellType::PowerShell),
+            "Write-Output 'it''s $HOME'"
+        );
+        assert_eq!(
+            fill_in("echo <text>", "a \"b\"", &ShellType::Cmd),
+            "echo \"a \"\"b\"\"\""
+        );
+    }
+
+    #[test]
+    fn test_fill_escapes_value_inside_quotes() {
+        let fill_in = |command: &str, value: &str, shell_type: &ShellType| {
+            fill(command, &find_placeholders(command)[0], value, shell_type)
+        };
+        assert_eq!(
+            fill_in("git commit -m \"<message>\"", "fix \"$(rm -rf ~)\"", &ShellType::Bash),
+            "git commit -m \"fix \\\"\\$(rm -rf ~)\\\"\""
+        );
+        assert_eq!(
+            fill_in("git commit -m '<message>'", "don't", &ShellType::Bash),
+            "git commit -m 'don'\\''t'"
+        );
+        assert_eq!(
+            fill_in("echo \"<text>\"", "$env:PATH", &ShellType::PowerShell),
+            "echo \"`$env:PATH\""
+        );
+        // An escaped quote doesn't open a quoted string
+        assert_eq!(
+            fill_in("echo \\' <text>", "a b", &ShellType::Bash),
+            "echo \\' 'a b'"
+        );
+    }
+
+    #[test]
+    fn test_suggests_current_git_branch() {
+        let dir = tempfile::tempdir().unwrap();
+        let git_ok = |args: &[&str]| {
+            Command::new("git")
+                .args(args)
+                .current_dir(dir.path())
+                .output()
+                .is_ok_and(|output| output.status.success())
+        };
+        if !git_ok(&["init", "-q", "-b", "feature"]) {
+            return;
+        }
+        let placeholder = &find_placeholders("git push origin <branch>")[0];
+        assert_eq!(
+            placeholder.suggested_value(dir.path()).as_deref(),
+            Some("feature")
+        );
+    }
+}
@@ -0,0 +1,1106 @@
+use std::cell::Cell;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::command_line::{redirect_targets, simple_commands, tokenize, Token, Word};
+use crate::shell::ShellType;
+
+/// Stop counting the contents of huge directory trees after this many entries
+const MAX_ENTRIES: u64 = 100_000;
+const MAX_LISTED: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Delete,
+    Move,
+    Overwrite,
+    Edit,
+    ChangePermissions,
+    ChangeOwner,
+}
+
+impl Action {
+    fn verb(self) -> &'static str {
+        match self {
+            Action::Delete => "Deletes",
+            Action::Move => "Moves",
+            Action::Overwrite => "Overwrites",
+            Action::Edit => "Edits",
+            Action::ChangePermissions => "Changes permissions of",
+            Action::ChangeOwner => "Changes the owner of",
+        }
+    }
+}
+
+/// What one part of a command would do to existing files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Operation {
+    pub action: Action,
+    /// The paths named by the command, after expanding globs
+    pub targets: Vec<PathBuf>,
+    /// Arguments that match no existing file
+    pub missing: Vec<String>,
+    /// Paths that don't exist yet and that the command would create, such as a move's
+    /// destination
+    pub created: Vec<PathBuf>,
+    pub files: u64,
+    pub dirs: u64,
+    pub bytes: u64,
+    /// Counting stopped early in a very large directory tree
+    pub truncated: bool,
+}
+
+impl Operation {
+    fn new(action: Action) -> Self {
+        Self {
+            action,
+            targets: Vec::new(),
+            missing: Vec::new(),
+            created: Vec::new(),
+            files: 0,
+            dirs: 0,
+            bytes: 0,
+            truncated: false,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.targets.is_empty() && self.missing.is_empty() && self.created.is_empty()
+    }
+
+    fn add(&mut self, path: PathBuf, recursive: bool) {
+        self.measure(&path, recursive);
+        self.targets.push(path);
+    }
+
+    fn measure(&mut self, path: &Path, recursive: bool) {
+        if self.files + self.dirs >= MAX_ENTRIES {
+            self.truncated = true;
+            return;
+        }
+        let Ok(metadata) = fs::symlink_metadata(path) else {
+            return;
+        };
+        if !metadata.is_dir() {
+            self.files += 1;
+            self.bytes += metadata.len();
+            return;
+        }
+
+        self.dirs += 1;
+        if recursive {
+            for entry in fs::read_dir(path).into_iter().flatten().flatten() {
+                self.measure(&entry.path(), true);
+            }
+        }
+    }
+}
+
+/// What a command would do to existing files, as far as clm can tell.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Preview {
+    pub operations: Vec<Operation>,
+    /// Some parts of the command change files at paths that depend on an earlier `cd` or on
+    /// variables, so they aren't included
+    pub unresolved: bool,
+}
+
+/// Lists the files that the file-manipulating parts of `command` would affect. Parts that
+/// clm doesn't understand are left out.
+pub fn preview(command: &str, shell_type: &ShellType, cwd: &Path) -> Preview {
+    let Some(tokens) = tokenize(command, shell_type) else {
+        return Preview::default();
+    };
+    let resolvable = before_directory_change(&tokens);
+    let after_cd = &tokens[resolvable.len()..];
+    let globs = Globs::new(shell_type, cwd);
+    let changes_files = |operations: &[Operation]| operations.iter().any(|op| !op.is_empty());
+
+    let mut operations = Vec::new();
+    let mut unresolved = false;
+    for words in simple_commands(resolvable) {
+        let found = preview_words(&words, shell_type, &globs);
+        if words.iter().any(|word| word.dynamic) {
+            unresolved |= changes_files(&found);
+        } else {
+            operations.extend(found);
+        }
+    }
+    // Looked at only to tell whether they change files, since their paths would be wrong
+    for words in simple_commands(after_cd) {
+        unresolved |= changes_files(&preview_words(&words, shell_type, &globs));
+    }
+    unresolved |= changes_files(&redirects(after_cd, &globs))
+        || redirect_targets(resolvable)
+            .iter()
+            .any(|(_, target)| target.dynamic);
+
+    operations.extend(redirects(resolvable, &globs));
+    operations.retain(|operation| !operation.is_empty());
+    Preview {
+        operations,
+        unresolved,
+    }
+}
+
+fn preview_words(words: &[&Word], shell_type: &ShellType, globs: &Globs) -> Vec<Operation> {
+    // Privilege and environment prefixes don't change what the command touches
+    let start = words
+        .iter()
+        .position(|word| !is_prefix(word))
+        .unwrap_or(words.len());
+    preview_command(&words[start..], shell_type, globs)
+}
+
+/// The tokens before the first command that changes directory. Relative paths after it no
+/// longer resolve against the starting directory.
+fn before_directory_change(tokens: &[Token]) -> &[Token] {
+    let mut command_start = 0;
+    let mut at_program = true;
+    let mut after_redirect = false;
+    for (index, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Operator(_) => {
+                command_start = index + 1;
+                at_program = true;
+            }
+            Token::Redirect(_) => after_redirect = true,
+            Token::Word(_) if after_redirect => after_redirect = false,
+            Token::Word(word) if at_program && !is_prefix(word) => {
+                let program = word.text.to_lowercase();
+                if matches!(
+                    program.as_str(),
+                    "cd" | "pushd" | "popd" | "chdir" | "set-location" | "sl" | "push-location"
+                        | "pop-location"
+                ) {
+                    return &tokens[..command_start];
+                }
+                at_program = false;
+            }
+            Token::Word(_) => {}
+        }
+    }
+    tokens
+}
+
+/// Files written by `>`, which truncates them, and `>>`, which appends.
+fn redirects(tokens: &[Token], globs: &Globs) -> Vec<Operation> {
+    let mut overwritten = Operation::new(Action::Overwrite);
+    let mut appended = Operation::new(Action::Edit);
+    for (operator, target) in redirect_targets(tokens) {
+        if target.dynamic
+            || target.text.starts_with("/dev/")
+            || target.text.eq_ignore_ascii_case("nul")
+        {
+            continue;
+        }
+        let path = globs.resolve(&target.text);
+        let operation = if operator.contains(">>") {
+            &mut appended
+        } else {
+            &mut overwritten
+        };
+        if fs::symlink_metadata(&path).is_ok() {
+            operation.add(path, false);
+        } else {
+            operation.created.push(path);
+        }
+    }
+    vec![overwritten, appended]
+}
+
+fn is_prefix(word: &Word) -> bool {
+    matches!(word.text.as_str(), "sudo" | "doas") || is_assignment(word)
+}
+
+fn is_assignment(word: &Word) -> bool {
+    word.text.split_once('=').is_some_and(|(name, _)| {
+        !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+    })
+}
+
+fn preview_command(words: &[&Word], shell_type: &ShellType, globs: &Globs) -> Vec<Operation> {
+    let Some((program, args)) = words.split_first() else {
+        return Vec::new();
+    };
+    let program = program.text.rsplit(['/', '\\']).next().unwrap_or_default();
+    let program = program.to_lowercase();
+    let program = program.trim_end_matches(".exe");
+
+    match (shell_type, program) {
+        (ShellType::PowerShell, "remove-item" | "rm" | "ri" | "del" | "erase" | "rmdir" | "rd") => {
+            powershell_remove(args, globs)
+        }
+        (ShellType::PowerShell, "move-item" | "mv" | "mi" | "move") => powershell_move(args, globs),
+        (ShellType::Cmd, "del" | "erase") => cmd_delete(args, globs, false),
+        (ShellType::Cmd, "rd" | "rmdir") => cmd_delete(args, globs, true),
+        (ShellType::Cmd, "move") => {
+            let operands: Vec<&Word> = args
+                .iter()
+                .copied()
+                .filter(|arg| !arg.text.starts_with('/'))
+                .collect();
+            move_operations(&operands, None, globs)
+        }
+        (ShellType::PowerShell | ShellType::Cmd, _) => Vec::new(),
+        (_, "rm" | "unlink") => posix_remove(args, globs),
+        (_, "rmdir") => {
+            let mut operation = Operation::new(Action::Delete);
+            for operand in operands(args, |_| false) {
+                globs.expand_into(operand, &mut operation, false);
+            }
+            vec![operation]
+        }
+        (_, "mv") => posix_move(args, globs),
+        (_, "sed") => sed_in_place(args, globs),
+        (_, "truncate") => {
+            let mut operation = Operation::new(Action::Edit);
+            for operand in operands(args, |option| matches!(option, "-s" | "-r")) {
+                globs.expand_into(operand, &mut operation, false);
+            }
+            vec![operation]
+        }
+        (_, "chmod") => change(args, globs, Action::ChangePermissions),
+        (_, "chown" | "chgrp") => change(args, globs, Action::ChangeOwner),
+        (_, "find") => find(args, globs).into_iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Operands of a POSIX utility: everything after `--`, and before it everything that isn't an
+/// option. `takes_value` names the options whose value is the next word.
+fn operands<'a>(args: &[&'a Word], takes_value: impl Fn(&str) -> bool) -> Vec<&'a Word> {
+    let mut operands = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg.text == "--" {
+            operands.extend(args.by_ref());
+        } else if arg.text.starts_with('-') && arg.text.len() > 1 {
+            if takes_value(&arg.text) {
+                args.next();
+            }
+        } else {
+            operands.push(*arg);
+        }
+    }
+    operands
+}
+
+fn has_flag(args: &[&Word], short: char, long: &str) -> bool {
+    args.iter().take_while(|arg| arg.text != "--").any(|arg| {
+        arg.text == long
+            || (arg.text.starts_with('-')
+                && !arg.text.starts_with("--")
+                && arg.text[1..].contains(short))
+    })
+}
+
+fn posix_remove(args: &[&Word], globs: &Globs) -> Vec<Operation> {
+    let recursive = has_flag(args, 'r', "--recursive") || has_flag(args, 'R', "--recursive");
+    let mut operation = Operation::new(Action::Delete);
+    for operand in operands(args, |_| false) {
+        globs.expand_into(operand, &mut operation, recursive);
+    }
+    // Without -r, rm refuses to remove directories
+    if !recursive {
+        let is_dir = |path: &PathBuf| fs::symlink_metadata(path).is_ok_and(|meta| meta.is_dir());
+        let skipped = operation.targets.iter().filter(|path| is_dir(path)).count();
+        operation.targets.retain(|path| !is_dir(path));
+        // Directories past the entry limit were never counted
+        operation.dirs = operation.dirs.saturating_sub(skipped as u64);
+    }
+    vec![operation]
+}
+
+fn posix_move(args: &[&Word], globs: &Globs) -> Vec<Operation> {
+    let target_dir = args
+        .iter()
+        .position(|arg| arg.text == "-t" || arg.text == "--target-directory")
+        .and_then(|index| args.get(index + 1))
+        .copied();
+    let operands = operands(args, |option| {
+        matches!(option, "-t" | "--target-directory" | "-S" | "--suffix")
+    });
+    move_operations(&operands, target_dir, globs)
+}
+
+/// Sources and destination of `mv`-like commands: the last operand is the destination unless
+/// a target directory was given separately.
+fn move_operations(operands: &[&Word], target_dir: Option<&Word>, globs: &Globs) -> Vec<Operation> {
+    let (sources, destination) = match target_dir {
+        Some(dir) => (operands, Some(dir)),
+        None => match operands.split_last() {
+            Some((destination, sources)) if !sources.is_empty() => (sources, Some(*destination)),
+            _ => return Vec::new(),
+        },
+    };
+
+    let mut moved = Operation::new(Action::Move);
+    for source in sources {
+        globs.expand_into(source, &mut moved, true);
+    }
+
+    let mut overwritten = Operation::new(Action::Overwrite);
+    if let Some(destination) = destination {
+        let destination = globs.resolve(&destination.text);
+        if destination.is_dir() {
+            let names = moved.targets.iter().filter_map(|source| source.file_name());
+            for path in names.map(|name| destination.join(name)) {
+                // An entry of the same name in the directory is replaced
+                if fs::symlink_metadata(&path).is_ok() {
+                    overwritten.add(path, true);
+                } else {
+                    moved.created.push(path);
+                }
+            }
+        } else if destination.is_file() && sources.len() == 1 {
+            // A single file moved onto an existing file replaces it
+            overwritten.add(destination, false);
+        } else {
+            moved.created.push(destination);
+        }
+    }
+    vec![moved, overwritten]
+}
+
+/// Files edited by `sed -i`. Without `-e` or `-f`, the first operand is the script.
+fn sed_in_place(args: &[&Word], globs: &Globs) -> Vec<Operation> {
+    let in_place = args.iter().any(|arg| {
+        arg.text.starts_with("--in-place")
+            || (arg.text.starts_with('-') && !arg.text.starts_with("--") && arg.text.contains('i'))
+    });
+    if !in_place {
+        return Vec::new();
+    }
+    let has_script = args.iter().any(|arg| {
+        matches!(arg.text.as_str(), "-e" | "-f" | "--expression" | "--file")
+            || arg.text.starts_with("--expression=")
+            || arg.text.starts_with("--file=")
+    });
+    let takes_value =
+        |option: &str| matches!(option, "-e" | "-f" | "-l" | "--expression" | "--file");
+
+    let mut operation = Operation::new(Action::Edit);
+    let operands = operands(args, takes_value);
+    for operand in operands.iter().skip(usize::from(!has_script)) {
+        globs.expand_into(operand, &mut operation, false);
+    }
+    vec![operation]
+}
+
+fn change(args: &[&Word], globs: &Globs, action: Action) -> Vec<Operation> {
+    let recursive = args
+        .iter()
+        .any(|arg| arg.text == "-R" || arg.text == "--recursive");
+    // chmod modes such as -x look like options, so only known options are skipped
+    let is_option = |text: &str| {
+        text.starts_with("--")
+            || matches!(text, "-R" | "-f" | "-v" | "-c" | "-h" | "-H" | "-L" | "-P")
+    };
+    let has_reference = args.iter().any(|arg| arg.text.starts_with("--reference"));
+
+    let mut operands = args.iter().filter(|arg| !is_option(&arg.text));
+    if !has_reference {
+        operands.next();
+    }
+
+    let mut operation = Operation::new(action);
+    for operand in operands {
+        globs.expand_into(operand, &mut operation, recursive);
+    }
+    vec![operation]
+}
+
+fn powershell_remove(args: &[&Word], globs: &Globs) -> Vec<Operation> {
+    let recursive = args
+        .iter()
+        .any(|arg| arg.text.eq_ignore_ascii_case("-recurse"));
+    let mut operation = Operation::new(Action::Delete);
+    for path in powershell_paths(args, &["-path", "-literalpath"]) {
+        globs.expand_into(path, &mut operation, recursive);
+    }
+    vec![operation]
+}
+
+fn powershell_move(args: &[&Word], globs: &Globs) -> Vec<Operation> {
+    let destination = args
+        .iter()
+        .position(|arg| arg.text.eq_ignore_ascii_case("-destination"))
+        .and_then(|index| args.get(index + 1))
+        .copied();
+    let paths = powershell_paths(args, &["-path", "-literalpath"]);
+    move_operations(&paths, destination, globs)
+}
+
+/// Values of the named path parameters, plus positional arguments.
+fn powershell_paths<'a>(args: &[&'a Word], path_parameters: &[&str]) -> Vec<&'a Word> {
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let name = arg.text.to_lowercase();
+        if path_parameters.contains(&name.as_str()) {
+            paths.extend(args.next());
+        } else if name == "-destination"
+            || name == "-include"
+            || name == "-exclude"
+            || name == "-filter"
+        {
+            args.next();
+        } else if !name.starts_with('-') {
+            paths.push(*arg);
+        }
+    }
+    paths
+}
+
+fn cmd_delete(args: &[&Word], globs: &Globs, directories: bool) -> Vec<Operation> {
+    let recursive = args.iter().any(|arg| arg.text.eq_ignore_ascii_case("/s"));
+    let mut operation = Operation::new(Action::Delete);
+    for arg in args.iter().filter(|arg| !arg.text.starts_with('/')) {
+        globs.expand_into(arg, &mut operation, recursive || directories);
+    }
+    vec![operation]
+}
+
+/// Evaluates the common `find` tests when the expression deletes what it finds.
+fn find(args: &[&Word], globs: &Globs) -> Option<Operation> {
+    let first_expression = args
+        .iter()
+        .position(|arg| arg.text.starts_with('-') || arg.text == "(" || arg.text == "!")
+        .unwrap_or(args.len());
+    let (roots, expression) = args.split_at(first_expression);
+
+    let mut filter = FindFilter::default();
+    let mut deletes = false;
+    let mut recursive = false;
+    let mut expression = expression.iter().map(|word| word.text.as_str());
+    while let Some(primary) = expression.next() {
+        match primary {
+            "-name" | "-iname" | "-path" | "-ipath" => {
+                let pattern = expression.next()?.to_string();
+                let case_insensitive = primary.starts_with("-i");
+                let on_path = primary.ends_with("path");
+                filter.patterns.push((pattern, case_insensitive, on_path));
+            }
+            "-type" => filter.kind = expression.next()?.chars().next(),
+            "-maxdepth" => filter.max_depth = Some(expression.next()?.parse().ok()?),
+            "-mindepth" => filter.min_depth = expression.next()?.parse().ok()?,
+            "-delete" => deletes = true,
+            "-exec" | "-execdir" => {
+                let command: Vec<&str> = expression
+                    .by_ref()
+                    .take_while(|word| *word != ";" && *word != "+")
+                    .collect();
+                if command.first() != Some(&"rm") {
+                    return None;
+                }
+                recursive = command
+                    .iter()
+                    .any(|word| word.starts_with('-') && word.contains(['r', 'R']));
+                deletes = true;
+            }
+            "-print" | "-print0" | "-depth" | "-xdev" | "-mount" | "-a" | "-and" => {}
+            // Anything else, such as -o, -newer or -size, isn't evaluated
+            _ => return None,
+        }
+    }
+    if !deletes {
+        return None;
+    }
+
+    let mut operation = Operation::new(Action::Delete);
+    let roots: Vec<PathBuf> = if roots.is_empty() {
+        vec![globs.cwd.clone()]
+    } else {
+        roots.iter().map(|root| globs.resolve(&root.text)).collect()
+    };
+    let mut visited = 0;
+    for root in roots {
+        if !root.exists() {
+            operation.missing.push(globs.display(&root));
+            continue;
+        }
+        let root_display = globs.display(&root);
+        filter.walk(&root, &root_display, 0, &mut visited, &mut |path| {
+            operation.add(path.to_path_buf(), recursive)
+        });
+    }
+    operation.truncated |= visited >= MAX_ENTRIES;
+    Some(operation)
+}
+
+#[derive(Default)]
+struct FindFilter {
+    /// Pattern, case-insensitive, matched against the whole path rather than the name
+    patterns: Vec<(String, bool, bool)>,
+    kind: Option<char>,
+    max_depth: Option<usize>,
+    min_depth: usize,
+}
+
+impl FindFilter {
+    /// Calls `found` for each match under `path`, giving up once `visited` reaches
+    /// `MAX_ENTRIES`.
+    fn walk(
+        &self,
+        path: &Path,
+        display: &str,
+        depth: usize,
+        visited: &mut u64,
+        found: &mut dyn FnMut(&Path),
+    ) {
+        if *visited >= MAX_ENTRIES {
+            return;
+        }
+        *visited += 1;
+        let Ok(metadata) = fs::symlink_metadata(path) else {
+            return;
+        };
+        if self.max_depth.is_some_and(|max| depth > max) {
+            return;
+        }
+        if metadata.is_dir() {
+            for entry in fs::read_dir(path).into_iter().flatten().flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                self.walk(
+                    &entry.path(),
+                    &format!("{display}/{name}"),
+                    depth + 1,
+                    visited,
+                    found,
+                );
+            }
+        }
+        if depth >= self.min_depth && self.matches(path, display, &metadata) {
+            found(path);
+        }
+    }
+
+    fn matches(&self, path: &Path, display: &str, metadata: &fs::Metadata) -> bool {
+        let kind_matches = match self.kind {
+            Some('f') => metadata.is_file(),
+            Some('d') => metadata.is_dir(),
+            Some('l') => metadata.file_type().is_symlink(),
+            _ => true,
+        };
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        kind_matches
+            && self
+                .patterns
+                .iter()
+                .all(|(pattern, case_insensitive, on_path)| {
+                    let subject = if *on_path { display } else { &name };
+                    wildcard_match(pattern, subject, true, *case_insensitive)
+                })
+    }
+}
+
+/// Expands words the way the current shell would before running the command.
+struct Globs {
+    cwd: PathBuf,
+    windows: bool,
+    recursive_star: bool,
+    /// A `**` stopped listing directories at `MAX_ENTRIES`
+    truncated: Cell<bool>,
+}
+
+impl Globs {
+    fn new(shell_type: &ShellType, cwd: &Path) -> Self {
+        Self {
+            cwd: cwd.to_path_buf(),
+            windows: matches!(shell_type, ShellType::PowerShell | ShellType::Cmd),
+            recursive_star: matches!(shell_type, ShellType::Zsh | ShellType::Fish),
+            truncated: Cell::new(false),
+        }
+    }
+
+    fn resolve(&self, text: &str) -> PathBuf {
+        let expanded = match text.strip_prefix('~') {
+            Some(rest) if rest.is_empty() || rest.starts_with(['/', '\\']) => home::home_dir()
+                .map(|home| home.join(rest.trim_start_matches(['/', '\\'])))
+                .unwrap_or_else(|| PathBuf::from(text)),
+            _ => PathBuf::from(text),
+        };
+        self.cwd.join(expanded)
+    }
+
+    fn display(&self, path: &Path) -> String {
+        let relative = path.strip_prefix(&self.cwd).unwrap_or(path);
+        if relative.as_os_str().is_empty() {
+            ".".to_string()
+        } else {
+            relative.display().to_string()
+        }
+    }
+
+    fn expand_into(&self, word: &Word, operation: &mut Operation, recursive: bool) {
+        let paths = self.expand(word);
+        operation.truncated |= self.truncated.take();
+        if paths.is_empty() {
+            operation.missing.push(word.text.clone());
+        }
+        for path in paths {
+            operation.add(path, recursive);
+        }
+    }
+
+    /// The existing paths a word refers to.
+    fn expand(&self, word: &Word) -> Vec<PathBuf> {
+        // Windows commands expand their own wildcards
+        if !(word.has_glob() || self.windows && word.text.contains(['*', '?'])) {
+            let path = self.resolve(&word.text);
+            return if fs::symlink_metadata(&path).is_ok() {
+                vec![path]
+            } else {
+                Vec::new()
+            };
+        }
+
+        let pattern = if self.windows {
+            word.text.clone()
+        } else {
+            word.glob_pattern()
+        };
+        let separators: &[char] = if self.windows { &['/', '\\'] } else { &['/'] };
+        let (start, pattern) = match pattern.strip_prefix(separators) {
+            Some(rest) => (
+                PathBuf::from(&pattern[..pattern.len() - rest.len()]),
+                rest.to_string(),
+            ),
+            None => match pattern.strip_prefix("~/") {
+                Some(rest) => (self.resolve("~"), rest.to_string()),
+                None => (self.cwd.clone(), pattern),
+            },
+        };
+
+        let mut matches = vec![start];
+        for component in pattern
+            .split(separators)
+            .filter(|component| !component.is_empty())
+        {
+            matches = matches
+                .iter()
+                .flat_map(|base| self.expand_component(base, component))
+                .collect();
+        }
+        matches.sort();
+        matches.dedup();
+        matches
+    }
+
+    fn expand_component(&self, base: &Path, component: &str) -> Vec<PathBuf> {
+        let has_wildcard = if self.windows {
+            component.contains(['*', '?'])
+        } else {
+            unescaped_wildcard(component)
+        };
+        if !has_wildcard {
+            let literal = if self.windows {
+                component.to_string()
+            } else {
+                unescape(component)
+            };
+            let path = base.join(literal);
+            return if fs::symlink_metadata(&path).is_ok() {
+                vec![path]
+            } else {
+                Vec::new()
+            };
+        }
+        if component == "**" && self.recursive_star {
+            let mut dirs = vec![base.to_path_buf()];
+            let mut visited = 0;
+            collect_dirs(base, &mut dirs, &mut visited);
+            if visited >= MAX_ENTRIES {
+                self.truncated.set(true);
+            }
+            return dirs;
+        }
+
+        let mut matches = Vec::new();
+        for entry in fs::read_dir(base).into_iter().flatten().flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            // POSIX shells only match hidden files with a pattern that starts with a dot
+            if !self.windows && name.starts_with('.') && !component.starts_with('.') {
+                continue;
+            }
+            if wildcard_match(component, &name, !self.windows, self.windows) {
+                matches.push(entry.path());
+            }
+        }
+        matches
+    }
+}
+
+fn collect_dirs(dir: &Path, dirs: &mut Vec<PathBuf>, visited: &mut u64) {
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        if *visited >= MAX_ENTRIES {
+            return;
+        }
+        *visited += 1;
+        let is_dir = entry.file_type().is_ok_and(|kind| kind.is_dir());
+        if is_dir && !entry.file_name().to_string_lossy().starts_with('.') {
+            dirs.push(entry.path());
+            collect_dirs(&entry.path(), dirs, visited);
+        }
+    }
+}
+
+fn unescaped_wildcard(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+fn unescape(pattern: &str) -> String {
+    let mut text = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        text.push(if c == '\\' {
+            chars.next().unwrap_or(c)
+        } else {
+            c
+        });
+    }
+    text
+}
+
+/// Matches `*`, `?` and bracket expressions like `[a-z]` or `[!0-9]`.
+pub fn wildcard_match(pattern: &str, text: &str, escapes: bool, case_insensitive: bool) -> bool {
+    let fold = |s: &str| -> Vec<char> {
+        if case_insensitive {
+            s.to_lowercase().chars().collect()
+        } else {
+            s.chars().collect()
+        }
+    };
+    matches_from(&fold(pattern), &fold(text), escapes)
+}
+
+fn matches_from(pattern: &[char], text: &[char], escapes: bool) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') => {
+            (0..=text.len()).any(|skip| matches_from(&pattern[1..], &text[skip..], escapes))
+        }
+        Some('?') => !text.is_empty() && matches_from(&pattern[1..], &text[1..], escapes),
+        Some('[') => {
+            let Some((&c, rest)) = text.split_first() else {
+                return false;
+            };
+            match bracket(&pattern[1..], c) {
+                Some((matched, len)) => matched && matches_from(&pattern[1 + len..], rest, escapes),
+                // An unclosed bracket is a literal
+                None => c == '[' && matches_from(&pattern[1..], rest, escapes),
+            }
+        }
+        Some('\\') if escapes && pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && matches_from(&pattern[2..], &text[1..], escapes)
+        }
+        Some(p) => text.first() == Some(p) && matches_from(&pattern[1..], &text[1..], escapes),
+    }
+}
+
+/// Whether `c` matches the bracket expression at the start of `pattern` (after the `[`), and
+/// how many pattern chars the expression used including the closing `]`.
+fn bracket(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let negated = matches!(pattern.first(), Some('!' | '^'));
+    let mut index = usize::from(negated);
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let current = *pattern.get(index)?;
+        if current == ']' && !first {
+            return Some((matched != negated, index + 1));
+        }
+        first = false;
+        if pattern.get(index + 1) == Some(&'-')
+            && pattern.get(index + 2).is_some_and(|end| *end != ']')
+        {
+            let end = pattern[index + 2];
+            matched |= (current..=end).contains(&c);
+            index += 3;
+        } else {
+            matched |= current == c;
+            index += 1;
+        }
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+fn count(n: u64, singular: &str, plural: &str) -> String {
+    format!("{n} {}", if n == 1 { singular } else { plural })
+}
+
+pub fn print_preview(preview: &Preview, cwd: &Path) {
+    let operations = &preview.operations;
+    if operations.is_empty() && !preview.unresolved {
+        return;
+    }
+
+    println!("{}", console::style("Preview:").cyan());
+    for operation in operations {
+        if !operation.targets.is_empty() {
+            let mut parts = Vec::new();
+            if operation.files > 0 {
+                parts.push(count(operation.files, "file", "files"));
+            }
+            if operation.dirs > 0 {
+                parts.push(count(operation.dirs, "directory", "directories"));
+            }
+            let at_least = if operation.truncated { "at least " } else { "" };
+            println!(
+                "  {} {at_least}{} ({})",
+                operation.action.verb(),
+                parts.join(" and "),
+                format_size(operation.bytes)
+            );
+
+            for target in operation.targets.iter().take(MAX_LISTED) {
+                let relative = target.strip_prefix(cwd).unwrap_or(target);
+                let suffix = if target.is_dir() { "/" } else { "" };
+                println!("    {}{suffix}", console::style(relative.display()).dim());
+            }
+            if operation.targets.len() > MAX_LISTED {
+                println!(
+                    "    {}",
+                    console::style(format!(
+                        "… and {} more",
+                        operation.targets.len() - MAX_LISTED
+                    ))
+                    .dim()
+                );
+            }
+        }
+        for missing in &operation.missing {
+            println!(
+                "  {}",
+                console::style(format!("Nothing matches {missing}")).yellow()
+            );
+        }
+    }
+    if preview.unresolved {
+        println!(
+            "  {}",
+            console::style("Some paths depend on a cd or on variables and aren't previewed")
+                .yellow()
+        );
+    }
+    println!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("build/debug")).unwrap();
+        fs::write(root.join("build/app"), vec![0; 1000]).unwrap();
+        fs::write(root.join("build/debug/app.o"), vec![0; 500]).unwrap();
+        fs::write(root.join("a.log"), "a").unwrap();
+        fs::write(root.join("b.log"), "bb").unwrap();
+        fs::write(root.join(".hidden.log"), "").unwrap();
+        fs::write(root.join("notes.txt"), "notes").unwrap();
+        dir
+    }
+
+    fn operations_of(command: &str, shell_type: &ShellType, cwd: &Path) -> Vec<Operation> {
+        preview(command, shell_type, cwd).operations
+    }
+
+    fn names(operation: &Operation, cwd: &Path) -> Vec<String> {
+        operation
+            .targets
+            .iter()
+            .map(|path| path.strip_prefix(cwd).unwrap().display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*.log", "a.log", true, false));
+        assert!(!wildcard_match("*.log", "a.txt", true, false));
+        assert!(wildcard_match("file?.[ch]", "file1.c", true, false));
+        assert!(wildcard_match("[!a]*", "build", true, false));
+        assert!(!wildcard_match("[!a]*", "app", true, false));
+        assert!(wildcard_match(r"\*.txt", "*.txt", true, false));
+        assert!(wildcard_match("*.LOG", "a.log", false, true));
+    }
+
+    #[test]
+    fn test_rm_glob_skips_hidden_files() {
+        let dir = tree();
+        let operations = operations_of("rm -f *.log", &ShellType::Bash, dir.path());
+        assert_eq!(operations.len(), 1);
+        assert_eq!(names(&operations[0], dir.path()), ["a.log", "b.log"]);
+        assert_eq!(operations[0].files, 2);
+        assert_eq!(operations[0].bytes, 3);
+    }
+
+    #[test]
+    fn test_rm_recursive_counts_directory_contents() {
+        let dir = tree();
+        let operations = operations_of(
+            "rm -rf build notes.txt missing",
+            &ShellType::Zsh,
+            dir.path(),
+        );
+        let operation = &operations[0];
+        assert_eq!(operation.action, Action::Delete);
+        assert_eq!(operation.files, 3);
+        assert_eq!(operation.dirs, 2);
+        assert_eq!(operation.bytes, 1505);
+        assert_eq!(operation.missing, ["missing"]);
+
+        // Without -r, rm leaves directories alone
+        let operations = operations_of("rm build notes.txt", &ShellType::Bash, dir.path());
+        assert_eq!(names(&operations[0], dir.path()), ["notes.txt"]);
+        assert_eq!(operations[0].dirs, 0);
+    }
+
+    #[test]
+    fn test_mv_reports_overwritten_destination() {
+        let dir = tree();
+        let operations = operations_of("mv a.log notes.txt", &ShellType::Bash, dir.path());
+        assert_eq!(operations.len(), 2);
+        assert_eq!(operations[0].action, Action::Move);
+        assert_eq!(names(&operations[0], dir.path()), ["a.log"]);
+        assert_eq!(operations[1].action, Action::Overwrite);
+        assert_eq!(names(&operations[1], dir.path()), ["notes.txt"]);
+
+        fs::write(dir.path().join("build/b.log"), "old").unwrap();
+        let operations = operations_of("mv *.log build", &ShellType::Bash, dir.path());
+        assert_eq!(operations.len(), 2);
+        assert_eq!(operations[0].files, 2);
+        assert_eq!(operations[0].created, [dir.path().join("build/a.log")]);
+        assert_eq!(operations[1].action, Action::Overwrite);
+        assert_eq!(names(&operations[1], dir.path()), ["build/b.log"]);
+    }
+
+    #[test]
+    fn test_edits_and_redirects() {
+        let dir = tree();
+        let operations = operations_of(
+            "sed -i 's/a/b/' a.log b.log > notes.txt 2>&1 && echo x >> new.txt",
+            &ShellType::Bash,
+            dir.path(),
+        );
+        assert_eq!(operations.len(), 3);
+        assert_eq!(operations[0].action, Action::Edit);
+        assert_eq!(names(&operations[0], dir.path()), ["a.log", "b.log"]);
+        assert_eq!(operations[1].action, Action::Overwrite);
+        assert_eq!(names(&operations[1], dir.path()), ["notes.txt"]);
+        assert_eq!(operations[2].created, [dir.path().join("new.txt")]);
+
+        assert!(operations_of("sed 's/a/b/' a.log", &ShellType::Bash, dir.path()).is_empty());
+    }
+
+    #[test]
+    fn test_chmod_skips_the_mode() {
+        let dir = tree();
+        let operations = operations_of("chmod -R -x build", &ShellType::Bash, dir.path());
+        assert_eq!(operations[0].action, Action::ChangePermissions);
+        assert_eq!(names(&operations[0], dir.path()), ["build"]);
+        assert_eq!(operations[0].files, 2);
+    }
+
+    #[test]
+    fn test_find_delete() {
+        let dir = tree();
+        let operations = operations_of(
+            "find . -name '*.o' -type f -delete",
+            &ShellType::Bash,
+            dir.path(),
+        );
+        assert_eq!(operations[0].files, 1);
+        assert_eq!(operations[0].bytes, 500);
+
+        // Expressions clm can't evaluate produce no preview rather than a wrong one
+        assert!(operations_of("find . -size +1M -delete", &ShellType::Bash, dir.path()).is_empty());
+        assert!(operations_of("find . -name '*.o'", &ShellType::Bash, dir.path()).is_empty());
+    }
+
+    #[test]
+    fn test_compound_and_dynamic_commands() {
+        let dir = tree();
+        let operations = operations_of(
+            "sudo rm a.log; echo done",
+            &ShellType::Bash,
+            dir.path(),
+        );
+        assert_eq!(names(&operations[0], dir.path()), ["a.log"]);
+
+        // After a cd, relative paths no longer point where the preview would look
+        let after_cd = preview(
+            "rm b.log && cd build && sudo rm notes.txt > app",
+            &ShellType::Bash,
+            dir.path(),
+        );
+        assert!(after_cd.unresolved);
+        assert_eq!(after_cd.operations.len(), 1);
+        assert_eq!(names(&after_cd.operations[0], dir.path()), ["b.log"]);
+        assert!(operations_of("rm -rf $DIR", &ShellType::Bash, dir.path()).is_empty());
+        assert!(preview("rm -rf $DIR", &ShellType::Bash, dir.path()).unresolved);
+        assert!(preview("echo x > $LOG", &ShellType::Bash, dir.path()).unresolved);
+        assert!(!preview("echo $HOME; cd build && make", &ShellType::Bash, dir.path()).unresolved);
+        assert!(operations_of("ls -la", &ShellType::Bash, dir.path()).is_empty());
+    }
+
+    #[test]
+    fn test_zsh_recursive_glob() {
+        let dir = tree();
+        let operations = operations_of("rm **/*.o", &ShellType::Zsh, dir.path());
+        assert_eq!(names(&operations[0], dir.path()), ["build/debug/app.o"]);
+    }
+
+    #[test]
+    fn test_powershell_remove_item() {
+        let dir = tree();
+        let operations = operations_of(
+            "Remove-Item -Path *.LOG -Force",
+            &ShellType::PowerShell,
+            dir.path(),
+        );
+        assert_eq!(operations[0].files, 3);
+
+        let operations = operations_of(
+            "Remove-Item build -Recurse",
+            &ShellType::PowerShell,
+            dir.path(),
+        );
+        assert_eq!(operations[0].files, 2);
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.0 MiB");
+    }
+}
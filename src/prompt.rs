@@ -0,0 +1,166 @@
+use anyhow::{anyhow, bail, Context, Result};
+use dialoguer::{Confirm, Input, Select};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Answers prompts from a file, one per line, instead of the terminal
+static PROMPT_SCRIPT_ENV: &str = "CLM_PROMPT_SCRIPT";
+
+/// The questions clm asks the user, so they can be answered by a script in tests.
+pub trait Prompter {
+    fn confirm(&self, prompt: &str, default: bool) -> Result<bool>;
+    /// Returns the index of the chosen item
+    fn select(&self, prompt: &str, items: &[&str], default: usize) -> Result<usize>;
+    fn input(&self, prompt: &str, initial: &str) -> Result<String>;
+}
+
+pub struct TerminalPrompter;
+
+impl Prompter for TerminalPrompter {
+    fn confirm(&self, prompt: &str, default: bool) -> Result<bool> {
+        Ok(Confirm::new()
+            .with_prompt(prompt)
+            .default(default)
+            .interact()?)
+    }
+
+    fn select(&self, prompt: &str, items: &[&str], default: usize) -> Result<usize> {
+        Ok(Select::new()
+            .with_prompt(prompt)
+            .items(items)
+            .default(default)
+            .interact()?)
+    }
+
+    fn input(&self, prompt: &str, initial: &str) -> Result<String> {
+        Ok(Input::new()
+            .with_prompt(prompt)
+            .with_initial_text(initial)
+            .interact_text()?)
+    }
+}
+
+/// Replays prepared answers in order. A blank answer takes the prompt's default: the
+/// default choice, or the initial text of an input.
+pub struct ScriptedPrompter {
+    answers: Mutex<VecDeque<String>>,
+}
+
+impl ScriptedPrompter {
+    pub fn new<S: Into<String>>(answers: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            answers: Mutex::new(answers.into_iter().map(Into::into).collect()),
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let script = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read prompt script {}", path.display()))?;
+        Ok(Self::new(script.lines()))
+    }
+
+    fn next_answer(&self, prompt: &str) -> Result<String> {
+        let answer = self
+            .answers
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or_else(|| anyhow!("The prompt script has no answer left for \"{prompt}\""))?;
+        eprintln!("{prompt} {answer}");
+        Ok(answer.trim().to_string())
+    }
+}
+
+impl Prompter for ScriptedPrompter {
+    fn confirm(&self, prompt: &str, default: bool) -> Result<bool> {
+        match self.next_answer(prompt)?.to_lowercase().as_str() {
+            "" => Ok(default),
+            "y" | "yes" => Ok(true),
+            "n" | "no" => Ok(false),
+            other => bail!("Expected yes or no for \"{prompt}\", got \"{other}\""),
+        }
+    }
+
+    fn select(&self, prompt: &str, items: &[&str], default: usize) -> Result<usize> {
+        let answer = self.next_answer(prompt)?;
+        if answer.is_empty() {
+            return Ok(default);
+        }
+        items
+            .iter()
+            .position(|item| item.eq_ignore_ascii_case(&answer))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Expected one of {} for \"{prompt}\", got \"{answer}\"",
+                    items.join(", ")
+                )
+            })
+    }
+
+    fn input(&self, prompt: &str, initial: &str) -> Result<String> {
+        let answer = self.next_answer(prompt)?;
+        Ok(if answer.is_empty() {
+            initial.to_string()
+        } else {
+            answer
+        })
+    }
+}
+
+/// The terminal, unless `CLM_PROMPT_SCRIPT` names a file of answers.
+pub fn from_env() -> Result<Box<dyn Prompter>> {
+    match std::env::var_os(PROMPT_SCRIPT_ENV) {
+        Some(path) => Ok(Box::new(ScriptedPrompter::from_file(Path::new(&path))?)),
+        None => Ok(Box::new(TerminalPrompter)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scripted_answers_in_order() {
+        let prompter = ScriptedPrompter::new(["yes", "edit", "ls -l", "", ""]);
+        assert!(prompter.confirm("Run?", false).unwrap());
+        assert_eq!(
+            prompter
+                .select("Execute?", &["Execute", "Edit", "Cancel"], 2)
+                .unwrap(),
+            1
+        );
+        assert_eq!(prompter.input("Command", "ls").unwrap(), "ls -l");
+        assert_eq!(prompter.input("Command", "ls").unwrap(), "ls");
+        assert!(!prompter.confirm("Sure?", false).unwrap());
+    }
+
+    #[test]
+    fn test_scripted_prompter_rejects_bad_answers() {
+        let prompter = ScriptedPrompter::new(["maybe", "Delete"]);
+        assert!(prompter.confirm("Run?", false).is_err());
+        assert!(prompter
+            .select("Execute?", &["Execute", "Cancel"], 1)
+            .is_err());
+        assert!(prompter
+            .confirm("Run?", false)
+            .unwrap_err()
+            .to_string()
+            .contains("no answer left"));
+    }
+
+    #[test]
+    fn test_scripted_prompter_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("answers.txt");
+        std::fs::write(&path, "Cancel\n").unwrap();
+
+        let prompter = ScriptedPrompter::from_file(&path).unwrap();
+        assert_eq!(
+            prompter
+                .select("Execute?", &["Execute", "Cancel"], 0)
+                .unwrap(),
+            1
+        );
+    }
+}
@@ -0,0 +1,510 @@
+use anyhow::{anyhow, Result};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::config::{ProviderConfig, ProviderKind, RequestConfig};
+use crate::credentials::Store;
+use crate::error::ClmError;
+use crate::retry::{with_retries, RetryAttempt, RetryListener};
+use crate::shell::ShellContext;
+
+pub mod anthropic;
+pub mod ollama;
+pub mod openai;
+
+use anthropic::AnthropicProvider;
+use ollama::OllamaProvider;
+use openai::{AzureProvider, OpenAIProvider};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn system(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::System,
+            content: content.into(),
+        }
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::User,
+            content: content.into(),
+        }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::Assistant,
+            content: content.into(),
+        }
+    }
+}
+
+/// A tool the model may call with structured input. Providers without tool support
+/// ignore it, so callers must still handle a plain text answer.
+#[derive(Debug, Clone)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: String,
+    pub input_schema: serde_json::Value,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolCall {
+    pub name: String,
+    pub input: serde_json::Value,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChatRequest {
+    pub messages: Vec<ChatMessage>,
+    pub temperature: f32,
+    pub max_tokens: Option<u16>,
+    pub tools: Vec<ToolDefinition>,
+    /// Name of a tool in `tools` the model must call
+    pub tool_choice: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ChatResponse {
+    pub content: Option<String>,
+    pub tool_calls: Vec<ToolCall>,
+    /// The model that produced this response, which may be a fallback
+    pub model: String,
+    /// Token counts, when the server reports them
+    pub usage: Option<Usage>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct ModelInfo {
+    pub name: String,
+    pub size_bytes: Option<u64>,
+    pub modified: Option<String>,
+}
+
+pub enum Backend {
+    OpenAI(OpenAIProvider),
+    Ollama(OllamaProvider),
+    Anthropic(AnthropicProvider),
+    Azure(AzureProvider),
+}
+
+impl Backend {
+    fn from_config(
+        shell_context: &ShellContext,
+        config: &ProviderConfig,
+        request: &RequestConfig,
+        store: &Store,
+    ) -> Result<Self> {
+        Ok(match config.kind {
+            ProviderKind::OpenAI => {
+                Backend::OpenAI(OpenAIProvider::new(shell_context, config, request, store)?)
+            }
+            ProviderKind::Ollama => {
+                Backend::Ollama(OllamaProvider::new(shell_context, config, request, store)?)
+            }
+            ProviderKind::Anthropic => {
+                Backend::Anthropic(AnthropicProvider::new(shell_context, config, request, store)?)
+            }
+            ProviderKind::Azure => {
+                Backend::Azure(AzureProvider::new_azure(shell_context, config, request, store)?)
+            }
+        })
+    }
+
+    fn model(&self) -> &str {
+        match self {
+            Backend::OpenAI(provider) => provider.model(),
+            Backend::Ollama(provider) => provider.model(),
+            Backend::Anthropic(provider) => provider.model(),
+            Backend::Azure(provider) => provider.model(),
+        }
+    }
+
+    async fn chat(&self, request: &ChatRequest) -> Result<ChatResponse> {
+        match self {
+            Backend::OpenAI(provider) => provider.chat(request).await,
+            Backend::Ollama(provider) => provider.chat(request).await,
+            Backend::Anthropic(provider) => provider.chat(request).await,
+            Backend::Azure(provider) => provider.chat(request).await,
+        }
+    }
+
+    async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        match self {
+            Backend::OpenAI(provider) => provider.list_models().await,
+            Backend::Ollama(provider) => provider.list_models().await,
+            Backend::Anthropic(provider) => provider.list_models().await,
+            Backend::Azure(provider) => provider.list_models().await,
+        }
+    }
+}
+
+/// A fallback provider, only built once every provider before it has failed.
+struct Fallback {
+    config: ProviderConfig,
+    backend: OnceLock<Backend>,
+}
+
+pub struct FallbackAttempt<'a> {
+    pub failed_model: &'a str,
+    pub next_model: &'a str,
+    pub error: &'a anyhow::Error,
+}
+
+pub type FallbackListener = Box<dyn Fn(&FallbackAttempt) + Send + Sync>;
+
+/// The configured backend, the fallbacks tried when it fails, and the retry behaviour
+/// shared by all of them.
+pub struct Provider {
+    backend: Backend,
+    fallbacks: Vec<Fallback>,
+    shell_context: ShellContext,
+    request: RequestConfig,
+    /// Kept for the fallbacks, whose backends are only created when they are needed
+    store: Store,
+    on_retry: Option<RetryListener>,
+    on_fallback: Option<FallbackListener>,
+}
+
+impl Provider {
+    pub fn from_config(
+        shell_context: &ShellContext,
+        config: &ProviderConfig,
+        request: &RequestConfig,
+    ) -> Result<Self> {
+        Self::from_config_with_store(shell_context, config, request, Store::system())
+    }
+
+    /// Like `from_config`, reading API keys from `store` instead of the system's stores.
+    pub fn from_config_with_store(
+        shell_context: &ShellContext,
+        config: &ProviderConfig,
+        request: &RequestConfig,
+        store: Store,
+    ) -> Result<Self> {
+        Ok(Self {
+            backend: Backend::from_config(shell_context, config, request, &store)?,
+            fallbacks: Vec::new(),
+            shell_context: shell_context.clone(),
+            request: request.clone(),
+            store,
+            on_retry: None,
+            on_fallback: None,
+        })
+    }
+
+    /// Providers to try in order when a chat request to the previous one fails.
+    pub fn with_fallbacks(mut self, fallbacks: &[ProviderConfig]) -> Self {
+        self.fallbacks = fallbacks
+            .iter()
+            .map(|config| Fallback {
+                config: config.clone(),
+                backend: OnceLock::new(),
+            })
+            .collect();
+        self
+    }
+
+    /// Calls `listener` before each retry, e.g. to show progress on a spinner.
+    pub fn on_retry(mut self, listener: impl Fn(&RetryAttempt) + Send + Sync + 'static) -> Self {
+        self.on_retry = Some(Box::new(listener));
+        self
+    }
+
+    /// Calls `listener` before switching to the next fallback provider.
+    pub fn on_fallback(
+        mut self,
+        listener: impl Fn(&FallbackAttempt) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_fallback = Some(Box::new(listener));
+        self
+    }
+
+    pub fn backend(&self) -> &Backend {
+        &self.backend
+    }
+
+    /// The primary model. `ChatResponse::model` says which one actually answered.
+    pub fn model(&self) -> &str {
+        self.backend.model()
+    }
+
+    pub async fn chat(&self, request: &ChatRequest) -> Result<ChatResponse> {
+        let mut failed_model = self.backend.model();
+        let mut result = self.chat_with(&self.backend, request).await;
+
+        for fallback in &self.fallbacks {
+            let err = match result {
+                Ok(response) => return Ok(response),
+                Err(err) => err,
+            };
+            if !err
+                .downcast_ref::<ClmError>()
+                .is_some_and(ClmError::allows_fallback)
+            {
+                return Err(err);
+            }
+
+            let backend = match fallback.backend(&self.shell_context, &self.request, &self.store) {
+                Ok(backend) => backend,
+                Err(build_err) => {
+                    eprintln!(
+                        "{}",
+                        console::style(format!(
+                            "Skipping fallback model {}: {build_err}",
+                            fallback.config.model()
+                        ))
+                        .yellow()
+                    );
+                    result = Err(err);
+                    continue;
+                }
+            };
+
+            if let Some(on_fallback) = &self.on_fallback {
+                on_fallback(&FallbackAttempt {
+                    failed_model,
+                    next_model: backend.model(),
+                    error: &err,
+                });
+            }
+            failed_model = backend.model();
+            result = self.chat_with(backend, request).await;
+        }
+
+        result
+    }
+
+    pub async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        with_retries(self.request.max_retries, self.on_retry.as_ref(), || {
+            self.backend.list_models()
+        })
+        .await
+    }
+
+    async fn chat_with(&self, backend: &Backend, request: &ChatRequest) -> Result<ChatResponse> {
+        with_retries(self.request.max_retries, self.on_retry.as_ref(), || {
+            backend.chat(request)
+        })
+        .await
+    }
+}
+
+impl Fallback {
+    fn backend(
+        &self,
+        shell_context: &ShellContext,
+        request: &RequestConfig,
+        store: &Store,
+    ) -> Result<&Backend> {
+        if let Some(backend) = self.backend.get() {
+            return Ok(backend);
+        }
+        let backend = Backend::from_config(shell_context, &self.config, request, store)?;
+        Ok(self.backend.get_or_init(|| backend))
+    }
+}
+
+pub(crate) fn build_headers(headers: &HashMap<String, String>) -> Result<HeaderMap> {
+    let mut header_map = HeaderMap::new();
+    for (name, value) in headers {
+        let name = HeaderName::try_from(name.as_str())
+            .map_err(|_| anyhow!("Invalid header name in config: {name}"))?;
+        let value = HeaderValue::try_from(value.as_str())
+            .map_err(|_| anyhow!("Invalid value for header {name} in config"))?;
+        header_map.insert(name, value);
+    }
+    Ok(header_map)
+}
+
+pub(crate) fn http_client(headers: HeaderMap, request: &RequestConfig) -> Result<reqwest::Client> {
+    Ok(reqwest::Client::builder()
+        .default_headers(headers)
+        .connect_timeout(request.connect_timeout())
+        .timeout(request.timeout())
+        .build()?)
+}
+
+/// Reads a `Retry-After` header given in seconds.
+pub(crate) fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+pub fn print_models(models: &[ModelInfo]) {
+    if models.is_empty() {
+        println!("{}", console::style("No models found.").dim());
+        return;
+    }
+
+    for model in models {
+        let size = model
+            .size_bytes
+            .map(|bytes| format!("{:.1} GB", bytes as f64 / 1_000_000_000.0))
+            .unwrap_or_default();
+        println!(
+            "{:<40} {:>10}  {}",
+            console::style(&model.name).bold(),
+            size,
+            console::style(model.modified.as_deref().unwrap_or_default()).dim()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_build_headers() {
+        let headers = HashMap::from([("X-Team".to_string(), "infra".to_string())]);
+        let header_map = build_headers(&headers).unwrap();
+        assert_eq!(header_map.get("x-team").unwrap(), "infra");
+    }
+
+    #[test]
+    fn test_build_headers_rejects_invalid_name() {
+        let headers = HashMap::from([("bad header".to_string(), "value".to_string())]);
+        assert!(build_headers(&headers).is_err());
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert(reqwest::header::RETRY_AFTER, HeaderValue::from_static("12"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(12)));
+        headers.insert(
+            reqwest::header::RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after(&headers), None);
+    }
+
+    #[test]
+    fn test_from_config_selects_provider() {
+        let dir = tempfile::tempdir().unwrap();
+        let from_config = |config: &ProviderConfig| {
+            Provider::from_config_with_store(
+                &ShellContext::default(),
+                config,
+                &RequestConfig::default(),
+                Store::empty_in(dir.path()),
+            )
+        };
+        let config = ProviderConfig {
+            kind: ProviderKind::Ollama,
+            ..Default::default()
+        };
+        let provider = from_config(&config).unwrap();
+        assert!(matches!(provider.backend(), Backend::Ollama(_)));
+        assert_eq!(provider.model(), "llama3.2");
+
+        let config = ProviderConfig {
+            api_base: Some("http://localhost:8080/v1/".to_string()),
+            model: Some("qwen2.5-coder".to_string()),
+            ..Default::default()
+        };
+        let provider = from_config(&config).unwrap();
+        assert!(matches!(provider.backend(), Backend::OpenAI(_)));
+        assert_eq!(provider.model(), "qwen2.5-coder");
+
+        let config = ProviderConfig {
+            kind: ProviderKind::Anthropic,
+            require_api_key: Some(false),
+            ..Default::default()
+        };
+        let provider = from_config(&config).unwrap();
+        assert!(matches!(provider.backend(), Backend::Anthropic(_)));
+    }
+
+    fn unreachable_ollama(model: &str) -> ProviderConfig {
+        ProviderConfig {
+            kind: ProviderKind::Ollama,
+            model: Some(model.to_string()),
+            api_base: Some("http://127.0.0.1:1".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chat_tries_fallbacks_in_order() {
+        let request = RequestConfig {
+            max_retries: 0,
+            ..Default::default()
+        };
+        let tried = Arc::new(Mutex::new(Vec::new()));
+        let seen = tried.clone();
+        let dir = tempfile::tempdir().unwrap();
+        let provider = Provider::from_config_with_store(
+            &ShellContext::default(),
+            &unreachable_ollama("first"),
+            &request,
+            Store::empty_in(dir.path()),
+        )
+        .unwrap()
+        .with_fallbacks(&[unreachable_ollama("second"), unreachable_ollama("third")])
+        .on_fallback(move |fallback| {
+            seen.lock().unwrap().push(format!(
+                "{}->{}",
+                fallback.failed_model, fallback.next_model
+            ));
+        });
+
+        let chat = ChatRequest {
+            messages: vec![ChatMessage::user("hi")],
+            temperature: 0.0,
+            max_tokens: None,
+            tools: Vec::new(),
+            tool_choice: None,
+        };
+        let err = provider.chat(&chat).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ClmError>(),
+            Some(ClmError::Network { .. })
+        ));
+        assert_eq!(
+            *tried.lock().unwrap(),
+            vec!["first->second".to_string(), "second->third".to_string()]
+        );
+        assert_eq!(provider.model(), "first");
+    }
+}
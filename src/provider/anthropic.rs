@@ -0,0 +1,581 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{
+    build_headers, http_client, retry_after, ChatRequest, ChatResponse, ModelInfo, Role, ToolCall,
+    Usage,
+};
+use crate::config::{get_api_key, ProviderConfig, ProviderKind, RequestConfig};
+use crate::credentials::Store;
+use crate::error::ClmError;
+use crate::shell::ShellContext;
+
+pub static DEFAULT_ANTHROPIC_API_BASE: &str = "https://api.anthropic.com";
+static ANTHROPIC_VERSION: &str = "2023-06-01";
+// The Messages API requires max_tokens on every request
+static DEFAULT_MAX_TOKENS: u16 = 1024;
+
+pub struct AnthropicProvider {
+    http_client: reqwest::Client,
+    base_url: String,
+    model: String,
+}
+
+#[derive(Serialize)]
+struct MessagesRequest<'a> {
+    model: &'a str,
+    max_tokens: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    messages: Vec<AnthropicMessage<'a>>,
+    temperature: f32,
+    stream: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<AnthropicTool<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<Value>,
+}
+
+#[derive(Serialize)]
+struct AnthropicMessage<'a> {
+    role: &'static str,
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+struct AnthropicTool<'a> {
+    name: &'a str,
+    description: &'a str,
+    input_schema: &'a Value,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamEvent {
+    MessageStart {
+        message: StreamMessage,
+    },
+    ContentBlockStart {
+        index: usize,
+        content_block: ContentBlock,
+    },
+    ContentBlockDelta {
+        index: usize,
+        delta: ContentDelta,
+    },
+    ContentBlockStop,
+    MessageDelta {
+        usage: Option<OutputUsage>,
+    },
+    MessageStop,
+    Ping,
+    Error {
+        error: ApiError,
+    },
+    /// Event types added to the API after this was written
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentBlock {
+    Text {
+        #[serde(default)]
+        text: String,
+    },
+    ToolUse {
+        name: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentDelta {
+    TextDelta {
+        text: String,
+    },
+    InputJsonDelta {
+        partial_json: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    #[serde(rename = "type")]
+    kind: String,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ApiError,
+}
+
+#[derive(Deserialize)]
+struct ModelsResponse {
+    data: Vec<AnthropicModel>,
+}
+
+#[derive(Deserialize)]
+struct AnthropicModel {
+    id: String,
+    created_at: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StreamMessage {
+    usage: Option<InputUsage>,
+}
+
+#[derive(Deserialize)]
+struct InputUsage {
+    #[serde(default)]
+    input_tokens: u32,
+    #[serde(default)]
+    output_tokens: u32,
+}
+
+/// The running output token count, sent with `message_delta`
+#[derive(Deserialize)]
+struct OutputUsage {
+    output_tokens: u32,
+}
+
+/// A content block being assembled from stream deltas.
+enum PartialBlock {
+    Text(String),
+    ToolUse { name: String, input_json: String },
+    Ignored,
+}
+
+impl AnthropicProvider {
+    pub fn new(
+        shell_context: &ShellContext,
+        config: &ProviderConfig,
+        request: &RequestConfig,
+        store: &Store,
+    ) -> Result<Self> {
+        let mut headers = build_headers(&config.headers)?;
+        if let Some(api_key) = get_api_key(store, &shell_context.shell_type, config)? {
+            headers.insert("x-api-key", api_key.parse()?);
+        }
+        headers.insert("anthropic-version", ANTHROPIC_VERSION.parse()?);
+
+        Ok(Self {
+            http_client: http_client(headers, request)?,
+            base_url: config
+                .api_base
+                .as_deref()
+                .unwrap_or(DEFAULT_ANTHROPIC_API_BASE)
+                .trim_end_matches('/')
+                .to_string(),
+            model: config.model().to_string(),
+        })
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub async fn chat(&self, request: &ChatRequest) -> Result<ChatResponse> {
+        let response = self
+            .send(
+                self.http_client
+                    .post(format!("{}/v1/messages", self.base_url))
+                    .json(&build_request(&self.model, request)),
+            )
+            .await?;
+
+        read_stream(response, &self.model).await
+    }
+
+    pub async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        let response = self
+            .send(self.http_client.get(format!("{}/v1/models", self.base_url)))
+            .await?;
+        let models: ModelsResponse = response
+            .json()
+            .await
+            .map_err(|err| ClmError::parse(ProviderKind::Anthropic, err))?;
+
+        Ok(models
+            .data
+            .into_iter()
+            .map(|model| ModelInfo {
+                name: model.id,
+                size_bytes: None,
+                modified: model
+                    .created_at
+                    .map(|created| created.chars().take(10).collect()),
+            })
+            .collect())
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        let response = request
+            .send()
+            .await
+            .map_err(|err| ClmError::network(ProviderKind::Anthropic, &self.base_url, err))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let delay = retry_after(response.headers());
+        let (code, message) = match response.json::<ErrorResponse>().await {
+            Ok(body) => (body.error.kind, body.error.message),
+            Err(_) => (String::new(), status.to_string()),
+        };
+        Err(ClmError::from_response(
+            ProviderKind::Anthropic,
+            &self.model,
+            Some(status.as_u16()),
+            &code,
+            message,
+        )
+        .with_retry_after(delay)
+        .into())
+    }
+}
+
+fn build_request<'a>(model: &'a str, request: &'a ChatRequest) -> MessagesRequest<'a> {
+    // The Messages API takes the system prompt as a separate field rather than a message
+    let system: Vec<&str> = request
+        .messages
+        .iter()
+        .filter(|message| message.role == Role::System)
+        .map(|message| message.content.as_str())
+        .collect();
+
+    MessagesRequest {
+        model,
+        max_tokens: request.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+        system: (!system.is_empty()).then(|| system.join("\n\n")),
+        messages: request
+            .messages
+            .iter()
+            .filter(|message| message.role != Role::System)
+            .map(|message| AnthropicMessage {
+                role: message.role.as_str(),
+                content: &message.content,
+            })
+            .collect(),
+        temperature: request.temperature,
+        stream: true,
+        tools: request
+            .tools
+            .iter()
+            .map(|tool| AnthropicTool {
+                name: &tool.name,
+                description: &tool.description,
+                input_schema: &tool.input_schema,
+            })
+            .collect(),
+        tool_choice: request
+            .tool_choice
+            .as_ref()
+            .map(|name| serde_json::json!({ "type": "tool", "name": name })),
+    }
+}
+
+async fn read_stream(mut response: reqwest::Response, model: &str) -> Result<ChatResponse> {
+    let mut events = SseEvents::default();
+    let mut blocks = Vec::new();
+    let mut usage = Usage::default();
+    let url = response.url().to_string();
+
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|err| ClmError::network(ProviderKind::Anthropic, &url, err))?
+    {
+        for data in events.push(&chunk) {
+            if apply_event(&data, &mut blocks, &mut usage, model)? {
+                return Ok(finish_blocks(blocks, usage, model));
+            }
+        }
+    }
+
+    Ok(finish_blocks(blocks, usage, model))
+}
+
+/// Applies one streamed event to the content blocks, returning true at the end of the message.
+fn apply_event(
+    data: &str,
+    blocks: &mut Vec<PartialBlock>,
+    usage: &mut Usage,
+    model: &str,
+) -> Result<bool> {
+    let event = serde_json::from_str::<StreamEvent>(data)
+        .map_err(|err| ClmError::parse(ProviderKind::Anthropic, err))?;
+    match event {
+        StreamEvent::ContentBlockStart {
+            index,
+            content_block,
+        } => {
+            let block = match content_block {
+                ContentBlock::Text { text } => PartialBlock::Text(text),
+                ContentBlock::ToolUse { name } => PartialBlock::ToolUse {
+                    name,
+                    input_json: String::new(),
+                },
+                ContentBlock::Other => PartialBlock::Ignored,
+            };
+            if index >= blocks.len() {
+                blocks.resize_with(index + 1, || PartialBlock::Ignored);
+            }
+            blocks[index] = block;
+        }
+        StreamEvent::ContentBlockDelta { index, delta } => match (blocks.get_mut(index), delta) {
+            (Some(PartialBlock::Text(text)), ContentDelta::TextDelta { text: delta }) => {
+                text.push_str(&delta)
+            }
+            (
+                Some(PartialBlock::ToolUse { input_json, .. }),
+                ContentDelta::InputJsonDelta { partial_json },
+            ) => input_json.push_str(&partial_json),
+            _ => {}
+        },
+        StreamEvent::Error { error } => {
+            return Err(ClmError::from_response(
+                ProviderKind::Anthropic,
+                model,
+                None,
+                &error.kind,
+                error.message,
+            )
+            .into())
+        }
+        StreamEvent::MessageStart { message } => {
+            if let Some(start) = message.usage {
+                usage.prompt_tokens = start.input_tokens;
+                usage.completion_tokens = start.output_tokens;
+            }
+        }
+        StreamEvent::MessageDelta { usage: Some(delta) } => {
+            usage.completion_tokens = delta.output_tokens
+        }
+        StreamEvent::MessageStop => return Ok(true),
+        StreamEvent::ContentBlockStop
+        | StreamEvent::MessageDelta { .. }
+        | StreamEvent::Ping
+        | StreamEvent::Unknown => {}
+    }
+
+    Ok(false)
+}
+
+fn finish_blocks(blocks: Vec<PartialBlock>, usage: Usage, model: &str) -> ChatResponse {
+    let mut text = String::new();
+    let mut tool_calls = Vec::new();
+
+    for block in blocks {
+        match block {
+            PartialBlock::Text(block_text) => text.push_str(&block_text),
+            PartialBlock::ToolUse { name, input_json } => {
+                // A tool called without arguments streams no input deltas at all
+                let input = if input_json.trim().is_empty() {
+                    Value::Object(Default::default())
+                } else {
+                    serde_json::from_str(&input_json).unwrap_or(Value::Null)
+                };
+                tool_calls.push(ToolCall { name, input });
+            }
+            PartialBlock::Ignored => {}
+        }
+    }
+
+    ChatResponse {
+        content: (!text.is_empty()).then_some(text),
+        tool_calls,
+        model: model.to_string(),
+        usage: Some(usage),
+    }
+}
+
+/// Splits a server-sent event stream into the `data` payloads of each event. Bytes are
+/// buffered until a whole line arrives, so a character split across chunks stays intact.
+#[derive(Default)]
+struct SseEvents {
+    buffer: Vec<u8>,
+    data: Vec<String>,
+}
+
+impl SseEvents {
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = Vec::new();
+        while let Some(newline) = self.buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=newline).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    events.push(self.data.join("\n"));
+                    self.data.clear();
+                }
+            } else if let Some(data) = line.strip_prefix("data:") {
+                self.data.push(data.trim_start().to_string());
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::{ChatMessage, ToolDefinition};
+
+    fn request(tools: Vec<ToolDefinition>, tool_choice: Option<String>) -> ChatRequest {
+        ChatRequest {
+            messages: vec![
+                ChatMessage::system("You are a shell assistant."),
+                ChatMessage::user("list files"),
+            ],
+            temperature: 0.3,
+            max_tokens: None,
+            tools,
+            tool_choice,
+        }
+    }
+
+    #[test]
+    fn test_build_request_separates_system_prompt() {
+        let request = request(Vec::new(), None);
+        let body = serde_json::to_value(build_request("claude", &request)).unwrap();
+
+        assert_eq!(body["system"], "You are a shell assistant.");
+        assert_eq!(body["messages"].as_array().unwrap().len(), 1);
+        assert_eq!(body["messages"][0]["role"], "user");
+        assert_eq!(body["max_tokens"], DEFAULT_MAX_TOKENS);
+        assert!(body.get("tools").is_none());
+    }
+
+    #[test]
+    fn test_build_request_with_forced_tool() {
+        let tool = ToolDefinition {
+            name: "suggest_command".to_string(),
+            description: "Suggest a command".to_string(),
+            input_schema: serde_json::json!({ "type": "object" }),
+        };
+        let request = request(vec![tool], Some("suggest_command".to_string()));
+        let body = serde_json::to_value(build_request("claude", &request)).unwrap();
+
+        assert_eq!(body["tools"][0]["name"], "suggest_command");
+        assert_eq!(body["tool_choice"]["type"], "tool");
+        assert_eq!(body["tool_choice"]["name"], "suggest_command");
+    }
+
+    #[test]
+    fn test_sse_events_across_chunks() {
+        let mut events = SseEvents::default();
+        assert!(events.push(b"event: ping\r\ndata: {\"type\"").is_empty());
+        assert_eq!(
+            events.push(b": \"ping\"}\r\n\r\nevent: message_stop\ndata: {}\n\n"),
+            vec!["{\"type\": \"ping\"}", "{}"]
+        );
+    }
+
+    #[test]
+    fn test_sse_events_keep_characters_split_across_chunks() {
+        let mut events = SseEvents::default();
+        let payload = "data: {\"text\": \"ls ü\"}\n\n".as_bytes();
+        let split = payload.iter().position(|byte| *byte == 0xc3).unwrap() + 1;
+        assert!(events.push(&payload[..split]).is_empty());
+        assert_eq!(events.push(&payload[split..]), vec!["{\"text\": \"ls ü\"}"]);
+    }
+
+    #[test]
+    fn test_unknown_stream_events_are_ignored() {
+        let mut blocks = Vec::new();
+        let done = apply_event(
+            r#"{"type":"content_block_annotation","index":0}"#,
+            &mut blocks,
+            &mut Usage::default(),
+            "claude",
+        )
+        .unwrap();
+        assert!(!done);
+    }
+
+    #[test]
+    fn test_stream_assembles_text_and_tool_use() {
+        let mut blocks = Vec::new();
+        let events = [
+            r#"{"type":"message_start","message":{"id":"msg_1","usage":{"input_tokens":12}}}"#,
+            r#"{"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Here you go"}}"#,
+            r#"{"type":"content_block_stop","index":0}"#,
+            r#"{"type":"content_block_start","index":1,"content_block":{"type":"tool_use","id":"toolu_1","name":"suggest_command","input":{}}}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"{\"command\": \"ls"}}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":" -la\"}"}}"#,
+            r#"{"type":"content_block_stop","index":1}"#,
+            r#"{"type":"message_delta","delta":{"stop_reason":"tool_use"},"usage":{"output_tokens":9}}"#,
+        ];
+        let mut usage = Usage::default();
+        for event in events {
+            assert!(!apply_event(event, &mut blocks, &mut usage, "claude").unwrap());
+        }
+        assert!(apply_event(
+            r#"{"type":"message_stop"}"#,
+            &mut blocks,
+            &mut usage,
+            "claude"
+        )
+        .unwrap());
+
+        let response = finish_blocks(blocks, usage, "claude");
+        assert_eq!(response.content.as_deref(), Some("Here you go"));
+        assert_eq!(
+            response.tool_calls,
+            vec![ToolCall {
+                name: "suggest_command".to_string(),
+                input: serde_json::json!({ "command": "ls -la" }),
+            }]
+        );
+        assert_eq!(
+            response.usage,
+            Some(Usage {
+                prompt_tokens: 12,
+                completion_tokens: 9,
+            })
+        );
+    }
+
+    #[test]
+    fn test_stream_error_event() {
+        let mut blocks = Vec::new();
+        let err = apply_event(
+            r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#,
+            &mut blocks,
+            &mut Usage::default(),
+            "claude",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("overloaded_error"));
+    }
+
+    #[test]
+    fn test_stream_error_event_is_classified() {
+        let mut blocks = Vec::new();
+        let err = apply_event(
+            r#"{"type":"error","error":{"type":"rate_limit_error","message":"Too many requests"}}"#,
+            &mut blocks,
+            &mut Usage::default(),
+            "claude",
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ClmError>(),
+            Some(ClmError::RateLimit { .. })
+        ));
+    }
+}
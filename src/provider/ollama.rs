@@ -0,0 +1,408 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::time::Duration;
+
+use super::{build_headers, http_client, retry_after, ChatRequest, ChatResponse, ModelInfo, Usage};
+use crate::config::{get_api_key, ProviderConfig, ProviderKind, RequestConfig};
+use crate::credentials::Store;
+use crate::error::ClmError;
+use crate::shell::ShellContext;
+
+pub static DEFAULT_OLLAMA_ADDRESS: &str = "http://localhost:11434";
+// Downloads of several gigabytes outlast the request timeout meant for chat
+const PULL_TIMEOUT: Duration = Duration::from_secs(6 * 60 * 60);
+
+pub struct OllamaProvider {
+    http_client: reqwest::Client,
+    base_url: String,
+    model: String,
+}
+
+#[derive(Serialize)]
+struct OllamaChatRequest<'a> {
+    model: &'a str,
+    messages: Vec<OllamaMessage<'a>>,
+    stream: bool,
+    options: OllamaOptions,
+}
+
+#[derive(Serialize)]
+struct OllamaMessage<'a> {
+    role: &'static str,
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+struct OllamaOptions {
+    temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_predict: Option<u16>,
+}
+
+#[derive(Deserialize)]
+struct OllamaChatChunk {
+    message: Option<OllamaChunkMessage>,
+    #[serde(default)]
+    done: bool,
+    error: Option<String>,
+    /// Left out when the prompt was already evaluated and cached
+    #[serde(default)]
+    prompt_eval_count: u32,
+    eval_count: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct OllamaChunkMessage {
+    #[serde(default)]
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct OllamaTags {
+    #[serde(default)]
+    models: Vec<OllamaModel>,
+}
+
+#[derive(Deserialize)]
+struct OllamaModel {
+    name: String,
+    size: Option<u64>,
+    modified_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PullProgress {
+    #[serde(default)]
+    pub status: String,
+    pub total: Option<u64>,
+    pub completed: Option<u64>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OllamaError {
+    error: String,
+}
+
+impl OllamaProvider {
+    pub fn new(
+        shell_context: &ShellContext,
+        config: &ProviderConfig,
+        request: &RequestConfig,
+        store: &Store,
+    ) -> Result<Self> {
+        let mut headers = build_headers(&config.headers)?;
+        // Ollama itself has no auth, but a reverse proxy in front of it might
+        if let Some(api_key) = get_api_key(store, &shell_context.shell_type, config)? {
+            headers.insert(
+                reqwest::header::AUTHORIZATION,
+                format!("Bearer {api_key}").parse()?,
+            );
+        }
+        Ok(Self {
+            http_client: http_client(headers, request)?,
+            base_url: resolve_base_url(config.api_base.as_deref(), env::var("OLLAMA_HOST").ok()),
+            model: config.model().to_string(),
+        })
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub async fn chat(&self, request: &ChatRequest) -> Result<ChatResponse> {
+        let body = OllamaChatRequest {
+            model: &self.model,
+            messages: request
+                .messages
+                .iter()
+                .map(|message| OllamaMessage {
+                    role: message.role.as_str(),
+                    content: &message.content,
+                })
+                .collect(),
+            stream: true,
+            options: OllamaOptions {
+                temperature: request.temperature,
+                num_predict: request.max_tokens,
+            },
+        };
+
+        let mut response = self
+            .send(self.http_client.post(self.url("/api/chat")).json(&body))
+            .await?;
+
+        let mut content = String::new();
+        let mut usage = None;
+        let mut lines = NdjsonLines::default();
+        'stream: while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|err| ClmError::network(ProviderKind::Ollama, &self.base_url, err))?
+        {
+            for line in lines.push(&chunk) {
+                if apply_chat_chunk(&line, &mut content, &mut usage, &self.model)? {
+                    break 'stream;
+                }
+            }
+        }
+        if let Some(line) = lines.finish() {
+            apply_chat_chunk(&line, &mut content, &mut usage, &self.model)?;
+        }
+
+        Ok(ChatResponse {
+            content: Some(content),
+            tool_calls: Vec::new(),
+            model: self.model.clone(),
+            usage,
+        })
+    }
+
+    pub async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        let response = self
+            .send(self.http_client.get(self.url("/api/tags")))
+            .await?;
+        let tags: OllamaTags = response
+            .json()
+            .await
+            .map_err(|err| ClmError::parse(ProviderKind::Ollama, err))?;
+
+        Ok(tags
+            .models
+            .into_iter()
+            .map(|model| ModelInfo {
+                name: model.name,
+                size_bytes: model.size,
+                modified: model
+                    .modified_at
+                    .map(|modified| modified.chars().take(10).collect()),
+            })
+            .collect())
+    }
+
+    pub async fn pull_model(
+        &self,
+        name: &str,
+        mut on_progress: impl FnMut(&PullProgress),
+    ) -> Result<()> {
+        let body = serde_json::json!({ "model": name, "stream": true });
+        let mut response = self
+            .send(
+                self.http_client
+                    .post(self.url("/api/pull"))
+                    .timeout(PULL_TIMEOUT)
+                    .json(&body),
+            )
+            .await?;
+
+        let mut lines = NdjsonLines::default();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|err| ClmError::network(ProviderKind::Ollama, &self.base_url, err))?
+        {
+            for line in lines.push(&chunk) {
+                let progress: PullProgress = serde_json::from_str(&line)
+                    .map_err(|err| ClmError::parse(ProviderKind::Ollama, err))?;
+                if let Some(error) = progress.error {
+                    bail!("Failed to pull {name}: {error}");
+                }
+                on_progress(&progress);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        let response = request
+            .send()
+            .await
+            .map_err(|err| ClmError::network(ProviderKind::Ollama, &self.base_url, err))?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let delay = retry_after(response.headers());
+        let message = response
+            .json::<OllamaError>()
+            .await
+            .map(|body| body.error)
+            .unwrap_or_else(|_| status.to_string());
+        Err(ClmError::from_response(
+            ProviderKind::Ollama,
+            &self.model,
+            Some(status.as_u16()),
+            "",
+            message,
+        )
+        .with_retry_after(delay)
+        .into())
+    }
+}
+
+/// Appends a streamed chat chunk to `content`, returning true once the response is complete.
+fn apply_chat_chunk(
+    line: &str,
+    content: &mut String,
+    usage: &mut Option<Usage>,
+    model: &str,
+) -> Result<bool> {
+    let chunk: OllamaChatChunk =
+        serde_json::from_str(line).map_err(|err| ClmError::parse(ProviderKind::Ollama, err))?;
+    if let Some(error) = chunk.error {
+        return Err(ClmError::from_response(ProviderKind::Ollama, model, None, "", error).into());
+    }
+    if let Some(message) = chunk.message {
+        content.push_str(&message.content);
+    }
+    if let Some(eval_count) = chunk.eval_count {
+        *usage = Some(Usage {
+            prompt_tokens: chunk.prompt_eval_count,
+            completion_tokens: eval_count,
+        });
+    }
+    Ok(chunk.done)
+}
+
+fn resolve_base_url(api_base: Option<&str>, ollama_host: Option<String>) -> String {
+    let address = api_base
+        .map(str::to_string)
+        .or(ollama_host.filter(|host| !host.trim().is_empty()))
+        .unwrap_or_else(|| DEFAULT_OLLAMA_ADDRESS.to_string());
+
+    // OLLAMA_HOST is commonly set without a scheme, e.g. "0.0.0.0:11434"
+    let address = if address.contains("://") {
+        address
+    } else {
+        format!("http://{address}")
+    };
+    address.trim_end_matches('/').to_string()
+}
+
+/// Splits a byte stream into newline-delimited JSON records.
+#[derive(Default)]
+struct NdjsonLines {
+    buffer: Vec<u8>,
+}
+
+impl NdjsonLines {
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut lines = Vec::new();
+        while let Some(newline) = self.buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=newline).collect();
+            let line = String::from_utf8_lossy(&line).trim().to_string();
+            if !line.is_empty() {
+                lines.push(line);
+            }
+        }
+        lines
+    }
+
+    fn finish(self) -> Option<String> {
+        let line = String::from_utf8_lossy(&self.buffer).trim().to_string();
+        (!line.is_empty()).then_some(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_base_url() {
+        assert_eq!(resolve_base_url(None, None), DEFAULT_OLLAMA_ADDRESS);
+        assert_eq!(
+            resolve_base_url(None, Some("0.0.0.0:11434".to_string())),
+            "http://0.0.0.0:11434"
+        );
+        assert_eq!(
+            resolve_base_url(Some("http://gpu-box:11434/"), Some("ignored:1".to_string())),
+            "http://gpu-box:11434"
+        );
+    }
+
+    #[test]
+    fn test_ndjson_lines_across_chunks() {
+        let mut lines = NdjsonLines::default();
+        assert!(lines.push(b"{\"a\":").is_empty());
+        assert_eq!(
+            lines.push(b"1}\n{\"b\":2}\n\n{\"c\""),
+            vec!["{\"a\":1}", "{\"b\":2}"]
+        );
+        assert_eq!(lines.push(b":3}"), Vec::<String>::new());
+        assert_eq!(lines.finish(), Some("{\"c\":3}".to_string()));
+    }
+
+    #[test]
+    fn test_apply_chat_chunk() {
+        let mut content = String::new();
+        let mut usage = None;
+        let done = apply_chat_chunk(
+            r#"{"message":{"role":"assistant","content":"ls"},"done":false}"#,
+            &mut content,
+            &mut usage,
+            "llama3.2",
+        )
+        .unwrap();
+        assert!(!done);
+        let done = apply_chat_chunk(
+            r#"{"message":{"role":"assistant","content":" -la"},"done":true,"prompt_eval_count":26,"eval_count":4}"#,
+            &mut content,
+            &mut usage,
+            "llama3.2",
+        )
+        .unwrap();
+        assert!(done);
+        assert_eq!(content, "ls -la");
+        assert_eq!(
+            usage,
+            Some(Usage {
+                prompt_tokens: 26,
+                completion_tokens: 4,
+            })
+        );
+    }
+
+    #[test]
+    fn test_apply_chat_chunk_error() {
+        let mut content = String::new();
+        let err = apply_chat_chunk(
+            r#"{"error":"model crashed"}"#,
+            &mut content,
+            &mut None,
+            "llama3.2",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("model crashed"));
+    }
+
+    #[tokio::test]
+    async fn test_connection_error_is_helpful() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = ProviderConfig {
+            api_base: Some("http://127.0.0.1:9".to_string()),
+            ..Default::default()
+        };
+        let provider = OllamaProvider::new(
+            &ShellContext::default(),
+            &config,
+            &RequestConfig::default(),
+            &Store::empty_in(dir.path()),
+        )
+        .unwrap();
+        let err = provider.list_models().await.unwrap_err();
+        let err = err.downcast_ref::<ClmError>().unwrap();
+        assert!(matches!(err, ClmError::Network { .. }));
+        assert!(err.hint().unwrap().contains("Is the daemon running?"));
+    }
+}
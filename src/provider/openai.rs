@@ -0,0 +1,314 @@
+use anyhow::{anyhow, Result};
+use async_openai::config::{AzureConfig, Config, OpenAIConfig};
+use async_openai::error::ApiError;
+use async_openai::types::{
+    ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestMessage,
+    ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessageArgs,
+    CreateChatCompletionRequestArgs, CreateChatCompletionResponse, ListModelResponse,
+};
+use serde::Deserialize;
+
+use super::{
+    build_headers, http_client, retry_after, ChatMessage, ChatRequest, ChatResponse, ModelInfo,
+    Role, Usage,
+};
+use crate::config::{get_api_key, ProviderConfig, ProviderKind, RequestConfig};
+use crate::credentials::Store;
+use crate::error::ClmError;
+use crate::shell::ShellContext;
+
+pub static DEFAULT_AZURE_API_VERSION: &str = "2024-06-01";
+
+/// Chat completions client, shared by the OpenAI API, compatible servers and Azure OpenAI.
+///
+/// async-openai only supplies the request types and endpoint configuration. Requests go
+/// through our own client because its errors drop the HTTP status and headers.
+pub struct OpenAIProvider<C: Config = OpenAIConfig> {
+    http_client: reqwest::Client,
+    config: C,
+    kind: ProviderKind,
+    model: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ApiError,
+}
+
+pub type AzureProvider = OpenAIProvider<AzureConfig>;
+
+impl OpenAIProvider<OpenAIConfig> {
+    pub fn new(
+        shell_context: &ShellContext,
+        provider: &ProviderConfig,
+        request: &RequestConfig,
+        store: &Store,
+    ) -> Result<Self> {
+        let mut config = OpenAIConfig::new();
+        if let Some(api_key) = get_api_key(store, &shell_context.shell_type, provider)? {
+            config = config.with_api_key(api_key);
+        }
+        if let Some(api_base) = &provider.api_base {
+            config = config.with_api_base(api_base.trim_end_matches('/'));
+        }
+
+        Ok(Self {
+            http_client: http_client(build_headers(&provider.headers)?, request)?,
+            config,
+            kind: ProviderKind::OpenAI,
+            model: provider.model().to_string(),
+        })
+    }
+
+    pub async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        let response: ListModelResponse = self
+            .send(self.http_client.get(self.config.url("/models")))
+            .await?
+            .json()
+            .await
+            .map_err(|err| ClmError::parse(self.kind, err))?;
+        let mut models: Vec<ModelInfo> = response
+            .data
+            .into_iter()
+            .map(|model| ModelInfo {
+                name: model.id,
+                size_bytes: None,
+                modified: None,
+            })
+            .collect();
+        models.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(models)
+    }
+}
+
+impl OpenAIProvider<AzureConfig> {
+    pub fn new_azure(
+        shell_context: &ShellContext,
+        provider: &ProviderConfig,
+        request: &RequestConfig,
+        store: &Store,
+    ) -> Result<Self> {
+        let api_base = provider.api_base.as_deref().ok_or_else(|| {
+            anyhow!(
+                "Azure OpenAI requires provider.api_base to be set to your resource endpoint, \
+                e.g. https://<resource>.openai.azure.com"
+            )
+        })?;
+        let deployment = provider.deployment.as_deref().unwrap_or(provider.model());
+
+        let mut config = AzureConfig::new()
+            .with_api_base(api_base.trim_end_matches('/'))
+            .with_deployment_id(deployment)
+            .with_api_version(
+                provider
+                    .api_version
+                    .as_deref()
+                    .unwrap_or(DEFAULT_AZURE_API_VERSION),
+            );
+        if let Some(api_key) = get_api_key(store, &shell_context.shell_type, provider)? {
+            config = config.with_api_key(api_key);
+        }
+
+        Ok(Self {
+            http_client: http_client(build_headers(&provider.headers)?, request)?,
+            config,
+            kind: ProviderKind::Azure,
+            // Answers are reported, cached and priced under the model, not the deployment
+            model: provider.model().to_string(),
+        })
+    }
+
+    /// Requests are routed by deployment, so that is the only model this client can reach
+    pub async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        Ok(vec![ModelInfo {
+            name: self.deployment(),
+            size_bytes: None,
+            modified: None,
+        }])
+    }
+
+    /// AzureConfig has no getter for it, but ends every URL it builds with the deployment.
+    fn deployment(&self) -> String {
+        let url = self.config.url("");
+        url.rsplit('/').next().unwrap_or_default().to_string()
+    }
+}
+
+impl<C: Config> OpenAIProvider<C> {
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub async fn chat(&self, request: &ChatRequest) -> Result<ChatResponse> {
+        let messages = request
+            .messages
+            .iter()
+            .map(to_openai_message)
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut args = CreateChatCompletionRequestArgs::default();
+        args.model(&self.model)
+            .messages(messages)
+            .temperature(request.temperature);
+        if let Some(max_tokens) = request.max_tokens {
+            args.max_tokens(max_tokens);
+        }
+
+        let response: CreateChatCompletionResponse = self
+            .send(
+                self.http_client
+                    .post(self.config.url("/chat/completions"))
+                    .json(&args.build()?),
+            )
+            .await?
+            .json()
+            .await
+            .map_err(|err| ClmError::parse(self.kind, err))?;
+
+        Ok(ChatResponse {
+            content: response
+                .choices
+                .into_iter()
+                .next()
+                .and_then(|choice| choice.message.content),
+            tool_calls: Vec::new(),
+            model: self.model.clone(),
+            usage: response.usage.map(|usage| Usage {
+                prompt_tokens: usage.prompt_tokens,
+                completion_tokens: usage.completion_tokens,
+            }),
+        })
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        let response = request
+            .query(&self.config.query())
+            .headers(self.config.headers())
+            .send()
+            .await
+            .map_err(|err| ClmError::network(self.kind, self.config.api_base(), err))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let delay = retry_after(response.headers());
+        let (code, message) = match response.json::<ErrorResponse>().await {
+            Ok(ErrorResponse { error }) => {
+                // Azure sends numeric codes such as 429 where OpenAI sends names
+                let code = match error.code {
+                    Some(serde_json::Value::String(code)) => code,
+                    Some(code) => code.to_string(),
+                    None => error.r#type.unwrap_or_default(),
+                };
+                (code, error.message)
+            }
+            Err(_) => (String::new(), status.to_string()),
+        };
+        Err(ClmError::from_response(
+            self.kind,
+            &self.model,
+            Some(status.as_u16()),
+            &code,
+            message,
+        )
+        .with_retry_after(delay)
+        .into())
+    }
+}
+
+fn to_openai_message(message: &ChatMessage) -> Result<ChatCompletionRequestMessage> {
+    let message = match message.role {
+        Role::System => ChatCompletionRequestSystemMessageArgs::default()
+            .content(message.content.clone())
+            .build()?
+            .into(),
+        Role::User => ChatCompletionRequestUserMessageArgs::default()
+            .content(message.content.clone())
+            .build()?
+            .into(),
+        Role::Assistant => ChatCompletionRequestAssistantMessageArgs::default()
+            .content(message.content.clone())
+            .build()?
+            .into(),
+    };
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_azure_requires_api_base() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = ProviderConfig {
+            kind: crate::config::ProviderKind::Azure,
+            require_api_key: Some(false),
+            ..Default::default()
+        };
+        let err = AzureProvider::new_azure(
+            &ShellContext::default(),
+            &provider,
+            &RequestConfig::default(),
+            &Store::empty_in(dir.path()),
+        )
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("provider.api_base"));
+    }
+
+    #[test]
+    fn test_azure_deployment_defaults_to_model() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = ProviderConfig {
+            kind: crate::config::ProviderKind::Azure,
+            api_base: Some("https://acme.openai.azure.com/".to_string()),
+            model: Some("gpt-4o".to_string()),
+            require_api_key: Some(false),
+            ..Default::default()
+        };
+        let azure = AzureProvider::new_azure(
+            &ShellContext::default(),
+            &provider,
+            &RequestConfig::default(),
+            &Store::empty_in(dir.path()),
+        )
+        .unwrap();
+        assert_eq!(azure.model(), "gpt-4o");
+        assert_eq!(azure.deployment(), "gpt-4o");
+
+        let provider = ProviderConfig {
+            deployment: Some("clm-prod".to_string()),
+            ..provider
+        };
+        let azure = AzureProvider::new_azure(
+            &ShellContext::default(),
+            &provider,
+            &RequestConfig::default(),
+            &Store::empty_in(dir.path()),
+        )
+        .unwrap();
+        assert_eq!(azure.model(), "gpt-4o");
+        assert_eq!(azure.deployment(), "clm-prod");
+        assert!(azure
+            .config
+            .url("/chat/completions")
+            .ends_with("/deployments/clm-prod/chat/completions"));
+    }
+
+    #[test]
+    fn test_to_openai_message_roles() {
+        let message = to_openai_message(&ChatMessage::system("be brief")).unwrap();
+        assert!(matches!(message, ChatCompletionRequestMessage::System(_)));
+
+        let message = to_openai_message(&ChatMessage::user("list files")).unwrap();
+        assert!(matches!(message, ChatCompletionRequestMessage::User(_)));
+
+        let message = to_openai_message(&ChatMessage::assistant("ls")).unwrap();
+        assert!(matches!(
+            message,
+            ChatCompletionRequestMessage::Assistant(_)
+        ));
+    }
+}
@@ -0,0 +1,157 @@
+use anyhow::Result;
+use rand::Rng;
+use std::future::Future;
+use std::time::Duration;
+
+use crate::error::ClmError;
+
+const BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(10);
+/// A longer `Retry-After` is reported to the user rather than waited out
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+pub type RetryListener = Box<dyn Fn(&RetryAttempt) + Send + Sync>;
+
+pub struct RetryAttempt<'a> {
+    /// 1 for the first retry
+    pub attempt: u32,
+    pub max_retries: u32,
+    pub delay: Duration,
+    pub error: &'a ClmError,
+}
+
+/// Runs `operation` until it succeeds, fails with an error that retrying can't fix, or
+/// `max_retries` retries have been used.
+pub async fn with_retries<T, F, Fut>(
+    max_retries: u32,
+    on_retry: Option<&RetryListener>,
+    mut operation: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempt = 0;
+    loop {
+        let err = match operation().await {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+
+        let Some(error) = err
+            .downcast_ref::<ClmError>()
+            .filter(|error| attempt < max_retries && error.is_retryable())
+        else {
+            return Err(err);
+        };
+        let Some(delay) = retry_delay(attempt, error.retry_after()) else {
+            return Err(err);
+        };
+
+        attempt += 1;
+        if let Some(on_retry) = on_retry {
+            on_retry(&RetryAttempt {
+                attempt,
+                max_retries,
+                delay,
+                error,
+            });
+        }
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Exponential backoff with jitter, or the server's `Retry-After` when it sent one.
+pub fn retry_delay(attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+    if let Some(retry_after) = retry_after {
+        return (retry_after <= MAX_RETRY_AFTER).then_some(retry_after);
+    }
+
+    let ceiling = BASE_DELAY
+        .saturating_mul(1 << attempt.min(16))
+        .min(MAX_DELAY);
+    // Always wait at least half the backoff so concurrent clients still spread out
+    let jitter = ceiling.mul_f64(rand::thread_rng().gen::<f64>() / 2.0);
+    Some(ceiling / 2 + jitter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProviderKind;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    fn error(status: u16) -> anyhow::Error {
+        ClmError::from_response(
+            ProviderKind::OpenAI,
+            "gpt-4o-mini",
+            Some(status),
+            "",
+            "failed",
+        )
+        .into()
+    }
+
+    #[test]
+    fn test_retry_delay_grows_and_is_capped() {
+        for attempt in 0..8 {
+            let delay = retry_delay(attempt, None).unwrap();
+            let ceiling = BASE_DELAY.saturating_mul(1 << attempt).min(MAX_DELAY);
+            assert!(delay >= ceiling / 2 && delay <= ceiling);
+        }
+    }
+
+    #[test]
+    fn test_retry_delay_honors_retry_after() {
+        assert_eq!(
+            retry_delay(0, Some(Duration::from_secs(7))),
+            Some(Duration::from_secs(7))
+        );
+        assert_eq!(retry_delay(0, Some(Duration::from_secs(3600))), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_with_retries_recovers() {
+        let calls = AtomicU32::new(0);
+        let retries = Arc::new(AtomicU32::new(0));
+        let seen = retries.clone();
+        let listener: RetryListener = Box::new(move |attempt| {
+            seen.store(attempt.attempt, Ordering::SeqCst);
+        });
+
+        let result = with_retries(3, Some(&listener), || async {
+            match calls.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(error(429)),
+                1 => Err(error(503)),
+                _ => Ok("done"),
+            }
+        })
+        .await;
+
+        assert_eq!(result.unwrap(), "done");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(retries.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_with_retries_gives_up() {
+        let calls = AtomicU32::new(0);
+        let result: Result<()> = with_retries(2, None, || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(error(503))
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        let calls = AtomicU32::new(0);
+        let result: Result<()> = with_retries(2, None, || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(error(401))
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,135 @@
+/// Explains why a command deserves a second confirmation, e.g. "deletes files recursively",
+/// or returns None. This is a heuristic over each part of a pipeline or command list, not a
+/// guarantee that anything else is safe.
+pub fn risk_reason(command: &str) -> Option<&'static str> {
+    let lowered = command.to_lowercase();
+    if pipes_download_to_shell(&lowered) {
+        return Some("runs a script downloaded from the internet");
+    }
+
+    lowered
+        .split(['|', ';', '&', '\n'])
+        .find_map(|segment| segment_risk(&segment.split_whitespace().collect::<Vec<_>>()))
+}
+
+fn segment_risk(words: &[&str]) -> Option<&'static str> {
+    let (&program, args) = words.split_first()?;
+    let program = program.rsplit(['/', '\\']).next().unwrap_or(program);
+    let has = |flag: &str| args.contains(&flag);
+    let has_short = |letter: char| {
+        args.iter()
+            .any(|arg| arg.starts_with('-') && !arg.starts_with("--") && arg[1..].contains(letter))
+    };
+
+    match program {
+        "sudo" | "doas" => return Some("runs with root privileges"),
+        "rm" if has_short('r') || has("--recursive") => return Some("deletes files recursively"),
+        "find" if has("-delete") || (has("-exec") && args.contains(&"rm")) => {
+            return Some("deletes the files it finds")
+        }
+        "dd" if args.iter().any(|arg| arg.starts_with("of=")) => {
+            return Some("overwrites data with dd")
+        }
+        "chmod" | "chown" | "chgrp" if has_short('r') || has("--recursive") => {
+            return Some("changes ownership or permissions recursively")
+        }
+        "git" => return git_risk(args),
+        "shutdown" | "reboot" | "halt" | "poweroff" => {
+            return Some("shuts down or restarts the machine")
+        }
+        "remove-item" if has("-recurse") => return Some("deletes files recursively"),
+        "rd" | "rmdir" if has("/s") => return Some("deletes files recursively"),
+        "del" | "erase" if has("/s") => return Some("deletes files recursively"),
+        "format" | "format-volume" | "diskpart" | "fdisk" | "parted" => {
+            return Some("modifies disks or partitions")
+        }
+        _ => {}
+    }
+
+    if program.starts_with("mkfs") {
+        return Some("modifies disks or partitions");
+    }
+    if words
+        .iter()
+        .any(|word| word.trim_start_matches('>').starts_with("/dev/sd"))
+    {
+        return Some("writes directly to a disk");
+    }
+    None
+}
+
+fn git_risk(args: &[&str]) -> Option<&'static str> {
+    let has = |flag: &str| args.contains(&flag);
+    match args.first() {
+        Some(&"push") if has("--force") || has("-f") || has("--force-with-lease") => {
+            Some("rewrites history on the remote")
+        }
+        Some(&"reset") if has("--hard") => Some("discards uncommitted changes"),
+        Some(&"clean")
+            if args
+                .iter()
+                .any(|arg| arg.starts_with('-') && arg.contains('f')) =>
+        {
+            Some("deletes untracked files")
+        }
+        _ => None,
+    }
+}
+
+fn pipes_download_to_shell(command: &str) -> bool {
+    let Some((source, sink)) = command.split_once('|') else {
+        return false;
+    };
+    let downloads = ["curl", "wget", "iwr", "invoke-webrequest"]
+        .iter()
+        .any(|tool| source.split_whitespace().any(|word| word == *tool));
+    let runs = ["sh", "bash", "zsh", "fish", "iex", "invoke-expression"]
+        .iter()
+        .any(|shell| {
+            sink.split_whitespace()
+                .any(|word| word.rsplit('/').next() == Some(*shell))
+        });
+    downloads && runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_risky_commands() {
+        for command in [
+            "rm -rf build",
+            "rm -r -f ./target",
+            "sudo apt-get remove nginx",
+            "find . -name '*.log' -delete",
+            "dd if=ubuntu.iso of=/dev/sdb bs=4M",
+            "chmod -R 777 .",
+            "git push --force origin main",
+            "git reset --hard HEAD~3",
+            "git clean -fdx",
+            "curl -fsSL https://example.com/install.sh | sh",
+            "ls && rm -rf node_modules",
+            "Remove-Item -Recurse -Force .\\build",
+            "mkfs.ext4 /dev/sdb1",
+        ] {
+            assert!(risk_reason(command).is_some(), "{command}");
+        }
+    }
+
+    #[test]
+    fn test_ordinary_commands() {
+        for command in [
+            "ls -la",
+            "rm notes.txt",
+            "find . -name '*.rs'",
+            "git push origin main",
+            "grep -r TODO src",
+            "curl -s https://example.com | jq .",
+            "curl -s https://example.com/key.pub | ssh host 'cat >> .ssh/authorized_keys'",
+            "chmod +x script.sh",
+        ] {
+            assert_eq!(risk_reason(command), None, "{command}");
+        }
+    }
+}
@@ -0,0 +1,487 @@
+//! Runs commands against a copy-on-write view of the working directory, so their effect can be
+//! reviewed before running them for real.
+
+use anyhow::{bail, Context, Result};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Mounts an overlay over the working directory, makes every other mount read-only and gives
+/// the command a private /tmp, then runs it from inside the overlay. Exits with 125 if any
+/// mount that isn't already read-only stays writable.
+/// Arguments: the working directory, the overlay's upper and work dirs, then the command.
+const SETUP_SCRIPT: &str = r#"
+mount -t overlay overlay -o "lowerdir=$1,upperdir=$2,workdir=$3,userxattr" "$1" || exit 125
+awk '{ print $5, $6 }' /proc/self/mountinfo | while read -r mount_point options; do
+    # mountinfo escapes spaces and other special characters as octal
+    mount_point=$(printf '%b' "$mount_point")
+    case "$mount_point" in
+        # Mounts below the working directory are hidden by the overlay
+        "$1" | "$1"/*) continue ;;
+    esac
+    error=$(mount -o remount,bind,ro "$mount_point" 2>&1) && continue
+    case ",$options," in
+        *,ro,*) ;;
+        *) echo "$error" >&2; exit 125 ;;
+    esac
+done || exit 125
+case "$1" in
+    /tmp | /tmp/*) ;;
+    *) mount -t tmpfs tmpfs /tmp ;;
+esac
+cd "$1" || exit 125
+shift 3
+exec "$@"
+"#;
+
+pub struct Sandbox {
+    cwd: PathBuf,
+    root: PathBuf,
+    allow_network: bool,
+}
+
+impl Sandbox {
+    /// Prepares a sandbox for `cwd` and checks that this system lets unprivileged users create one.
+    pub fn new(cwd: &Path, allow_network: bool) -> Result<Self> {
+        if !cfg!(target_os = "linux") {
+            bail!("Sandboxed execution is only supported on Linux");
+        }
+
+        let name = format!(
+            "clm-sandbox-{}-{}",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        );
+        // The overlay's upper directory can't live inside the directory it covers
+        let root = [
+            Some(std::env::temp_dir()),
+            directories::BaseDirs::new().map(|dirs| dirs.cache_dir().to_path_buf()),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|base| base.join(&name))
+        .find(|root| !root.starts_with(cwd))
+        .context("No place outside the current directory to keep the sandbox's changes")?;
+
+        let sandbox = Self {
+            cwd: cwd.to_path_buf(),
+            root,
+            allow_network,
+        };
+        fs::create_dir_all(sandbox.upper_dir())?;
+        fs::create_dir_all(sandbox.root.join("work"))?;
+
+        let check = sandbox
+            .command("true", &[])
+            .output()
+            .context("Sandboxed execution needs the unshare command from util-linux")?;
+        if !check.status.success() {
+            bail!(
+                "Failed to create a sandbox: {}\nThis needs unprivileged user namespaces and overlay mounts (Linux 5.11 or later).",
+                String::from_utf8_lossy(&check.stderr).trim()
+            );
+        }
+        Ok(sandbox)
+    }
+
+    fn upper_dir(&self) -> PathBuf {
+        self.root.join("upper")
+    }
+
+    /// A command that runs `program` with `args` inside the sandbox.
+    pub fn command(&self, program: impl AsRef<OsStr>, args: &[&str]) -> Command {
+        let mut command = Command::new("unshare");
+        command.args(["--user", "--map-root-user", "--mount"]);
+        if !self.allow_network {
+            command.arg("--net");
+        }
+        command
+            .args(["sh", "-c", SETUP_SCRIPT, "sh"])
+            .arg(&self.cwd)
+            .arg(self.upper_dir())
+            .arg(self.root.join("work"))
+            .arg(program)
+            .args(args);
+        command
+    }
+
+    /// What the commands run so far would have changed in the working directory.
+    pub fn changes(&self) -> Result<Vec<Change>> {
+        let mut changes = Vec::new();
+        collect_changes(&self.cwd, &self.upper_dir(), Path::new(""), &mut changes)?;
+        Ok(changes)
+    }
+
+    pub fn cwd(&self) -> &Path {
+        &self.cwd
+    }
+
+    pub fn changed_file(&self, path: &Path) -> PathBuf {
+        self.upper_dir().join(path)
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        // The overlay leaves its work directory unreadable
+        let _ = make_accessible(&self.root);
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+#[cfg(unix)]
+fn make_accessible(dir: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+    for entry in fs::read_dir(dir)?.flatten() {
+        if entry.file_type()?.is_dir() {
+            make_accessible(&entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn make_accessible(_dir: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Deleted,
+    Permissions { from: u32, to: u32 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub kind: ChangeKind,
+    /// Relative to the working directory
+    pub path: PathBuf,
+}
+
+/// Compares the overlay's upper directory with the directory it covered.
+fn collect_changes(
+    lower: &Path,
+    upper: &Path,
+    relative: &Path,
+    changes: &mut Vec<Change>,
+) -> Result<()> {
+    let upper_dir = upper.join(relative);
+    let mut entries: Vec<_> = fs::read_dir(&upper_dir)?.flatten().collect();
+    entries.sort_by_key(|entry| entry.file_name());
+
+    if is_opaque(&upper_dir) {
+        // The directory was replaced, so everything it held before is gone
+        for entry in fs::read_dir(lower.join(relative))
+            .into_iter()
+            .flatten()
+            .flatten()
+        {
+            if !upper_dir.join(entry.file_name()).exists() {
+                changes.push(Change {
+                    kind: ChangeKind::Deleted,
+                    path: relative.join(entry.file_name()),
+                });
+            }
+        }
+    }
+
+    for entry in entries {
+        let path = relative.join(entry.file_name());
+        let metadata = entry.metadata()?;
+        let kind = match fs::symlink_metadata(lower.join(&path)).ok() {
+            _ if is_whiteout(&metadata) => Some(ChangeKind::Deleted),
+            None => Some(ChangeKind::Added),
+            Some(before) if before.is_dir() && metadata.is_dir() => {
+                collect_changes(lower, upper, &path, changes)?;
+                permissions_change(&before, &metadata)
+            }
+            Some(before) if same_content(&lower.join(&path), &entry.path())? => {
+                permissions_change(&before, &metadata)
+            }
+            Some(_) => Some(ChangeKind::Modified),
+        };
+
+        if let Some(kind) = kind {
+            changes.push(Change { kind, path });
+        }
+    }
+    Ok(())
+}
+
+fn same_content(before: &Path, after: &Path) -> Result<bool> {
+    let (before_meta, after_meta) = (fs::symlink_metadata(before)?, fs::symlink_metadata(after)?);
+    if before_meta.file_type().is_symlink() || after_meta.file_type().is_symlink() {
+        return Ok(fs::read_link(before).ok() == fs::read_link(after).ok());
+    }
+    if before_meta.is_dir() != after_meta.is_dir() || before_meta.len() != after_meta.len() {
+        return Ok(false);
+    }
+    Ok(fs::read(before)? == fs::read(after)?)
+}
+
+#[cfg(unix)]
+fn permissions_change(before: &fs::Metadata, after: &fs::Metadata) -> Option<ChangeKind> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let (from, to) = (
+        before.permissions().mode() & 0o7777,
+        after.permissions().mode() & 0o7777,
+    );
+    (from != to).then_some(ChangeKind::Permissions { from, to })
+}
+
+#[cfg(not(unix))]
+fn permissions_change(_before: &fs::Metadata, _after: &fs::Metadata) -> Option<ChangeKind> {
+    None
+}
+
+/// Overlayfs records a deletion as a 0:0 character device.
+#[cfg(unix)]
+fn is_whiteout(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+    metadata.file_type().is_char_device() && metadata.rdev() == 0
+}
+
+#[cfg(not(unix))]
+fn is_whiteout(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+/// Overlayfs marks a directory that replaced an existing one with an `opaque` attribute.
+#[cfg(target_os = "linux")]
+fn is_opaque(dir: &Path) -> bool {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let Ok(path) = CString::new(dir.as_os_str().as_bytes()) else {
+        return false;
+    };
+    let mut value = [0u8; 1];
+    // SAFETY: both names are NUL-terminated and the buffer length matches `value`
+    let len = unsafe {
+        libc::getxattr(
+            path.as_ptr(),
+            c"user.overlay.opaque".as_ptr(),
+            value.as_mut_ptr().cast(),
+            value.len(),
+        )
+    };
+    len == 1 && value[0] == b'y'
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_opaque(_dir: &Path) -> bool {
+    false
+}
+
+pub fn print_changes(sandbox: &Sandbox, changes: &[Change]) {
+    if changes.is_empty() {
+        println!(
+            "{}",
+            console::style(format!(
+                "The command changed nothing in {}.",
+                sandbox.cwd().display()
+            ))
+            .dim()
+        );
+        return;
+    }
+
+    println!("\n{}", console::style("Changes in the sandbox:").cyan());
+    for change in changes {
+        let path = change.path.display();
+        match change.kind {
+            ChangeKind::Added => println!("  {}", console::style(format!("+ {path}")).green()),
+            ChangeKind::Deleted => println!("  {}", console::style(format!("- {path}")).red()),
+            ChangeKind::Modified => println!("  {}", console::style(format!("~ {path}")).yellow()),
+            ChangeKind::Permissions { from, to } => println!(
+                "  {}",
+                console::style(format!("~ {path} (mode {from:o} → {to:o})")).yellow()
+            ),
+        }
+    }
+
+    for change in changes
+        .iter()
+        .filter(|change| change.kind == ChangeKind::Modified)
+    {
+        print_diff(sandbox, &change.path);
+    }
+    println!();
+}
+
+fn print_diff(sandbox: &Sandbox, path: &Path) {
+    let before = sandbox.cwd().join(path);
+    let after = sandbox.changed_file(path);
+    if !before.is_file() || !after.is_file() {
+        return;
+    }
+    let Ok(output) = Command::new("diff")
+        .arg("-u")
+        .arg("--label")
+        .arg(format!("a/{}", path.display()))
+        .arg("--label")
+        .arg(format!("b/{}", path.display()))
+        .arg(&before)
+        .arg(&after)
+        .output()
+    else {
+        return;
+    };
+
+    println!();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let styled = if line.starts_with("+++") || line.starts_with("---") {
+            console::style(line).bold()
+        } else if line.starts_with('+') {
+            console::style(line).green()
+        } else if line.starts_with('-') {
+            console::style(line).red()
+        } else if line.starts_with("@@") {
+            console::style(line).cyan()
+        } else {
+            console::style(line)
+        };
+        println!("{styled}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn test_changes_compare_upper_with_lower() {
+        let lower = tempfile::tempdir().unwrap();
+        let upper = tempfile::tempdir().unwrap();
+        write(&lower.path().join("same.txt"), "same");
+        write(&lower.path().join("src/main.rs"), "fn main() {}");
+        write(&lower.path().join("touched.txt"), "unchanged");
+        write(&upper.path().join("same.txt"), "same");
+        write(&upper.path().join("src/main.rs"), "fn main() { run() }");
+        write(&upper.path().join("src/new.rs"), "");
+        write(&upper.path().join("touched.txt"), "unchanged");
+        write(&upper.path().join("notes/todo.md"), "- test");
+
+        let mut changes = Vec::new();
+        collect_changes(lower.path(), upper.path(), Path::new(""), &mut changes).unwrap();
+        assert_eq!(
+            changes,
+            [
+                Change {
+                    kind: ChangeKind::Added,
+                    path: PathBuf::from("notes"),
+                },
+                Change {
+                    kind: ChangeKind::Modified,
+                    path: PathBuf::from("src/main.rs"),
+                },
+                Change {
+                    kind: ChangeKind::Added,
+                    path: PathBuf::from("src/new.rs"),
+                },
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_permission_changes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let lower = tempfile::tempdir().unwrap();
+        let upper = tempfile::tempdir().unwrap();
+        write(&lower.path().join("run.sh"), "echo hi");
+        write(&upper.path().join("run.sh"), "echo hi");
+        fs::set_permissions(
+            lower.path().join("run.sh"),
+            fs::Permissions::from_mode(0o644),
+        )
+        .unwrap();
+        fs::set_permissions(
+            upper.path().join("run.sh"),
+            fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+
+        let mut changes = Vec::new();
+        collect_changes(lower.path(), upper.path(), Path::new(""), &mut changes).unwrap();
+        assert_eq!(
+            changes[0].kind,
+            ChangeKind::Permissions {
+                from: 0o644,
+                to: 0o755
+            }
+        );
+    }
+
+    #[test]
+    fn test_sandboxed_command_leaves_directory_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        write(&dir.path().join("keep.txt"), "keep");
+        write(&dir.path().join("edit.txt"), "before\n");
+        let Ok(sandbox) = Sandbox::new(dir.path(), false) else {
+            // User namespaces are often disabled in containers and CI
+            return;
+        };
+
+        let status = sandbox
+            .command(
+                "sh",
+                &[
+                    "-c",
+                    "rm keep.txt && echo after > edit.txt && touch new.txt",
+                ],
+            )
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert!(dir.path().join("keep.txt").exists());
+        assert!(!dir.path().join("new.txt").exists());
+
+        let kinds: Vec<(String, ChangeKind)> = sandbox
+            .changes()
+            .unwrap()
+            .into_iter()
+            .map(|change| (change.path.display().to_string(), change.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("edit.txt".to_string(), ChangeKind::Modified),
+                ("keep.txt".to_string(), ChangeKind::Deleted),
+                ("new.txt".to_string(), ChangeKind::Added),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sandbox_cannot_write_outside_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let Ok(sandbox) = Sandbox::new(dir.path(), false) else {
+            return;
+        };
+        // Outside /tmp, which the sandbox replaces with its own
+        let outside = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("target")
+            .join(format!("clm-sandbox-probe-{}", std::process::id()));
+
+        let status = sandbox
+            .command("touch", &[&outside.to_string_lossy()])
+            .status()
+            .unwrap();
+        assert!(!status.success());
+        assert!(!outside.exists());
+    }
+}
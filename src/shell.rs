@@ -1,22 +1,30 @@
+use std::path::{Path, PathBuf};
 use std::{env, fmt};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ShellContext {
     pub shell_type: ShellType,
     pub os: SupportedOperatingSystem,
+    /// The shell binary from $SHELL or %COMSPEC%, when known
+    pub shell_path: Option<PathBuf>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ShellType {
     Zsh,
     Bash,
     Fish,
+    Nushell,
+    Xonsh,
+    Elvish,
+    Dash,
+    Ksh,
     PowerShell,
     Cmd,
     Unknown(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum SupportedOperatingSystem {
     Mac,
     Linux,
@@ -31,6 +39,11 @@ impl fmt::Display for ShellType {
             ShellType::Zsh => write!(f, "zsh"),
             ShellType::Bash => write!(f, "bash"),
             ShellType::Fish => write!(f, "fish"),
+            ShellType::Nushell => write!(f, "nushell"),
+            ShellType::Xonsh => write!(f, "xonsh"),
+            ShellType::Elvish => write!(f, "elvish"),
+            ShellType::Dash => write!(f, "dash"),
+            ShellType::Ksh => write!(f, "ksh"),
             ShellType::PowerShell => write!(f, "PowerShell"),
             ShellType::Cmd => write!(f, "Command Prompt"),
             ShellType::Unknown(shell) => write!(f, "{}", shell),
@@ -50,9 +63,11 @@ impl fmt::Display for SupportedOperatingSystem {
 
 impl Default for ShellContext {
     fn default() -> Self {
+        let (shell_type, shell_path) = detect();
         Self {
-            shell_type: detect_shell(),
+            shell_type,
             os: detect_os(),
+            shell_path,
         }
     }
 }
@@ -72,32 +87,50 @@ pub fn detect_os() -> SupportedOperatingSystem {
 }
 
 pub fn detect_shell() -> ShellType {
+    detect().0
+}
+
+fn detect() -> (ShellType, Option<PathBuf>) {
     //For windows, check PowerShell and CMD
     if cfg!(windows) {
         if env::var("PSModulePath").is_ok() {
-            return ShellType::PowerShell;
+            return (ShellType::PowerShell, None);
         }
         if let Ok(comspec) = env::var("COMSPEC") {
             if comspec.to_lowercase().contains("cmd.exe") {
-                return ShellType::Cmd;
+                return (ShellType::Cmd, Some(PathBuf::from(comspec)));
             }
         }
     }
 
     // For unix based systems, check the $SHELL env var which will contain the console type in the path
-    if let Ok(shell_path) = env::var("SHELL") {
-        let shell_path = shell_path.to_lowercase();
-        if shell_path.contains("zsh") {
-            return ShellType::Zsh;
-        } else if shell_path.contains("bash") {
-            return ShellType::Bash;
-        } else if shell_path.contains("fish") {
-            return ShellType::Fish;
-        } else {
-            return ShellType::Unknown(shell_path);
-        }
-    } else {
-        ShellType::Bash
+    match env::var("SHELL") {
+        Ok(shell_path) => (shell_type_of(&shell_path), Some(PathBuf::from(shell_path))),
+        Err(_) => (ShellType::Bash, None),
+    }
+}
+
+/// Works out the shell from the name of its binary, such as `/usr/bin/zsh` or `nu.exe`.
+pub fn shell_type_of(shell_path: &str) -> ShellType {
+    let shell_path = shell_path.to_lowercase();
+    let name = Path::new(&shell_path)
+        .file_stem()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    match name {
+        "zsh" => ShellType::Zsh,
+        "bash" => ShellType::Bash,
+        "fish" => ShellType::Fish,
+        "nu" | "nushell" => ShellType::Nushell,
+        "xonsh" => ShellType::Xonsh,
+        "elvish" => ShellType::Elvish,
+        "dash" => ShellType::Dash,
+        "ksh" | "ksh93" | "mksh" | "oksh" => ShellType::Ksh,
+        "pwsh" | "powershell" => ShellType::PowerShell,
+        _ if shell_path.contains("zsh") => ShellType::Zsh,
+        _ if shell_path.contains("bash") => ShellType::Bash,
+        _ if shell_path.contains("fish") => ShellType::Fish,
+        _ => ShellType::Unknown(shell_path),
     }
 }
 
@@ -111,6 +144,7 @@ mod tests {
         assert_eq!(ShellType::Bash.to_string(), "bash");
         assert_eq!(ShellType::Zsh.to_string(), "zsh");
         assert_eq!(ShellType::Fish.to_string(), "fish");
+        assert_eq!(ShellType::Nushell.to_string(), "nushell");
         assert_eq!(ShellType::PowerShell.to_string(), "PowerShell");
         assert_eq!(ShellType::Cmd.to_string(), "Command Prompt");
         assert_eq!(ShellType::Unknown("custom".to_string()).to_string(), "custom");
@@ -142,6 +176,21 @@ mod tests {
         assert!(!context.os.to_string().is_empty());
     }
 
+    #[test]
+    fn test_shell_type_of() {
+        assert!(matches!(shell_type_of("/usr/local/bin/zsh"), ShellType::Zsh));
+        assert!(matches!(shell_type_of("/usr/bin/nu"), ShellType::Nushell));
+        assert!(matches!(shell_type_of("/usr/bin/xonsh"), ShellType::Xonsh));
+        assert!(matches!(shell_type_of("/usr/bin/elvish"), ShellType::Elvish));
+        assert!(matches!(shell_type_of("/bin/dash"), ShellType::Dash));
+        assert!(matches!(shell_type_of("/bin/mksh"), ShellType::Ksh));
+        assert!(matches!(shell_type_of("/opt/bin/bash-5.2"), ShellType::Bash));
+        assert!(matches!(
+            shell_type_of("/bin/tcsh"),
+            ShellType::Unknown(path) if path == "/bin/tcsh"
+        ));
+    }
+
     #[test]
     fn test_detect_shell_with_env() {
         // Store original value
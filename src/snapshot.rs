@@ -0,0 +1,346 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::{get_data_dir, SnapshotConfig};
+use crate::preview::Preview;
+
+static SNAPSHOTS_DIR: &str = "snapshots";
+static MANIFEST_FILE: &str = "manifest.json";
+static FILES_DIR: &str = "files";
+
+/// What a snapshot saved before a command ran, so `clm undo` can put it back.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub id: String,
+    pub timestamp: DateTime<Utc>,
+    pub cwd: PathBuf,
+    pub command: String,
+    /// Paths that existed before the command, each copied to `files/<index>`
+    pub saved: Vec<PathBuf>,
+    /// Paths the command was expected to create, which undo removes again
+    pub created: Vec<PathBuf>,
+}
+
+/// Copies of the files commands were about to change, newest last, in the data directory.
+pub struct Snapshots {
+    dir: PathBuf,
+    config: SnapshotConfig,
+}
+
+impl Snapshots {
+    pub fn new(dir: impl Into<PathBuf>, config: &SnapshotConfig) -> Self {
+        Self {
+            dir: dir.into(),
+            config: config.clone(),
+        }
+    }
+
+    pub fn open_default(config: &SnapshotConfig) -> Result<Self> {
+        Ok(Self::new(get_data_dir()?.join(SNAPSHOTS_DIR), config))
+    }
+
+    /// Saves the paths that the previewed command would change. Returns None when it touches
+    /// nothing that exists or would be created.
+    pub fn take(&self, command: &str, cwd: &Path, preview: &Preview) -> Result<Option<Manifest>> {
+        if preview.unresolved {
+            bail!("some of the paths it changes depend on a cd or on variables");
+        }
+        let operations = &preview.operations;
+        let mut saved: Vec<PathBuf> = Vec::new();
+        let mut targets: Vec<&PathBuf> = operations
+            .iter()
+            .flat_map(|operation| &operation.targets)
+            .collect();
+        // Parents sort before their contents, which a saved parent already covers
+        targets.sort();
+        for target in targets {
+            if !saved.iter().any(|parent| target.starts_with(parent)) {
+                saved.push(target.clone());
+            }
+        }
+        let mut created: Vec<PathBuf> = operations
+            .iter()
+            .flat_map(|operation| &operation.created)
+            .filter(|path| fs::symlink_metadata(path).is_err())
+            .cloned()
+            .collect();
+        created.sort();
+        created.dedup();
+        if saved.is_empty() && created.is_empty() {
+            return Ok(None);
+        }
+
+        let bytes: u64 = operations.iter().map(|operation| operation.bytes).sum();
+        if operations.iter().any(|operation| operation.truncated)
+            || bytes > self.config.max_size_mb * 1024 * 1024
+        {
+            bail!(
+                "the files it changes are larger than snapshots.max_size_mb ({} MiB)",
+                self.config.max_size_mb
+            );
+        }
+
+        let timestamp = Utc::now();
+        let manifest = Manifest {
+            id: timestamp.format("%Y%m%dT%H%M%S%.6f").to_string(),
+            timestamp,
+            cwd: cwd.to_path_buf(),
+            command: command.to_string(),
+            saved,
+            created,
+        };
+        let dir = self.dir.join(&manifest.id);
+        let files = dir.join(FILES_DIR);
+        fs::create_dir_all(&files)
+            .with_context(|| format!("Failed to create {}", files.display()))?;
+        let copied = manifest
+            .saved
+            .iter()
+            .enumerate()
+            .try_for_each(|(index, path)| copy_path(path, &files.join(index.to_string())));
+        if let Err(err) = copied {
+            let _ = fs::remove_dir_all(&dir);
+            return Err(err).context("Failed to copy files into the snapshot");
+        }
+        fs::write(
+            dir.join(MANIFEST_FILE),
+            serde_json::to_string_pretty(&manifest)?,
+        )?;
+
+        self.prune()?;
+        Ok(Some(manifest))
+    }
+
+    /// All snapshots, oldest first.
+    pub fn list(&self) -> Result<Vec<Manifest>> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return Ok(Vec::new());
+        };
+        let mut manifests: Vec<Manifest> = entries
+            .flatten()
+            .filter_map(|entry| fs::read_to_string(entry.path().join(MANIFEST_FILE)).ok())
+            .filter_map(|manifest| serde_json::from_str(&manifest).ok())
+            .collect();
+        manifests.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(manifests)
+    }
+
+    /// Puts every saved path back as it was and removes the paths the command created, then
+    /// deletes the snapshot.
+    pub fn restore(&self, manifest: &Manifest) -> Result<()> {
+        for path in manifest.created.iter().chain(&manifest.saved) {
+            remove_path(path).with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+
+        let files = self.dir.join(&manifest.id).join(FILES_DIR);
+        for (index, path) in manifest.saved.iter().enumerate() {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            copy_path(&files.join(index.to_string()), path)
+                .with_context(|| format!("Failed to restore {}", path.display()))?;
+        }
+        fs::remove_dir_all(self.dir.join(&manifest.id))?;
+        Ok(())
+    }
+
+    fn prune(&self) -> Result<()> {
+        let manifests = self.list()?;
+        let excess = manifests.len().saturating_sub(self.config.keep);
+        for manifest in &manifests[..excess] {
+            fs::remove_dir_all(self.dir.join(&manifest.id))?;
+        }
+        Ok(())
+    }
+}
+
+fn copy_path(from: &Path, to: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(from)?;
+    if metadata.file_type().is_symlink() {
+        return copy_symlink(from, to);
+    }
+    if !metadata.is_dir() {
+        return fs::copy(from, to).map(|_| ());
+    }
+
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        copy_path(&entry.path(), &to.join(entry.file_name()))?;
+    }
+    fs::set_permissions(to, metadata.permissions())
+}
+
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(from)?, to)
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    fs::copy(from, to).map(|_| ())
+}
+
+fn remove_path(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn print_manifest(manifest: &Manifest) {
+    println!(
+        "{}  {}",
+        manifest
+            .timestamp
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M"),
+        console::style(&manifest.command).bold()
+    );
+    for path in &manifest.saved {
+        println!(
+            "    {}",
+            console::style(format!("restore {}", path.display())).dim()
+        );
+    }
+    for path in &manifest.created {
+        println!(
+            "    {}",
+            console::style(format!("remove {}", path.display())).dim()
+        );
+    }
+}
+
+pub fn print_saved(manifest: &Manifest) {
+    println!(
+        "{}",
+        console::style(format!(
+            "Saved a snapshot of {} path(s). Run `clm undo` to revert this command.",
+            manifest.saved.len() + manifest.created.len()
+        ))
+        .dim()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preview::preview;
+    use crate::shell::ShellType;
+
+    fn snapshots(dir: &Path, keep: usize) -> Snapshots {
+        Snapshots::new(
+            dir.join(SNAPSHOTS_DIR),
+            &SnapshotConfig {
+                keep,
+                ..SnapshotConfig::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_undo_restores_deleted_and_moved_files() {
+        let data = tempfile::tempdir().unwrap();
+        let cwd = tempfile::tempdir().unwrap();
+        let cwd = cwd.path();
+        fs::create_dir(cwd.join("src")).unwrap();
+        fs::write(cwd.join("src/lib.rs"), "pub fn lib() {}").unwrap();
+        fs::write(cwd.join("notes.txt"), "notes").unwrap();
+
+        let command = "rm -r src && mv notes.txt todo.txt";
+        let preview = preview(command, &ShellType::Bash, cwd);
+        let snapshots = snapshots(data.path(), 10);
+        let manifest = snapshots.take(command, cwd, &preview).unwrap().unwrap();
+        assert_eq!(manifest.saved, [cwd.join("notes.txt"), cwd.join("src")]);
+        assert_eq!(manifest.created, [cwd.join("todo.txt")]);
+
+        fs::remove_dir_all(cwd.join("src")).unwrap();
+        fs::rename(cwd.join("notes.txt"), cwd.join("todo.txt")).unwrap();
+
+        snapshots
+            .restore(&snapshots.list().unwrap().pop().unwrap())
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(cwd.join("src/lib.rs")).unwrap(),
+            "pub fn lib() {}"
+        );
+        assert_eq!(fs::read_to_string(cwd.join("notes.txt")).unwrap(), "notes");
+        assert!(!cwd.join("todo.txt").exists());
+        assert!(snapshots.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_commands_that_touch_nothing_take_no_snapshot() {
+        let data = tempfile::tempdir().unwrap();
+        let cwd = tempfile::tempdir().unwrap();
+        let preview = preview("ls -la", &ShellType::Bash, cwd.path());
+        let snapshots = snapshots(data.path(), 10);
+        assert_eq!(
+            snapshots.take("ls -la", cwd.path(), &preview).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_unresolved_paths_take_no_snapshot() {
+        let data = tempfile::tempdir().unwrap();
+        let cwd = tempfile::tempdir().unwrap();
+        fs::create_dir(cwd.path().join("build")).unwrap();
+        fs::write(cwd.path().join("notes.txt"), "notes").unwrap();
+        let snapshots = snapshots(data.path(), 10);
+        for command in ["cd build && rm notes.txt", "rm -rf $TARGET"] {
+            let preview = preview(command, &ShellType::Bash, cwd.path());
+            assert!(snapshots.take(command, cwd.path(), &preview).is_err());
+        }
+        assert!(snapshots.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_undo_restores_file_replaced_by_move() {
+        let data = tempfile::tempdir().unwrap();
+        let cwd = tempfile::tempdir().unwrap();
+        let cwd = cwd.path();
+        fs::create_dir(cwd.join("build")).unwrap();
+        fs::write(cwd.join("a.log"), "new").unwrap();
+        fs::write(cwd.join("build/a.log"), "old").unwrap();
+
+        let command = "mv a.log build/";
+        let preview = preview(command, &ShellType::Bash, cwd);
+        let snapshots = snapshots(data.path(), 10);
+        let manifest = snapshots.take(command, cwd, &preview).unwrap().unwrap();
+        fs::rename(cwd.join("a.log"), cwd.join("build/a.log")).unwrap();
+
+        snapshots.restore(&manifest).unwrap();
+        assert_eq!(fs::read_to_string(cwd.join("a.log")).unwrap(), "new");
+        assert_eq!(fs::read_to_string(cwd.join("build/a.log")).unwrap(), "old");
+    }
+
+    #[test]
+    fn test_retention_limits() {
+        let data = tempfile::tempdir().unwrap();
+        let cwd = tempfile::tempdir().unwrap();
+        fs::write(cwd.path().join("a.txt"), "a").unwrap();
+        let preview = preview("rm a.txt", &ShellType::Bash, cwd.path());
+
+        let snapshots = snapshots(data.path(), 2);
+        for _ in 0..3 {
+            snapshots.take("rm a.txt", cwd.path(), &preview).unwrap();
+        }
+        assert_eq!(snapshots.list().unwrap().len(), 2);
+
+        let small = Snapshots::new(
+            data.path().join("small"),
+            &SnapshotConfig {
+                max_size_mb: 0,
+                ..SnapshotConfig::default()
+            },
+        );
+        assert!(small.take("rm a.txt", cwd.path(), &preview).is_err());
+    }
+}
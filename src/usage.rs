@@ -0,0 +1,471 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crate::config::{get_data_dir, BudgetAction, ModelPrice, UsageConfig};
+use crate::error::ClmError;
+use crate::provider::Usage;
+
+static USAGE_FILE: &str = "usage.jsonl";
+
+/// USD per million input and output tokens, matched by the longest model name prefix.
+static DEFAULT_PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-4o-mini", 0.15, 0.60),
+    ("gpt-4o", 2.50, 10.00),
+    ("gpt-4.1-nano", 0.10, 0.40),
+    ("gpt-4.1-mini", 0.40, 1.60),
+    ("gpt-4.1", 2.00, 8.00),
+    ("gpt-4-turbo", 10.00, 30.00),
+    ("gpt-3.5-turbo", 0.50, 1.50),
+    ("o3-mini", 1.10, 4.40),
+    ("o4-mini", 1.10, 4.40),
+    ("claude-3-haiku", 0.25, 1.25),
+    ("claude-3-5-haiku", 0.80, 4.00),
+    ("claude-3-5-sonnet", 3.00, 15.00),
+    ("claude-3-7-sonnet", 3.00, 15.00),
+    ("claude-sonnet-4", 3.00, 15.00),
+    ("claude-3-opus", 15.00, 75.00),
+    ("claude-opus-4", 15.00, 75.00),
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UsageEntry {
+    pub timestamp: DateTime<Utc>,
+    pub model: String,
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    /// None when there is no price for the model
+    pub cost: Option<f64>,
+}
+
+impl UsageEntry {
+    pub fn new(model: &str, usage: Usage, prices: &HashMap<String, ModelPrice>) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            model: model.to_string(),
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            cost: price_for(model, prices).map(|price| cost(usage, price)),
+        }
+    }
+}
+
+/// Looks up the configured price for a model first, then the built-in one.
+pub fn price_for(model: &str, prices: &HashMap<String, ModelPrice>) -> Option<ModelPrice> {
+    let configured = prices
+        .iter()
+        .filter(|(name, _)| model.starts_with(name.as_str()))
+        .max_by_key(|(name, _)| name.len())
+        .map(|(_, price)| *price);
+
+    configured.or_else(|| {
+        DEFAULT_PRICES
+            .iter()
+            .filter(|(name, _, _)| model.starts_with(name))
+            .max_by_key(|(name, _, _)| name.len())
+            .map(|(_, input, output)| ModelPrice {
+                input: *input,
+                output: *output,
+            })
+    })
+}
+
+pub fn cost(usage: Usage, price: ModelPrice) -> f64 {
+    (usage.prompt_tokens as f64 * price.input + usage.completion_tokens as f64 * price.output)
+        / 1_000_000.0
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct UsageTotals {
+    pub requests: u32,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cost: f64,
+    /// Requests for models without a price, which are left out of `cost`
+    pub unpriced: u32,
+}
+
+impl UsageTotals {
+    fn add(&mut self, entry: &UsageEntry) {
+        self.requests += 1;
+        self.prompt_tokens += entry.prompt_tokens as u64;
+        self.completion_tokens += entry.completion_tokens as u64;
+        match entry.cost {
+            Some(cost) => self.cost += cost,
+            None => self.unpriced += 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Day,
+    Month,
+}
+
+impl Period {
+    fn key(self, timestamp: &DateTime<Utc>) -> String {
+        let local = timestamp.with_timezone(&Local);
+        match self {
+            Period::Day => local.format("%Y-%m-%d").to_string(),
+            Period::Month => local.format("%Y-%m").to_string(),
+        }
+    }
+}
+
+/// Totals per local day or month, oldest first.
+pub fn totals_by(entries: &[UsageEntry], period: Period) -> BTreeMap<String, UsageTotals> {
+    let mut totals: BTreeMap<String, UsageTotals> = BTreeMap::new();
+    for entry in entries {
+        totals
+            .entry(period.key(&entry.timestamp))
+            .or_default()
+            .add(entry);
+    }
+    totals
+}
+
+pub fn month_to_date(entries: &[UsageEntry], now: DateTime<Local>) -> UsageTotals {
+    let mut totals = UsageTotals::default();
+    for entry in entries {
+        let local = entry.timestamp.with_timezone(&Local);
+        if local.year() == now.year() && local.month() == now.month() {
+            totals.add(entry);
+        }
+    }
+    totals
+}
+
+/// Append-only log of the tokens used by each request, stored as one JSON object per line.
+pub struct UsageLog {
+    path: PathBuf,
+}
+
+impl UsageLog {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn open_default() -> Result<Self> {
+        Ok(Self::new(get_data_dir()?.join(USAGE_FILE)))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append(&self, entry: &UsageEntry) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Models and spend stay private to the user, like the history log
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let mut file = options
+            .open(&self.path)
+            .with_context(|| format!("Failed to open usage log {}", self.path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        Ok(())
+    }
+
+    pub fn entries(&self) -> Result<Vec<UsageEntry>> {
+        let file = match fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(BufReader::new(file)
+            .lines()
+            .map_while(|line| line.ok())
+            .filter_map(|line| serde_json::from_str(&line).ok())
+            .collect())
+    }
+}
+
+/// Records the usage of each request and enforces the monthly budget before sending one.
+pub struct UsageTracker {
+    config: UsageConfig,
+    log: UsageLog,
+    show: bool,
+}
+
+impl UsageTracker {
+    pub fn new(config: &UsageConfig, log: UsageLog, show: bool) -> Self {
+        Self {
+            config: config.clone(),
+            log,
+            show,
+        }
+    }
+
+    /// Warns, or fails when configured to block, once this month's spending reaches the budget.
+    pub fn check_budget(&self) -> Result<()> {
+        let Some(budget) = self.config.monthly_budget else {
+            return Ok(());
+        };
+        let spent = month_to_date(&self.log.entries()?, Local::now()).cost;
+        if spent < budget {
+            return Ok(());
+        }
+
+        match self.config.on_budget_exceeded {
+            BudgetAction::Block => Err(ClmError::BudgetExceeded { spent, budget }.into()),
+            BudgetAction::Warn => {
+                eprintln!(
+                    "{}",
+                    console::style(format!(
+                        "Warning: ${spent:.2} spent this month, over the ${budget:.2} budget"
+                    ))
+                    .yellow()
+                );
+                Ok(())
+            }
+        }
+    }
+
+    pub fn record(&self, model: &str, usage: Option<Usage>) {
+        let Some(usage) = usage else {
+            return;
+        };
+        let entry = UsageEntry::new(model, usage, &self.config.prices);
+
+        if self.show {
+            let cost = entry
+                .cost
+                .map(|cost| format!("${cost:.4}"))
+                .unwrap_or_else(|| "no price".to_string());
+            eprintln!(
+                "{}",
+                console::style(format!(
+                    "{} prompt + {} completion tokens ({cost})",
+                    entry.prompt_tokens, entry.completion_tokens
+                ))
+                .dim()
+            );
+        }
+
+        if let Err(err) = self.log.append(&entry) {
+            eprintln!(
+                "{}",
+                console::style(format!("Warning: failed to write usage log: {err}")).yellow()
+            );
+        }
+    }
+}
+
+pub fn print_usage(
+    entries: &[UsageEntry],
+    period: Period,
+    config: &UsageConfig,
+    json: bool,
+) -> Result<()> {
+    let totals = totals_by(entries, period);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&totals)?);
+        return Ok(());
+    }
+
+    if totals.is_empty() {
+        println!("{}", console::style("No usage recorded yet.").dim());
+        return Ok(());
+    }
+
+    let header = match period {
+        Period::Day => "Day",
+        Period::Month => "Month",
+    };
+    println!(
+        "{}",
+        console::style(format!(
+            "{header:<12} {:>8} {:>12} {:>12} {:>10}",
+            "Requests", "Prompt", "Completion", "Cost"
+        ))
+        .green()
+    );
+    for (key, total) in &totals {
+        let unpriced = if total.unpriced > 0 {
+            format!(" ({} unpriced)", total.unpriced)
+        } else {
+            String::new()
+        };
+        println!(
+            "{key:<12} {:>8} {:>12} {:>12} {:>10}{}",
+            total.requests,
+            total.prompt_tokens,
+            total.completion_tokens,
+            format!("${:.4}", total.cost),
+            console::style(unpriced).dim()
+        );
+    }
+
+    let month = month_to_date(entries, Local::now());
+    let budget = config
+        .monthly_budget
+        .map(|budget| format!(" of ${budget:.2}"))
+        .unwrap_or_default();
+    println!(
+        "\n{} ${:.2}{budget}",
+        console::style("This month:").green(),
+        month.cost
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn usage(prompt_tokens: u32, completion_tokens: u32) -> Usage {
+        Usage {
+            prompt_tokens,
+            completion_tokens,
+        }
+    }
+
+    fn entry_at(timestamp: DateTime<Utc>, cost: Option<f64>) -> UsageEntry {
+        UsageEntry {
+            timestamp,
+            model: "gpt-4o-mini".to_string(),
+            prompt_tokens: 100,
+            completion_tokens: 20,
+            cost,
+        }
+    }
+
+    #[test]
+    fn test_price_for_uses_longest_prefix() {
+        let prices = HashMap::new();
+        assert_eq!(
+            price_for("gpt-4o-mini-2024-07-18", &prices).unwrap().input,
+            0.15
+        );
+        assert_eq!(price_for("gpt-4o-2024-08-06", &prices).unwrap().input, 2.50);
+        assert_eq!(price_for("llama3.2", &prices), None);
+    }
+
+    #[test]
+    fn test_configured_price_overrides_default() {
+        let prices = HashMap::from([
+            (
+                "gpt-4o-mini".to_string(),
+                ModelPrice {
+                    input: 1.0,
+                    output: 2.0,
+                },
+            ),
+            (
+                "llama".to_string(),
+                ModelPrice {
+                    input: 0.0,
+                    output: 0.0,
+                },
+            ),
+        ]);
+        assert_eq!(price_for("gpt-4o-mini", &prices).unwrap().input, 1.0);
+        assert_eq!(price_for("llama3.2", &prices).unwrap().output, 0.0);
+    }
+
+    #[test]
+    fn test_cost() {
+        let price = ModelPrice {
+            input: 0.15,
+            output: 0.60,
+        };
+        let cost = cost(usage(1_000_000, 500_000), price);
+        assert!((cost - 0.45).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_totals_by_period() {
+        // Periods follow the local calendar, so the times are local too
+        let local = |year, month, day, hour, min, sec| {
+            Local
+                .with_ymd_and_hms(year, month, day, hour, min, sec)
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+        let entries = vec![
+            entry_at(local(2024, 3, 1, 12, 0, 0), Some(0.5)),
+            entry_at(local(2024, 3, 1, 13, 0, 0), None),
+            entry_at(local(2024, 3, 15, 12, 0, 0), Some(0.25)),
+            entry_at(local(2024, 4, 15, 12, 0, 0), Some(1.0)),
+        ];
+
+        let daily = totals_by(&entries, Period::Day);
+        assert_eq!(daily.len(), 3);
+        let first = daily.values().next().unwrap();
+        assert_eq!(first.requests, 2);
+        assert_eq!(first.unpriced, 1);
+        assert_eq!(first.prompt_tokens, 200);
+
+        let monthly = totals_by(&entries, Period::Month);
+        assert_eq!(monthly.len(), 2);
+        assert_eq!(monthly["2024-03"].cost, 0.75);
+
+        let now = local(2024, 4, 20, 12, 0, 0);
+        assert_eq!(month_to_date(&entries, now.with_timezone(&Local)).cost, 1.0);
+    }
+
+    #[test]
+    fn test_budget() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = UsageLog::new(dir.path().join(USAGE_FILE));
+        log.append(&entry_at(Utc::now(), Some(2.0))).unwrap();
+
+        let mut config = UsageConfig {
+            monthly_budget: Some(5.0),
+            on_budget_exceeded: BudgetAction::Block,
+            ..Default::default()
+        };
+        let tracker = UsageTracker::new(&config, UsageLog::new(log.path()), false);
+        assert!(tracker.check_budget().is_ok());
+
+        tracker.record("gpt-4o", Some(usage(1_000_000, 300_000)));
+        assert_eq!(log.entries().unwrap().len(), 2);
+        let err = tracker.check_budget().unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ClmError>(),
+            Some(ClmError::BudgetExceeded { .. })
+        ));
+
+        config.on_budget_exceeded = BudgetAction::Warn;
+        let tracker = UsageTracker::new(&config, UsageLog::new(log.path()), false);
+        assert!(tracker.check_budget().is_ok());
+    }
+
+    #[test]
+    fn test_record_skips_missing_usage() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = UsageLog::new(dir.path().join(USAGE_FILE));
+        let tracker = UsageTracker::new(&UsageConfig::default(), UsageLog::new(log.path()), false);
+        tracker.record("gpt-4o-mini", None);
+        assert!(log.entries().unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_usage_log_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let log = UsageLog::new(dir.path().join(USAGE_FILE));
+        log.append(&entry_at(Utc::now(), Some(0.01))).unwrap();
+
+        let mode = fs::metadata(log.path()).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,427 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::command_line::{simple_commands, tokenize, Word};
+use crate::shell::{ShellContext, ShellType, SupportedOperatingSystem};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueKind {
+    MissingProgram,
+    Syntax,
+    /// Flags that only the GNU or only the BSD version of a tool accepts
+    Portability,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    pub kind: IssueKind,
+    pub message: String,
+    /// A command that resolves the issue, such as installing the missing program
+    pub fix: Option<String>,
+}
+
+impl Issue {
+    fn new(kind: IssueKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            fix: None,
+        }
+    }
+}
+
+/// Checks a suggested command without running it: that its programs are installed, that the
+/// shell can parse it, and that its flags suit the local versions of common tools.
+pub fn validate(command: &str, shell_context: &ShellContext, cwd: &Path) -> Vec<Issue> {
+    if let Some(message) = syntax_error(command, shell_context) {
+        return vec![Issue::new(IssueKind::Syntax, message)];
+    }
+    let Some(tokens) = tokenize(command, &shell_context.shell_type) else {
+        return vec![Issue::new(
+            IssueKind::Syntax,
+            "The command has an unterminated quote",
+        )];
+    };
+    // These shells have many builtins, like nushell's `where` or elvish's `put`, or resolve
+    // cmdlets and aliases in ways PATH doesn't show
+    if matches!(
+        shell_context.shell_type,
+        ShellType::PowerShell
+            | ShellType::Cmd
+            | ShellType::Nushell
+            | ShellType::Xonsh
+            | ShellType::Elvish
+    ) {
+        return Vec::new();
+    }
+
+    let mut issues = Vec::new();
+    let mut checked: Vec<String> = Vec::new();
+    for words in simple_commands(&tokens) {
+        let Some((program, args)) = program_and_args(&words) else {
+            continue;
+        };
+        if program.dynamic || checked.contains(&program.text) {
+            continue;
+        }
+        checked.push(program.text.clone());
+
+        let Some(path) = find_program(&program.text, cwd) else {
+            let mut issue = Issue::new(
+                IssueKind::MissingProgram,
+                format!("{} is not installed or not on your PATH", program.text),
+            );
+            issue.fix = install_command(&program.text, &shell_context.os);
+            issues.push(issue);
+            continue;
+        };
+
+        let args: Vec<&str> = args.iter().map(|arg| arg.text.as_str()).collect();
+        let name = program.text.rsplit('/').next().unwrap_or_default();
+        if let Some(flavor) = flavor(&path, &shell_context.os) {
+            if let Some(message) = portability_issue(name, &args, flavor) {
+                issues.push(Issue::new(IssueKind::Portability, message));
+            }
+        }
+    }
+    issues
+}
+
+/// Asks the shell to parse the command without running it. None when it parses or when the
+/// shell can't check syntax.
+fn syntax_error(command: &str, shell_context: &ShellContext) -> Option<String> {
+    let (shell, args): (&str, &[&str]) = match &shell_context.shell_type {
+        ShellType::Bash => ("bash", &["-n", "-c"]),
+        ShellType::Zsh => ("zsh", &["-n", "-c"]),
+        ShellType::Fish => ("fish", &["--no-execute", "-c"]),
+        ShellType::Dash => ("dash", &["-n", "-c"]),
+        ShellType::Ksh => ("ksh", &["-n", "-c"]),
+        ShellType::Unknown(name) if Path::new(name).ends_with("sh") => ("sh", &["-n", "-c"]),
+        _ => return None,
+    };
+    let program = shell_context
+        .shell_path
+        .as_deref()
+        .unwrap_or_else(|| Path::new(shell));
+    let output = Command::new(program).args(args).arg(command).output().ok()?;
+    if output.status.success() {
+        return None;
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let message = stderr
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or("invalid syntax");
+    Some(format!(
+        "{shell} can't parse the command: {}",
+        message.trim()
+    ))
+}
+
+const KEYWORDS: &[&str] = &[
+    "if", "then", "else", "elif", "do", "while", "until", "!", "{", "time", "and", "or", "not",
+    "begin",
+];
+const CLOSING_KEYWORDS: &[&str] = &["fi", "done", "esac", "}", "end"];
+/// Words whose arguments aren't commands
+const COMPOUND_KEYWORDS: &[&str] = &["for", "case", "select", "function", "switch"];
+const PREFIXES: &[&str] = &[
+    "sudo", "doas", "env", "command", "exec", "nohup", "nice", "builtin",
+];
+#[rustfmt::skip]
+const BUILTINS: &[&str] = &[
+    ".", ":", "[", "[[", "alias", "bg", "bind", "break", "builtin", "cd", "compgen", "complete",
+    "continue", "declare", "dirs", "disown", "echo", "enable", "eval", "exit", "export",
+    "false", "fc", "fg", "getopts", "hash", "help", "history", "jobs", "let", "local", "logout",
+    "mapfile", "popd", "print", "printf", "pushd", "pwd", "read", "readarray", "readonly",
+    "return", "set", "setopt", "shift", "shopt", "source", "test", "times", "trap", "true",
+    "type", "typeset", "ulimit", "umask", "unalias", "unset", "unsetopt", "wait", "whence",
+    "autoload", "bindkey", "functions", "set_color", "string", "math", "contains", "count",
+    "abbr", "emit", "status", "argparse",
+];
+
+/// The program a simple command runs, skipping keywords, variable assignments and prefixes
+/// like `sudo`.
+fn program_and_args<'a>(words: &'a [&'a Word]) -> Option<(&'a Word, &'a [&'a Word])> {
+    let mut index = 0;
+    while let Some(word) = words.get(index) {
+        let text = word.text.as_str();
+        if COMPOUND_KEYWORDS.contains(&text) || CLOSING_KEYWORDS.contains(&text) {
+            return None;
+        }
+        let assignment = text.split_once('=').is_some_and(|(name, _)| {
+            !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        });
+        if KEYWORDS.contains(&text) || assignment {
+            index += 1;
+        } else if PREFIXES.contains(&text) {
+            index += 1;
+            // Options of the prefix, and the user or group sudo runs as
+            while let Some(option) = words.get(index).filter(|word| word.text.starts_with('-')) {
+                index += if matches!(option.text.as_str(), "-u" | "-g") {
+                    2
+                } else {
+                    1
+                };
+            }
+        } else if BUILTINS.contains(&text) {
+            return None;
+        } else {
+            return Some((word, &words[index + 1..]));
+        }
+    }
+    None
+}
+
+fn find_program(name: &str, cwd: &Path) -> Option<PathBuf> {
+    if name.contains('/') {
+        let path = cwd.join(expand_home(name));
+        return path.exists().then_some(path);
+    }
+    let extensions: Vec<String> = if cfg!(windows) {
+        std::env::var("PATHEXT")
+            .unwrap_or_else(|_| ".EXE;.BAT;.CMD".to_string())
+            .split(';')
+            .map(str::to_string)
+            .chain([String::new()])
+            .collect()
+    } else {
+        vec![String::new()]
+    };
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .flat_map(|dir| {
+            extensions
+                .iter()
+                .map(move |ext| dir.join(format!("{name}{ext}")))
+        })
+        .find(|candidate| is_executable(candidate))
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => home::home_dir()
+            .map(|home| home.join(rest))
+            .unwrap_or_else(|| PathBuf::from(path)),
+        None => PathBuf::from(path),
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Package names that differ from the program they install, per package manager
+const PACKAGES: &[(&str, &str, &str)] = &[
+    ("rg", "ripgrep", "ripgrep"),
+    ("fd", "fd-find", "fd"),
+    ("http", "httpie", "httpie"),
+    ("convert", "imagemagick", "imagemagick"),
+    ("magick", "imagemagick", "imagemagick"),
+    ("dig", "dnsutils", "bind"),
+    ("nc", "netcat-openbsd", "netcat"),
+    ("7z", "p7zip-full", "p7zip"),
+    ("pdftotext", "poppler-utils", "poppler"),
+    ("gs", "ghostscript", "ghostscript"),
+];
+
+/// How to install a missing program with the package manager found on this system.
+fn install_command(program: &str, os: &SupportedOperatingSystem) -> Option<String> {
+    let package = |apt_style: bool| {
+        PACKAGES
+            .iter()
+            .find(|(name, _, _)| *name == program)
+            .map(|(_, apt, other)| if apt_style { *apt } else { *other })
+            .unwrap_or(program)
+    };
+    let cwd = Path::new(".");
+    let managers: &[(&str, &str, bool)] = match os {
+        SupportedOperatingSystem::Mac => &[
+            ("brew", "brew install", false),
+            ("port", "sudo port install", false),
+        ],
+        SupportedOperatingSystem::Windows => &[
+            ("winget", "winget install", false),
+            ("scoop", "scoop install", false),
+        ],
+        SupportedOperatingSystem::Linux => &[
+            ("apt-get", "sudo apt-get install", true),
+            ("dnf", "sudo dnf install", false),
+            ("pacman", "sudo pacman -S", false),
+            ("zypper", "sudo zypper install", false),
+            ("apk", "sudo apk add", false),
+            ("brew", "brew install", false),
+        ],
+    };
+    managers
+        .iter()
+        .find(|(manager, _, _)| find_program(manager, cwd).is_some())
+        .map(|(_, install, apt_style)| format!("{install} {}", package(*apt_style)))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flavor {
+    Gnu,
+    Bsd,
+}
+
+/// Whether the program at `path` is the GNU version, which says so in its `--version`
+/// output, or the BSD version that ships with macOS.
+fn flavor(path: &Path, os: &SupportedOperatingSystem) -> Option<Flavor> {
+    let name = path.file_name()?.to_str()?;
+    if !matches!(
+        name,
+        "sed" | "grep" | "date" | "stat" | "find" | "xargs" | "ls" | "cp" | "du" | "readlink"
+    ) {
+        return None;
+    }
+    let output = Command::new(path).arg("--version").output().ok()?;
+    let version = String::from_utf8_lossy(&output.stdout);
+    if version.contains("GNU") {
+        Some(Flavor::Gnu)
+    } else if matches!(os, SupportedOperatingSystem::Mac) {
+        Some(Flavor::Bsd)
+    } else {
+        None
+    }
+}
+
+/// Flags that the local flavor of a tool doesn't understand.
+pub fn portability_issue(program: &str, args: &[&str], flavor: Flavor) -> Option<String> {
+    let has = |flag: &str| args.contains(&flag);
+    let value_after = |flag: &str| {
+        args.iter()
+            .position(|arg| *arg == flag)
+            .and_then(|index| args.get(index + 1))
+    };
+
+    match (program, flavor) {
+        ("sed", Flavor::Bsd)
+            if value_after("-i").is_some_and(|next| !next.is_empty() && !next.starts_with('.')) =>
+        {
+            Some("BSD sed needs a backup suffix after -i, e.g. `sed -i '' …`".to_string())
+        }
+        ("sed", Flavor::Gnu) if value_after("-i") == Some(&"") => {
+            Some("GNU sed reads the '' after -i as the script; use `sed -i` on its own".to_string())
+        }
+        ("grep", Flavor::Bsd) if args.iter().any(|arg| is_short_flag(arg, 'P')) => {
+            Some("BSD grep has no -P (Perl regular expressions); try -E".to_string())
+        }
+        ("date", Flavor::Bsd) if has("-d") || has("--date") => {
+            Some("BSD date has no -d; use -v to adjust or -j -f to parse a date".to_string())
+        }
+        ("date", Flavor::Gnu) if has("-v") || has("-j") => {
+            Some("GNU date has no -v or -j; use -d to parse or adjust a date".to_string())
+        }
+        ("stat", Flavor::Bsd) if has("-c") || has("--format") => {
+            Some("BSD stat uses -f instead of -c for formats".to_string())
+        }
+        ("stat", Flavor::Gnu) if value_after("-f").is_some_and(|format| format.contains('%')) => {
+            Some("GNU stat uses -c instead of -f for formats".to_string())
+        }
+        ("find", Flavor::Bsd) if has("-printf") => {
+            Some("BSD find has no -printf; try -exec stat -f".to_string())
+        }
+        ("xargs", Flavor::Bsd) if has("--no-run-if-empty") => {
+            Some("BSD xargs has no --no-run-if-empty".to_string())
+        }
+        ("ls" | "cp" | "du" | "readlink", Flavor::Bsd) => args
+            .iter()
+            .find(|arg| arg.starts_with("--") && arg.len() > 2)
+            .map(|arg| format!("BSD {program} doesn't accept long options like {arg}")),
+        _ => None,
+    }
+}
+
+fn is_short_flag(arg: &str, flag: char) -> bool {
+    arg.starts_with('-') && !arg.starts_with("--") && arg[1..].contains(flag)
+}
+
+pub fn print_issues(issues: &[Issue]) {
+    if issues.is_empty() {
+        return;
+    }
+    println!("{}", console::style("Problems found:").red());
+    for issue in issues {
+        let marker = match issue.kind {
+            IssueKind::Portability => console::style("!").yellow(),
+            IssueKind::MissingProgram | IssueKind::Syntax => console::style("✗").red(),
+        };
+        println!("  {marker} {}", issue.message);
+        if let Some(fix) = &issue.fix {
+            println!(
+                "    {}",
+                console::style(format!("Install it with: {fix}")).dim()
+            );
+        }
+    }
+    println!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(shell_type: ShellType) -> ShellContext {
+        ShellContext {
+            shell_type,
+            os: SupportedOperatingSystem::Linux,
+            shell_path: None,
+        }
+    }
+
+    fn kinds(command: &str) -> Vec<IssueKind> {
+        validate(
+            command,
+            &context(ShellType::Unknown("sh".to_string())),
+            Path::new("."),
+        )
+        .into_iter()
+        .map(|issue| issue.kind)
+        .collect()
+    }
+
+    #[test]
+    fn test_missing_programs() {
+        assert!(kinds("ls -la | sort && cd /tmp").is_empty());
+        assert_eq!(
+            kinds("sudo -u nobody clm-no-such-tool --flag && FOO=1 clm-no-such-tool"),
+            [IssueKind::MissingProgram]
+        );
+        assert!(kinds("if true; then echo yes; fi; for f in a b; do echo $f; done").is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_syntax_errors() {
+        assert_eq!(kinds("echo 'unterminated"), [IssueKind::Syntax]);
+        assert_eq!(kinds("if true; then echo"), [IssueKind::Syntax]);
+
+        // The check runs the detected shell binary rather than whatever `sh` is on PATH
+        let mut missing_shell = context(ShellType::Unknown("sh".to_string()));
+        missing_shell.shell_path = Some(PathBuf::from("/nonexistent/sh"));
+        assert!(validate("if true; then echo", &missing_shell, Path::new(".")).is_empty());
+    }
+
+    #[test]
+    fn test_portability_issues() {
+        assert!(portability_issue("sed", &["-i", "s/a/b/", "f.txt"], Flavor::Bsd).is_some());
+        assert!(portability_issue("sed", &["-i", "", "s/a/b/", "f.txt"], Flavor::Bsd).is_none());
+        assert!(portability_issue("sed", &["-i", "", "s/a/b/", "f.txt"], Flavor::Gnu).is_some());
+        assert!(portability_issue("sed", &["-i", "s/a/b/", "f.txt"], Flavor::Gnu).is_none());
+        assert!(portability_issue("grep", &["-rP", "\\d+"], Flavor::Bsd).is_some());
+        assert!(portability_issue("date", &["-d", "yesterday"], Flavor::Bsd).is_some());
+        assert!(portability_issue("stat", &["-c", "%s", "f"], Flavor::Bsd).is_some());
+        assert!(portability_issue("ls", &["--color=auto"], Flavor::Bsd).is_some());
+        assert!(portability_issue("ls", &["-la"], Flavor::Bsd).is_none());
+    }
+}
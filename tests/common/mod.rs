@@ -0,0 +1,306 @@
+//! A local HTTP server that replays recorded provider responses, and a `clm` runner that
+//! points at it from an isolated home directory.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use serde_json::Value;
+
+pub struct Fixture {
+    status: u16,
+    content_type: &'static str,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Fixture {
+    /// Loads `tests/fixtures/<name>`, served with a content type matching its extension.
+    pub fn load(name: &str) -> Self {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name);
+        let body = std::fs::read_to_string(&path)
+            .unwrap_or_else(|err| panic!("Missing fixture {}: {err}", path.display()));
+        let content_type = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("sse") => "text/event-stream",
+            Some("ndjson") => "application/x-ndjson",
+            _ => "application/json",
+        };
+        Self {
+            status: 200,
+            content_type,
+            headers: Vec::new(),
+            body,
+        }
+    }
+
+    /// An OpenAI chat completion whose message is `content`, for suggestions that no
+    /// recorded fixture covers.
+    pub fn chat_completion(content: &str) -> Self {
+        let mut body: Value =
+            serde_json::from_str(&Self::load("openai_chat_completion.json").body).unwrap();
+        body["choices"][0]["message"]["content"] = Value::from(content);
+        Self {
+            status: 200,
+            content_type: "application/json",
+            headers: Vec::new(),
+            body: body.to_string(),
+        }
+    }
+
+    /// A plain-text response, like the pages proxies and load balancers send on errors.
+    pub fn text(status: u16, body: &str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain",
+            headers: Vec::new(),
+            body: body.to_string(),
+        }
+    }
+
+    pub fn with_status(mut self, status: u16) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Value,
+}
+
+impl RecordedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Serves the fixtures in order, one per request, repeating the last one when they run out.
+pub struct FixtureServer {
+    url: String,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl FixtureServer {
+    pub fn start(fixtures: Vec<Fixture>) -> Self {
+        assert!(!fixtures.is_empty());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = requests.clone();
+        thread::spawn(move || {
+            for (index, stream) in listener.incoming().enumerate() {
+                let Ok(stream) = stream else { break };
+                let fixture = &fixtures[index.min(fixtures.len() - 1)];
+                if let Some(request) = serve(stream, fixture) {
+                    recorded.lock().unwrap().push(request);
+                }
+            }
+        });
+
+        Self { url, requests }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn serve(stream: TcpStream, fixture: &Fixture) -> Option<RecordedRequest> {
+    let mut reader = BufReader::new(stream.try_clone().ok()?);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).ok()?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    let length = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+
+    // Each response closes its connection, so every request gets the next fixture
+    let mut stream = stream;
+    let extra_headers: String = fixture
+        .headers
+        .iter()
+        .map(|(name, value)| format!("{name}: {value}\r\n"))
+        .collect();
+    write!(
+        stream,
+        "HTTP/1.1 {} Fixture\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+        fixture.status,
+        fixture.content_type,
+        fixture.body.len(),
+        extra_headers,
+        fixture.body
+    )
+    .ok()?;
+
+    Some(RecordedRequest {
+        method,
+        path,
+        headers,
+        body: serde_json::from_slice(&body).unwrap_or(Value::Null),
+    })
+}
+
+/// Runs `clm` with its config, cache and data directories inside a temporary home.
+pub struct Clm {
+    home: tempfile::TempDir,
+}
+
+impl Clm {
+    pub fn with_config(config: Value) -> Self {
+        Self::with_config_text(&config.to_string())
+    }
+
+    /// Writes the config file verbatim, for configs that aren't valid JSON.
+    pub fn with_config_text(config: &str) -> Self {
+        let clm = Self {
+            home: tempfile::tempdir().unwrap(),
+        };
+        let config_dir = clm.config_dir();
+        std::fs::create_dir_all(&config_dir).unwrap();
+        std::fs::create_dir_all(clm.work_dir()).unwrap();
+        std::fs::write(config_dir.join("config.json"), config).unwrap();
+        clm
+    }
+
+    pub fn data_dir(&self) -> PathBuf {
+        if cfg!(target_os = "macos") {
+            self.home
+                .path()
+                .join("Library/Application Support/com.commandlm.commandlm")
+        } else {
+            self.home.path().join("data/commandlm")
+        }
+    }
+
+    /// The directory commands run in
+    pub fn work_dir(&self) -> PathBuf {
+        self.home.path().join("work")
+    }
+
+    fn config_dir(&self) -> PathBuf {
+        if cfg!(target_os = "macos") {
+            self.data_dir()
+        } else {
+            self.home.path().join("config/commandlm")
+        }
+    }
+
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_clm"));
+        command
+            .current_dir(self.work_dir())
+            .env("HOME", self.home.path())
+            .env("SHELL", "/bin/sh")
+            .env("XDG_CONFIG_HOME", self.home.path().join("config"))
+            .env("XDG_DATA_HOME", self.home.path().join("data"))
+            .env("XDG_CACHE_HOME", self.home.path().join("cache"))
+            .env("NO_COLOR", "1")
+            .env("RUST_BACKTRACE", "0")
+            .env_remove("CLM_API_BASE")
+            .env_remove("CLM_PROMPT_SCRIPT")
+            .env_remove("OLLAMA_HOST");
+        for key in [
+            "OPENAI_API_KEY",
+            "ANTHROPIC_API_KEY",
+            "AZURE_OPENAI_API_KEY",
+            "OLLAMA_API_KEY",
+        ] {
+            command.env_remove(key);
+        }
+        command
+    }
+
+    pub fn run(&self, args: &[&str]) -> Output {
+        self.run_with_input(args, "")
+    }
+
+    /// Runs with `input` piped to stdin, so `clm` never sees a terminal.
+    pub fn run_with_input(&self, args: &[&str], input: &str) -> Output {
+        let mut child = self
+            .command()
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to execute clm");
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        child.wait_with_output().unwrap()
+    }
+
+    /// Runs with each prompt answered from `answers` in order, as `CLM_PROMPT_SCRIPT` does.
+    pub fn run_with_answers(&self, args: &[&str], answers: &[&str]) -> Output {
+        let script = self.home.path().join("answers.txt");
+        let lines: String = answers.iter().map(|answer| format!("{answer}\n")).collect();
+        std::fs::write(&script, lines).unwrap();
+        self.command()
+            .env("CLM_PROMPT_SCRIPT", &script)
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .expect("Failed to execute clm")
+    }
+
+    pub fn history(&self) -> Vec<Value> {
+        read_jsonl(&self.data_dir().join("history.jsonl"))
+    }
+
+    pub fn usage_log(&self) -> Vec<Value> {
+        read_jsonl(&self.data_dir().join("usage.jsonl"))
+    }
+}
+
+fn read_jsonl(path: &Path) -> Vec<Value> {
+    std::fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
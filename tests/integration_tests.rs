@@ -1,6 +1,8 @@
 use std::process::Command;
 use commandlm::shell::{ShellContext, ShellType, SupportedOperatingSystem};
 
+mod common;
+
 #[test]
 fn test_help_command() {
     let output = Command::new("./target/debug/clm")
@@ -55,6 +57,8 @@ fn test_shell_context_creation() {
     // Ensure the context is properly initialized
     match context.shell_type {
         ShellType::Bash | ShellType::Zsh | ShellType::Fish | 
+        ShellType::Nushell | ShellType::Xonsh | ShellType::Elvish |
+        ShellType::Dash | ShellType::Ksh |
         ShellType::PowerShell | ShellType::Cmd | ShellType::Unknown(_) => {},
     }
     
@@ -74,6 +78,11 @@ fn test_shell_command_mapping() {
         ShellType::Bash => ("bash", vec!["-c"]),
         ShellType::Zsh => ("zsh", vec!["-c"]),
         ShellType::Fish => ("fish", vec!["-c"]),
+        ShellType::Nushell => ("nu", vec!["-c"]),
+        ShellType::Xonsh => ("xonsh", vec!["-c"]),
+        ShellType::Elvish => ("elvish", vec!["-c"]),
+        ShellType::Dash => ("dash", vec!["-c"]),
+        ShellType::Ksh => ("ksh", vec!["-c"]),
         ShellType::PowerShell => ("powershell", vec!["-Command"]),
         ShellType::Cmd => ("cmd", vec!["/C"]),
         ShellType::Unknown(_) => ("sh", vec!["-c"]),
@@ -83,35 +92,579 @@ fn test_shell_command_mapping() {
     assert!(!shell_args.is_empty());
 }
 
-#[cfg(test)]
-mod mock_tests {
+mod end_to_end {
+    use super::common::{Clm, Fixture, FixtureServer};
+    use serde_json::{json, Value};
+
+    fn openai_config(server: &FixtureServer) -> Value {
+        json!({
+            "provider": { "api_base": format!("{}/v1", server.url()) },
+            "request": { "max_retries": 0 }
+        })
+    }
+
+    fn stdout(output: &std::process::Output) -> String {
+        String::from_utf8_lossy(&output.stdout).to_string()
+    }
+
+    fn stderr(output: &std::process::Output) -> String {
+        String::from_utf8_lossy(&output.stderr).to_string()
+    }
+
+    #[test]
+    fn test_query_prints_suggested_command() {
+        let server = FixtureServer::start(vec![Fixture::load("openai_chat_completion.json")]);
+        let clm = Clm::with_config(openai_config(&server));
+
+        let output = clm.run(&["--print", "list all files"]);
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert_eq!(stdout(&output), "ls -la\n");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].path, "/v1/chat/completions");
+        assert_eq!(requests[0].body["model"], "gpt-4o-mini");
+        let messages = requests[0].body["messages"].as_array().unwrap();
+        assert_eq!(messages[0]["role"], "system");
+        assert!(messages[1]["content"]
+            .as_str()
+            .unwrap()
+            .contains("Query: list all files"));
+
+        let history = clm.history();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0]["query"], "list all files");
+        assert_eq!(history[0]["command"], "ls -la");
+        assert_eq!(history[0]["model"], "gpt-4o-mini");
+        assert_eq!(history[0]["executed"], false);
+    }
+
+    #[test]
+    fn test_piped_input_is_sent_as_context() {
+        let server = FixtureServer::start(vec![Fixture::load("openai_chat_completion.json")]);
+        let clm = Clm::with_config(openai_config(&server));
+
+        let output = clm.run_with_input(&["--print", "what is this"], "drwxr-xr-x src\n");
+        assert!(output.status.success(), "{}", stderr(&output));
+
+        let body = &server.requests()[0].body;
+        assert!(body["messages"][1]["content"]
+            .as_str()
+            .unwrap()
+            .contains("drwxr-xr-x src"));
+    }
+
+    #[test]
+    fn test_init_prints_shell_integration() {
+        let clm = Clm::with_config(json!({}));
+        for shell in ["bash", "zsh", "fish", "powershell"] {
+            let output = clm.run(&["init", shell]);
+            assert!(output.status.success(), "{}", stderr(&output));
+            assert!(stdout(&output).contains("clm --print"));
+        }
+    }
 
-    // Mock test for command execution without actually running commands
     #[test]
-    fn test_command_execution_logic() {
-        // Test safe command that just echoes
-        let test_command = "echo 'test'";
-        
-        // In a real scenario, we'd want to mock the execution
-        // For now, just test that the command format is valid
-        assert!(!test_command.is_empty());
-        assert!(test_command.contains("echo"));
+    fn test_init_rejects_unknown_shell() {
+        let clm = Clm::with_config(json!({}));
+        let output = clm.run(&["init", "tcsh"]);
+        assert!(!output.status.success());
+    }
+
+    #[test]
+    fn test_broken_config_only_fails_commands_that_read_it() {
+        let clm = Clm::with_config_text("{ \"cache\": { \"enabled\": true, } }");
+
+        for args in [&["init", "bash"][..], &["completions", "zsh"], &["man"]] {
+            let output = clm.run(args);
+            assert!(output.status.success(), "{args:?}: {}", stderr(&output));
+            assert!(!stdout(&output).is_empty());
+        }
+        let output = clm.run(&["cache", "stats"]);
+        assert!(!output.status.success());
+        assert!(stderr(&output).contains("Invalid config file"));
+    }
+
+    #[test]
+    fn test_repeated_query_is_served_from_cache() {
+        let server = FixtureServer::start(vec![Fixture::load("openai_chat_completion.json")]);
+        let clm = Clm::with_config(openai_config(&server));
+
+        for _ in 0..2 {
+            let output = clm.run(&["--print", "list all files"]);
+            assert_eq!(stdout(&output), "ls -la\n");
+        }
+        assert_eq!(server.requests().len(), 1);
+
+        clm.run(&["--print", "--refresh", "list all files"]);
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn test_azure_answer_is_cached_under_model() {
+        let server = FixtureServer::start(vec![Fixture::load("openai_chat_completion.json")]);
+        let clm = Clm::with_config(json!({
+            "provider": {
+                "type": "azure",
+                "api_base": server.url(),
+                "model": "gpt-4o-mini",
+                "deployment": "clm-prod",
+                "require_api_key": false
+            },
+            "request": { "max_retries": 0 }
+        }));
+
+        for _ in 0..2 {
+            let output = clm.run(&["--print", "list all files"]);
+            assert!(output.status.success(), "{}", stderr(&output));
+            assert_eq!(stdout(&output), "ls -la\n");
+        }
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0]
+            .path
+            .starts_with("/openai/deployments/clm-prod/chat/completions"));
+        assert_eq!(clm.history()[0]["model"], "gpt-4o-mini");
+    }
+
+    #[test]
+    fn test_rejected_key_exits_with_auth_code() {
+        let server = FixtureServer::start(vec![
+            Fixture::load("openai_invalid_api_key.json").with_status(401)
+        ]);
+        let clm = Clm::with_config(openai_config(&server));
+
+        let output = clm.run(&["--print", "list all files"]);
+        assert_eq!(output.status.code(), Some(3));
+        assert!(stderr(&output).contains("Incorrect API key provided"));
+        assert!(stderr(&output).contains("Hint:"));
+        assert!(clm.history().is_empty());
+    }
+
+    #[test]
+    fn test_missing_key_keeps_stdout_empty_in_print_mode() {
+        let clm = Clm::with_config(json!({}));
+
+        let output = clm.run(&["--print", "list all files"]);
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(stdout(&output), "");
+        assert!(stderr(&output).contains("OPENAI_API_KEY"));
+    }
+
+    #[test]
+    fn test_server_error_is_retried() {
+        let server = FixtureServer::start(vec![
+            Fixture::load("openai_server_error.json").with_status(503),
+            Fixture::load("openai_chat_completion.json"),
+        ]);
+        let clm = Clm::with_config(json!({
+            "provider": { "api_base": format!("{}/v1", server.url()) },
+            "request": { "max_retries": 1 }
+        }));
+
+        let output = clm.run(&["--print", "list all files"]);
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert_eq!(stdout(&output), "ls -la\n");
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn test_server_error_without_json_body_is_retried() {
+        let server = FixtureServer::start(vec![
+            Fixture::text(502, "Bad Gateway"),
+            Fixture::load("openai_chat_completion.json"),
+        ]);
+        let clm = Clm::with_config(json!({
+            "provider": { "api_base": format!("{}/v1", server.url()) },
+            "request": { "max_retries": 1 }
+        }));
+
+        let output = clm.run(&["--print", "list all files"]);
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert_eq!(stdout(&output), "ls -la\n");
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn test_rate_limit_honors_retry_after() {
+        // A wait beyond the retry cap is reported instead of slept through
+        let server = FixtureServer::start(vec![Fixture::text(429, "Too Many Requests")
+            .with_header("Retry-After", "3600")]);
+        let clm = Clm::with_config(json!({
+            "provider": { "api_base": format!("{}/v1", server.url()) },
+            "request": { "max_retries": 3 }
+        }));
+
+        let output = clm.run(&["--print", "list all files"]);
+        assert_eq!(output.status.code(), Some(5));
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn test_fallback_model_answers_when_primary_fails() {
+        let primary = FixtureServer::start(vec![
+            Fixture::load("openai_server_error.json").with_status(500)
+        ]);
+        let fallback = FixtureServer::start(vec![Fixture::load("ollama_chat.ndjson")]);
+        let clm = Clm::with_config(json!({
+            "provider": { "api_base": format!("{}/v1", primary.url()) },
+            "fallbacks": [{ "type": "ollama", "model": "llama3.2", "api_base": fallback.url() }],
+            "request": { "max_retries": 0 }
+        }));
+
+        let output = clm.run(&["--print", "count lines of rust"]);
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert_eq!(stdout(&output), "wc -l **/*.rs\n");
+        assert_eq!(primary.requests().len(), 1);
+        assert_eq!(fallback.requests()[0].path, "/api/chat");
+        assert_eq!(clm.history()[0]["model"], "llama3.2");
+    }
+
+    #[test]
+    fn test_anthropic_tool_use_stream() {
+        let server = FixtureServer::start(vec![Fixture::load("anthropic_messages.sse")]);
+        let clm = Clm::with_config(json!({
+            "provider": { "type": "anthropic", "api_base": server.url() },
+            "request": { "max_retries": 0 }
+        }));
+
+        let output = clm
+            .command()
+            .args(["--print", "disk usage per folder"])
+            .env("ANTHROPIC_API_KEY", "sk-ant-test")
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert_eq!(stdout(&output), "du -sh *\n");
+
+        let request = &server.requests()[0];
+        assert_eq!(request.path, "/v1/messages");
+        assert_eq!(request.header("x-api-key"), Some("sk-ant-test"));
+        assert_eq!(request.body["tool_choice"]["name"], "suggest_command");
     }
 
     #[test]
-    fn test_command_parsing_logic() {
-        // Test that commands can be parsed properly
-        let commands = vec![
-            "ls -la",
-            "mkdir test_dir",
-            "echo 'hello world'",
-            "pwd",
-        ];
-        
-        for cmd in commands {
-            assert!(!cmd.is_empty());
-            // Basic validation that command has proper format
-            assert!(cmd.chars().any(|c| !c.is_whitespace()));
-        }
-    }
-}
\ No newline at end of file
+    fn test_usage_is_recorded_and_reported() {
+        let server = FixtureServer::start(vec![Fixture::load("openai_chat_completion.json")]);
+        let clm = Clm::with_config(openai_config(&server));
+
+        let output = clm.run(&["--print", "--usage", "list all files"]);
+        assert!(stderr(&output).contains("212 prompt + 17 completion tokens"));
+
+        let usage = clm.usage_log();
+        assert_eq!(usage.len(), 1);
+        assert_eq!(usage[0]["model"], "gpt-4o-mini");
+
+        let output = clm.run(&["usage", "--monthly", "--json"]);
+        let totals: Value = serde_json::from_slice(&output.stdout).unwrap();
+        let month = totals.as_object().unwrap().values().next().unwrap();
+        assert_eq!(month["requests"], 1);
+        assert_eq!(month["completion_tokens"], 17);
+    }
+
+    #[cfg(unix)]
+    mod confirm_flow {
+        use super::*;
+
+        fn clm_suggesting(server: &FixtureServer) -> Clm {
+            Clm::with_config(openai_config(server))
+        }
+
+        #[test]
+        fn test_execute() {
+            let server = FixtureServer::start(vec![Fixture::chat_completion(
+                "Writes a greeting to a file§echo hello > greeting.txt",
+            )]);
+            let clm = clm_suggesting(&server);
+
+            let output = clm.run_with_answers(&["write a greeting"], &["Execute"]);
+            assert!(output.status.success(), "{}", stderr(&output));
+            assert!(stdout(&output).contains("echo hello > greeting.txt"));
+            let greeting = std::fs::read_to_string(clm.work_dir().join("greeting.txt")).unwrap();
+            assert_eq!(greeting, "hello\n");
+
+            let history = clm.history();
+            assert_eq!(history[0]["executed"], true);
+            assert_eq!(history[0]["exit_code"], 0);
+            assert_eq!(history[0]["edited"], false);
+        }
+
+        #[test]
+        fn test_decline() {
+            let server = FixtureServer::start(vec![Fixture::chat_completion(
+                "Writes a greeting to a file§echo hello > greeting.txt",
+            )]);
+            let clm = clm_suggesting(&server);
+
+            let output = clm.run_with_answers(&["write a greeting"], &["Cancel"]);
+            assert!(output.status.success(), "{}", stderr(&output));
+            assert!(stdout(&output).contains("Command not executed."));
+            assert!(!clm.work_dir().join("greeting.txt").exists());
+            assert_eq!(clm.history()[0]["executed"], false);
+        }
+
+        #[test]
+        fn test_default_answer_declines() {
+            let server = FixtureServer::start(vec![Fixture::chat_completion(
+                "Writes a greeting to a file§echo hello > greeting.txt",
+            )]);
+            let clm = clm_suggesting(&server);
+
+            let output = clm.run_with_answers(&["write a greeting"], &[""]);
+            assert!(output.status.success(), "{}", stderr(&output));
+            assert!(!clm.work_dir().join("greeting.txt").exists());
+        }
+
+        #[test]
+        fn test_edit_then_execute() {
+            let server = FixtureServer::start(vec![Fixture::chat_completion(
+                "Writes a greeting to a file§echo hello > greeting.txt",
+            )]);
+            let clm = clm_suggesting(&server);
+
+            let output = clm.run_with_answers(
+                &["write a greeting"],
+                &["Edit", "echo goodbye > greeting.txt", "Execute"],
+            );
+            assert!(output.status.success(), "{}", stderr(&output));
+            let greeting = std::fs::read_to_string(clm.work_dir().join("greeting.txt")).unwrap();
+            assert_eq!(greeting, "goodbye\n");
+
+            let history = clm.history();
+            assert_eq!(history[0]["suggestion"], "echo hello > greeting.txt");
+            assert_eq!(history[0]["command"], "echo goodbye > greeting.txt");
+            assert_eq!(history[0]["edited"], true);
+        }
+
+        #[test]
+        fn test_edit_then_cancel_records_edit() {
+            let server = FixtureServer::start(vec![Fixture::chat_completion(
+                "Writes a greeting to a file§echo hello > greeting.txt",
+            )]);
+            let clm = clm_suggesting(&server);
+
+            let output = clm.run_with_answers(
+                &["write a greeting"],
+                &["Edit", "echo goodbye > greeting.txt", "Cancel"],
+            );
+            assert!(output.status.success(), "{}", stderr(&output));
+            assert!(!clm.work_dir().join("greeting.txt").exists());
+
+            let history = clm.history();
+            assert_eq!(history[0]["command"], "echo goodbye > greeting.txt");
+            assert_eq!(history[0]["edited"], true);
+            assert_eq!(history[0]["executed"], false);
+        }
+
+        #[test]
+        fn test_high_risk_command_needs_second_confirmation() {
+            let server = FixtureServer::start(vec![Fixture::chat_completion(
+                "Deletes the build directory§rm -rf build",
+            )]);
+            let clm = clm_suggesting(&server);
+            std::fs::create_dir(clm.work_dir().join("build")).unwrap();
+
+            let output = clm.run_with_answers(&["delete build"], &["Execute", "n"]);
+            assert!(output.status.success(), "{}", stderr(&output));
+            assert!(stdout(&output).contains("Warning: this command deletes files recursively."));
+            assert!(clm.work_dir().join("build").exists());
+
+            let output = clm.run_with_answers(&["delete build"], &["Execute", "y"]);
+            assert!(output.status.success(), "{}", stderr(&output));
+            assert!(!clm.work_dir().join("build").exists());
+        }
+
+        #[test]
+        fn test_preview_lists_affected_files() {
+            let server = FixtureServer::start(vec![Fixture::chat_completion(
+                "Deletes the log files§rm -f *.log",
+            )]);
+            let clm = clm_suggesting(&server);
+            std::fs::write(clm.work_dir().join("a.log"), "12345").unwrap();
+            std::fs::write(clm.work_dir().join("b.log"), "12345").unwrap();
+
+            let output = clm.run_with_answers(&["delete logs"], &["Cancel"]);
+            let stdout = stdout(&output);
+            assert!(stdout.contains("Deletes 2 files (10 B)"), "{stdout}");
+            assert!(stdout.contains("a.log") && stdout.contains("b.log"));
+        }
+
+        #[test]
+        fn test_dry_run_never_executes() {
+            let server = FixtureServer::start(vec![Fixture::chat_completion(
+                "Deletes the build directory§rm -rf build",
+            )]);
+            let clm = clm_suggesting(&server);
+            std::fs::create_dir(clm.work_dir().join("build")).unwrap();
+
+            let output = clm.run_with_answers(&["--dry-run", "delete build"], &[]);
+            assert!(output.status.success(), "{}", stderr(&output));
+            assert!(stdout(&output).contains("Deletes 1 directory"));
+            assert!(stdout(&output).contains("Dry run: command not executed."));
+            assert!(clm.work_dir().join("build").exists());
+            assert_eq!(clm.history()[0]["executed"], false);
+        }
+
+        #[cfg(target_os = "linux")]
+        #[test]
+        fn test_sandbox_shows_changes_before_running_for_real() {
+            let server = FixtureServer::start(vec![Fixture::chat_completion(
+                "Writes a greeting to a file§echo hello > greeting.txt",
+            )]);
+            let clm = clm_suggesting(&server);
+            let namespaces = std::process::Command::new("unshare")
+                .args(["--user", "--map-root-user", "--mount", "true"])
+                .status();
+            if !namespaces.is_ok_and(|status| status.success()) {
+                return;
+            }
+
+            let output = clm.run_with_answers(&["--sandbox", "write a greeting"], &["Execute", "n"]);
+            assert!(output.status.success(), "{}", stderr(&output));
+            assert!(stdout(&output).contains("+ greeting.txt"));
+            assert!(!clm.work_dir().join("greeting.txt").exists());
+
+            let output = clm.run_with_answers(&["--sandbox", "write a greeting"], &["Execute", "y"]);
+            assert!(output.status.success(), "{}", stderr(&output));
+            assert!(clm.work_dir().join("greeting.txt").exists());
+            assert_eq!(clm.history()[1]["executed"], true);
+        }
+
+        #[test]
+        fn test_undo_restores_deleted_file() {
+            let server = FixtureServer::start(vec![Fixture::chat_completion(
+                "Deletes the notes§rm notes.txt",
+            )]);
+            let clm = clm_suggesting(&server);
+            std::fs::write(clm.work_dir().join("notes.txt"), "keep me").unwrap();
+
+            let output = clm.run_with_answers(&["delete notes"], &["Execute"]);
+            assert!(output.status.success(), "{}", stderr(&output));
+            assert!(stdout(&output).contains("Run `clm undo` to revert this command."));
+            assert!(!clm.work_dir().join("notes.txt").exists());
+
+            let output = clm.run_with_answers(&["undo"], &["y"]);
+            assert!(output.status.success(), "{}", stderr(&output));
+            let notes = std::fs::read_to_string(clm.work_dir().join("notes.txt")).unwrap();
+            assert_eq!(notes, "keep me");
+
+            let output = clm.run(&["undo"]);
+            assert!(stdout(&output).contains("Nothing to undo."));
+        }
+
+        #[test]
+        fn test_placeholders_are_filled_before_confirming() {
+            let server = FixtureServer::start(vec![Fixture::chat_completion(
+                "Writes a greeting to a file§echo <greeting> > <file name>",
+            )]);
+            let clm = clm_suggesting(&server);
+
+            let output = clm.run_with_answers(
+                &["write a greeting"],
+                &["hello", "greeting.txt", "Execute"],
+            );
+            assert!(output.status.success(), "{}", stderr(&output));
+            assert!(stdout(&output).contains("echo hello > greeting.txt"));
+            let greeting = std::fs::read_to_string(clm.work_dir().join("greeting.txt")).unwrap();
+            assert_eq!(greeting, "hello\n");
+            assert_eq!(clm.history()[0]["command"], "echo hello > greeting.txt");
+            assert_eq!(clm.history()[0]["edited"], false);
+        }
+
+        #[test]
+        fn test_validation_reports_missing_program() {
+            let server = FixtureServer::start(vec![Fixture::chat_completion(
+                "Searches for TODOs§clm-missing-tool TODO src",
+            )]);
+            let clm = clm_suggesting(&server);
+
+            let output = clm.run_with_answers(&["find todos"], &["Cancel"]);
+            assert!(output.status.success(), "{}", stderr(&output));
+            assert!(stdout(&output).contains("clm-missing-tool is not installed"));
+        }
+
+        #[test]
+        fn test_repair_asks_for_corrected_command() {
+            let server = FixtureServer::start(vec![
+                Fixture::chat_completion("Searches for TODOs§clm-missing-tool TODO src"),
+                Fixture::chat_completion("Uses grep instead§grep -r TODO src"),
+            ]);
+            let mut config = openai_config(&server);
+            config["validation"] = json!({ "repair": true });
+            let clm = Clm::with_config(config);
+
+            let output = clm.run_with_answers(&["find todos"], &["Cancel"]);
+            assert!(output.status.success(), "{}", stderr(&output));
+            assert!(stdout(&output).contains("grep -r TODO src"));
+            let requests = server.requests();
+            assert_eq!(requests.len(), 2);
+            assert!(requests[1].body.to_string().contains("clm-missing-tool is not installed"));
+        }
+
+        #[test]
+        fn test_failing_command_sets_exit_code() {
+            let server =
+                FixtureServer::start(vec![Fixture::chat_completion("Fails on purpose§exit 42")]);
+            let clm = clm_suggesting(&server);
+
+            let output = clm.run_with_answers(&["fail"], &["Execute"]);
+            assert_eq!(output.status.code(), Some(42));
+            assert_eq!(clm.history()[0]["exit_code"], 42);
+        }
+
+        #[test]
+        fn test_correction_offers_fix_for_failed_command() {
+            let server = FixtureServer::start(vec![
+                Fixture::chat_completion("Fails on purpose§echo broken >&2; exit 3"),
+                Fixture::chat_completion("Succeeds instead§echo fixed"),
+            ]);
+            let mut config = openai_config(&server);
+            config["correction"] = json!({ "enabled": true, "max_attempts": 1 });
+            let clm = Clm::with_config(config);
+
+            let output = clm.run_with_answers(&["do it"], &["Execute", "Execute"]);
+            assert!(output.status.success(), "{}", stderr(&output));
+            assert!(stdout(&output).contains("Suggested fix 1/1"));
+            assert!(stdout(&output).contains("fixed"));
+            let requests = server.requests();
+            assert_eq!(requests.len(), 2);
+            let body = requests[1].body.to_string();
+            assert!(body.contains("exited with code 3"));
+            assert!(body.contains("broken"));
+            let history = clm.history();
+            assert_eq!(history.len(), 2);
+            assert_eq!(history[1]["exit_code"], 0);
+        }
+
+        #[test]
+        fn test_correction_fills_placeholders_in_fix() {
+            let server = FixtureServer::start(vec![
+                Fixture::chat_completion("Fails on purpose§exit 3"),
+                Fixture::chat_completion("Writes the message§echo <message> > message.txt"),
+            ]);
+            let mut config = openai_config(&server);
+            config["correction"] = json!({ "enabled": true, "max_attempts": 1 });
+            let clm = Clm::with_config(config);
+
+            let output = clm.run_with_answers(&["do it"], &["Execute", "fix bug; exit 4", "Execute"]);
+            assert!(output.status.success(), "{}", stderr(&output));
+            assert!(stdout(&output).contains("echo 'fix bug; exit 4' > message.txt"));
+            let message = std::fs::read_to_string(clm.work_dir().join("message.txt")).unwrap();
+            assert_eq!(message, "fix bug; exit 4\n");
+        }
+
+        #[test]
+        fn test_running_out_of_answers_is_an_error() {
+            let server = FixtureServer::start(vec![Fixture::chat_completion(
+                "Deletes the build directory§rm -rf build",
+            )]);
+            let clm = clm_suggesting(&server);
+
+            let output = clm.run_with_answers(&["delete build"], &["Execute"]);
+            assert_eq!(output.status.code(), Some(1));
+            assert!(stderr(&output).contains("no answer left"));
+        }
+    }
+}